@@ -0,0 +1,653 @@
+use aes::cipher::consts::U16;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, BlockSizeUser, KeyInit};
+use aes::{Aes128, Aes192, Aes256};
+use anyhow::{anyhow, bail, Context, Result};
+use hmac::{Hmac, Mac};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::collections::HashMap;
+
+use crate::cfb::{self, CompoundFile};
+use crate::local_name;
+
+/// Mã hoá Agile (ECMA-376 / MS-OFFCRYPTO 2.3.4.10): EncryptionInfo phiên bản 4.4
+const AGILE_VERSION: [u8; 8] = [0x04, 0x00, 0x04, 0x00, 0x40, 0x00, 0x00, 0x00];
+const ENCRYPTION_INFO: &str = "EncryptionInfo";
+const ENCRYPTED_PACKAGE: &str = "EncryptedPackage";
+const SEGMENT_SIZE: usize = 4096;
+
+// blockKey cố định của MS-OFFCRYPTO cho từng giá trị được mã hoá
+const BLOCK_VERIFIER_INPUT: &[u8] = &[0xFE, 0xA7, 0xD2, 0x76, 0x3B, 0x4B, 0x9E, 0x79];
+const BLOCK_VERIFIER_VALUE: &[u8] = &[0xD7, 0xAA, 0x0F, 0x6D, 0x30, 0x61, 0x34, 0x4E];
+const BLOCK_KEY_VALUE: &[u8] = &[0x14, 0x6E, 0x0B, 0xE7, 0xAB, 0xAC, 0xD0, 0xD6];
+const BLOCK_HMAC_KEY: &[u8] = &[0x5F, 0xB2, 0xAD, 0x01, 0x0C, 0xB9, 0xE1, 0xF6];
+const BLOCK_HMAC_VALUE: &[u8] = &[0xA0, 0x67, 0x7F, 0x02, 0xB2, 0x2C, 0x84, 0x33];
+
+#[derive(Debug, Clone, Copy)]
+enum HashAlg {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlg {
+    fn parse(name: &str) -> Result<HashAlg> {
+        match name.replace('-', "").to_ascii_uppercase().as_str() {
+            "SHA1" => Ok(HashAlg::Sha1),
+            "SHA256" => Ok(HashAlg::Sha256),
+            "SHA384" => Ok(HashAlg::Sha384),
+            "SHA512" => Ok(HashAlg::Sha512),
+            _ => bail!("thuật toán băm không hỗ trợ: {}", name),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            HashAlg::Sha1 => "SHA1",
+            HashAlg::Sha256 => "SHA256",
+            HashAlg::Sha384 => "SHA384",
+            HashAlg::Sha512 => "SHA512",
+        }
+    }
+
+    fn hash(self, parts: &[&[u8]]) -> Vec<u8> {
+        fn run<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+            let mut d = D::new();
+            for p in parts {
+                d.update(p);
+            }
+            d.finalize().to_vec()
+        }
+        match self {
+            HashAlg::Sha1 => run::<Sha1>(parts),
+            HashAlg::Sha256 => run::<Sha256>(parts),
+            HashAlg::Sha384 => run::<Sha384>(parts),
+            HashAlg::Sha512 => run::<Sha512>(parts),
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        fn run<M: Mac + KeyInit>(key: &[u8], data: &[u8]) -> Vec<u8> {
+            let mut m = <M as Mac>::new_from_slice(key).expect("HMAC nhận khoá mọi độ dài");
+            m.update(data);
+            m.finalize().into_bytes().to_vec()
+        }
+        match self {
+            HashAlg::Sha1 => run::<Hmac<Sha1>>(key, data),
+            HashAlg::Sha256 => run::<Hmac<Sha256>>(key, data),
+            HashAlg::Sha384 => run::<Hmac<Sha384>>(key, data),
+            HashAlg::Sha512 => run::<Hmac<Sha512>>(key, data),
+        }
+    }
+}
+
+/// Tham số chung của `keyData` và `p:encryptedKey`
+#[derive(Debug, Clone)]
+struct KeyParams {
+    salt: Vec<u8>,
+    block_size: usize,
+    key_bytes: usize,
+    hash_size: usize,
+    hash: HashAlg,
+}
+
+impl KeyParams {
+    fn parse(attrs: &HashMap<String, String>) -> Result<KeyParams> {
+        let get = |name: &str| {
+            attrs
+                .get(name)
+                .with_context(|| format!("EncryptionInfo thiếu {}", name))
+        };
+        let number = |name: &str| -> Result<usize> {
+            get(name)?
+                .parse()
+                .with_context(|| format!("EncryptionInfo: {} không hợp lệ", name))
+        };
+        if get("cipherAlgorithm")? != "AES" || get("cipherChaining")? != "ChainingModeCBC" {
+            bail!(
+                "chỉ hỗ trợ AES-CBC, file dùng {} {}",
+                get("cipherAlgorithm")?,
+                get("cipherChaining")?
+            );
+        }
+        let params = KeyParams {
+            salt: base64_decode(get("saltValue")?)?,
+            block_size: number("blockSize")?,
+            key_bytes: number("keyBits")? / 8,
+            hash_size: number("hashSize")?,
+            hash: HashAlg::parse(get("hashAlgorithm")?)?,
+        };
+        if params.block_size != 16 || ![16, 24, 32].contains(&params.key_bytes) {
+            bail!("kích thước khoá/khối AES không hợp lệ");
+        }
+        Ok(params)
+    }
+
+    /// Các thuộc tính XML, theo thứ tự Excel ghi
+    fn attributes(&self) -> String {
+        format!(
+            r#"saltSize="{}" blockSize="{}" keyBits="{}" hashSize="{}" cipherAlgorithm="AES" cipherChaining="ChainingModeCBC" hashAlgorithm="{}" saltValue="{}""#,
+            self.salt.len(),
+            self.block_size,
+            self.key_bytes * 8,
+            self.hash_size,
+            self.hash.name(),
+            base64_encode(&self.salt)
+        )
+    }
+
+    /// IV cho khối `block_key`: băm salt với blockKey rồi cắt/độn đủ một khối
+    fn iv(&self, block_key: &[u8]) -> Vec<u8> {
+        fit(self.hash.hash(&[&self.salt, block_key]), self.block_size)
+    }
+}
+
+/// Số vòng băm mật khẩu tối đa mà Excel chấp nhận
+const MAX_SPIN_COUNT: u32 = 10_000_000;
+
+#[derive(Debug, Clone)]
+struct EncryptionInfo {
+    key_data: KeyParams,
+    encrypted_hmac_key: Vec<u8>,
+    encrypted_hmac_value: Vec<u8>,
+    password: KeyParams,
+    spin_count: u32,
+    encrypted_verifier_input: Vec<u8>,
+    encrypted_verifier_value: Vec<u8>,
+    encrypted_key_value: Vec<u8>,
+}
+
+impl EncryptionInfo {
+    fn parse(stream: &[u8]) -> Result<EncryptionInfo> {
+        if stream.len() < 8 || stream[..4] != AGILE_VERSION[..4] {
+            let version = stream.get(..4).unwrap_or_default();
+            bail!(
+                "chỉ hỗ trợ mã hoá Agile (4.4), file dùng phiên bản {:?} (Standard/RC4 của Office cũ)",
+                version
+            );
+        }
+        let mut reader = Reader::from_reader(&stream[8..]);
+        let mut buf = Vec::new();
+        let (mut key_data, mut integrity, mut password) = (None, None, None);
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) | Event::Empty(ref e) => {
+                    let attrs = attributes(e);
+                    match local_name(e.name().as_ref()) {
+                        "keyData" => key_data = Some(attrs),
+                        "dataIntegrity" => integrity = Some(attrs),
+                        // khoá theo chứng chỉ (c:encryptedKey) không có spinCount
+                        "encryptedKey" if attrs.contains_key("spinCount") => password = Some(attrs),
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+        let key_data = key_data.context("EncryptionInfo thiếu keyData")?;
+        let integrity = integrity.context("EncryptionInfo thiếu dataIntegrity")?;
+        let password = password.context("file không được mã hoá bằng mật khẩu")?;
+        let spin_count: u32 = password["spinCount"]
+            .parse()
+            .context("EncryptionInfo: spinCount không hợp lệ")?;
+        // MS-OFFCRYPTO giới hạn 10 triệu vòng; lớn hơn thì một file có thể treo cả luồng
+        if spin_count > MAX_SPIN_COUNT {
+            bail!(
+                "EncryptionInfo: spinCount {} vượt giới hạn {}",
+                spin_count,
+                MAX_SPIN_COUNT
+            );
+        }
+        let value = |attrs: &HashMap<String, String>, name: &str| -> Result<Vec<u8>> {
+            base64_decode(
+                attrs
+                    .get(name)
+                    .with_context(|| format!("EncryptionInfo thiếu {}", name))?,
+            )
+        };
+        Ok(EncryptionInfo {
+            key_data: KeyParams::parse(&key_data)?,
+            encrypted_hmac_key: value(&integrity, "encryptedHmacKey")?,
+            encrypted_hmac_value: value(&integrity, "encryptedHmacValue")?,
+            spin_count,
+            encrypted_verifier_input: value(&password, "encryptedVerifierHashInput")?,
+            encrypted_verifier_value: value(&password, "encryptedVerifierHashValue")?,
+            encrypted_key_value: value(&password, "encryptedKeyValue")?,
+            password: KeyParams::parse(&password)?,
+        })
+    }
+
+    fn to_stream(&self) -> Vec<u8> {
+        let xml = format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\r\n",
+                r#"<encryption xmlns="http://schemas.microsoft.com/office/2006/encryption" "#,
+                r#"xmlns:p="http://schemas.microsoft.com/office/2006/keyEncryptor/password" "#,
+                r#"xmlns:c="http://schemas.microsoft.com/office/2006/keyEncryptor/certificate">"#,
+                r#"<keyData {}/>"#,
+                r#"<dataIntegrity encryptedHmacKey="{}" encryptedHmacValue="{}"/>"#,
+                r#"<keyEncryptors><keyEncryptor uri="http://schemas.microsoft.com/office/2006/keyEncryptor/password">"#,
+                r#"<p:encryptedKey spinCount="{}" {} encryptedVerifierHashInput="{}" encryptedVerifierHashValue="{}" encryptedKeyValue="{}"/>"#,
+                r#"</keyEncryptor></keyEncryptors></encryption>"#
+            ),
+            self.key_data.attributes(),
+            base64_encode(&self.encrypted_hmac_key),
+            base64_encode(&self.encrypted_hmac_value),
+            self.spin_count,
+            self.password.attributes(),
+            base64_encode(&self.encrypted_verifier_input),
+            base64_encode(&self.encrypted_verifier_value),
+            base64_encode(&self.encrypted_key_value),
+        );
+        [&AGILE_VERSION[..], xml.as_bytes()].concat()
+    }
+
+    /// Băm mật khẩu (UTF-16LE) với salt, lặp `spinCount` lần
+    fn password_hash(&self, password: &str) -> Vec<u8> {
+        let hash = self.password.hash;
+        let utf16: Vec<u8> = password
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        let mut h = hash.hash(&[&self.password.salt, &utf16]);
+        for i in 0..self.spin_count {
+            h = hash.hash(&[&i.to_le_bytes(), &h]);
+        }
+        h
+    }
+
+    /// Khoá AES suy từ băm mật khẩu cho từng blockKey
+    fn password_key(&self, pw_hash: &[u8], block_key: &[u8]) -> Vec<u8> {
+        fit(
+            self.password.hash.hash(&[pw_hash, block_key]),
+            self.password.key_bytes,
+        )
+    }
+
+    /// Kiểm tra mật khẩu qua verifier rồi giải mã khoá bí mật của package
+    fn secret_key(&self, password: &str) -> Result<Vec<u8>> {
+        let p = &self.password;
+        let pw_hash = self.password_hash(password);
+        let decrypt = |block_key: &[u8], data: &[u8]| {
+            aes_cbc(
+                &self.password_key(&pw_hash, block_key),
+                &p.salt,
+                data,
+                false,
+            )
+        };
+        let mut input = decrypt(BLOCK_VERIFIER_INPUT, &self.encrypted_verifier_input)?;
+        input.truncate(p.salt.len());
+        let mut expected = decrypt(BLOCK_VERIFIER_VALUE, &self.encrypted_verifier_value)?;
+        expected.truncate(p.hash_size);
+        if p.hash.hash(&[&input]) != expected {
+            bail!("sai mật khẩu");
+        }
+        let mut key = decrypt(BLOCK_KEY_VALUE, &self.encrypted_key_value)?;
+        key.truncate(p.key_bytes);
+        Ok(key)
+    }
+
+    /// HMAC của toàn bộ stream EncryptedPackage, dùng khoá HMAC đã giải mã
+    fn package_hmac(&self, secret: &[u8], package_stream: &[u8]) -> Result<Vec<u8>> {
+        let k = &self.key_data;
+        let mut hmac_key = aes_cbc(
+            secret,
+            &k.iv(BLOCK_HMAC_KEY),
+            &self.encrypted_hmac_key,
+            false,
+        )?;
+        hmac_key.truncate(k.hash_size);
+        Ok(k.hash.hmac(&hmac_key, package_stream))
+    }
+}
+
+/// Giải mã package OOXML (zip) trong compound file được bảo vệ bằng mật khẩu
+pub fn decrypt(cf: &CompoundFile, password: &str) -> Result<Vec<u8>> {
+    let info = EncryptionInfo::parse(&cf.read_stream(ENCRYPTION_INFO)?)?;
+    let secret = info.secret_key(password)?;
+    let stream = cf.read_stream(ENCRYPTED_PACKAGE)?;
+    if stream.len() < 8 {
+        bail!("EncryptedPackage quá ngắn");
+    }
+
+    let k = &info.key_data;
+    let mut expected = aes_cbc(
+        &secret,
+        &k.iv(BLOCK_HMAC_VALUE),
+        &info.encrypted_hmac_value,
+        false,
+    )?;
+    expected.truncate(k.hash_size);
+    if info.package_hmac(&secret, &stream)? != expected {
+        bail!("EncryptedPackage hỏng (HMAC không khớp)");
+    }
+
+    let size = u64::from_le_bytes(stream[..8].try_into().unwrap()) as usize;
+    let mut package = Vec::with_capacity(stream.len());
+    for (i, segment) in stream[8..].chunks(SEGMENT_SIZE).enumerate() {
+        let iv = k.iv(&(i as u32).to_le_bytes());
+        package.extend(aes_cbc(&secret, &iv, segment, false)?);
+    }
+    if package.len() < size {
+        bail!("EncryptedPackage bị cắt cụt");
+    }
+    package.truncate(size);
+    Ok(package)
+}
+
+/// Mã hoá `package` bằng mật khẩu với cùng thuật toán, số vòng lặp như file gốc `cf` nhưng
+/// salt và khoá mới. Các stream khác của file gốc (\x06DataSpaces...) được chép lại.
+pub fn encrypt(cf: &CompoundFile, package: &[u8], password: &str) -> Result<Vec<u8>> {
+    let mut info = EncryptionInfo::parse(&cf.read_stream(ENCRYPTION_INFO)?)?;
+    let k = &mut info.key_data;
+    k.salt = random_bytes(k.salt.len())?;
+    let secret = random_bytes(k.key_bytes)?;
+    let hmac_key = random_bytes(k.hash_size)?;
+
+    let k = &info.key_data;
+    let mut stream = (package.len() as u64).to_le_bytes().to_vec();
+    for (i, segment) in package.chunks(SEGMENT_SIZE).enumerate() {
+        let iv = k.iv(&(i as u32).to_le_bytes());
+        stream.extend(aes_cbc(&secret, &iv, &pad(segment, k.block_size), true)?);
+    }
+    let hmac_value = k.hash.hmac(&hmac_key, &stream);
+    info.encrypted_hmac_key = aes_cbc(
+        &secret,
+        &k.iv(BLOCK_HMAC_KEY),
+        &pad(&hmac_key, k.block_size),
+        true,
+    )?;
+    info.encrypted_hmac_value = aes_cbc(
+        &secret,
+        &k.iv(BLOCK_HMAC_VALUE),
+        &pad(&hmac_value, k.block_size),
+        true,
+    )?;
+
+    info.password.salt = random_bytes(info.password.salt.len())?;
+    let verifier = random_bytes(info.password.salt.len())?;
+    let pw_hash = info.password_hash(password);
+    let p = &info.password;
+    let encrypt = |block_key: &[u8], data: &[u8]| {
+        aes_cbc(
+            &info.password_key(&pw_hash, block_key),
+            &p.salt,
+            &pad(data, p.block_size),
+            true,
+        )
+    };
+    let verifier_input = encrypt(BLOCK_VERIFIER_INPUT, &verifier)?;
+    let verifier_value = encrypt(BLOCK_VERIFIER_VALUE, &p.hash.hash(&[&verifier]))?;
+    let key_value = encrypt(BLOCK_KEY_VALUE, &secret)?;
+    info.encrypted_verifier_input = verifier_input;
+    info.encrypted_verifier_value = verifier_value;
+    info.encrypted_key_value = key_value;
+
+    let mut streams: Vec<(String, Vec<u8>)> = cf
+        .streams()?
+        .into_iter()
+        .filter(|(path, _)| {
+            !path.eq_ignore_ascii_case(ENCRYPTION_INFO)
+                && !path.eq_ignore_ascii_case(ENCRYPTED_PACKAGE)
+        })
+        .collect();
+    streams.push((ENCRYPTION_INFO.to_string(), info.to_stream()));
+    streams.push((ENCRYPTED_PACKAGE.to_string(), stream));
+    Ok(cfb::build(&streams))
+}
+
+fn attributes(e: &BytesStart) -> HashMap<String, String> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .map(|a| {
+            (
+                local_name(a.key.as_ref()).to_string(),
+                String::from_utf8_lossy(&a.value).to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Cắt hoặc độn (0x36) cho đủ `len` byte, như MS-OFFCRYPTO quy định cho khoá và IV
+fn fit(mut bytes: Vec<u8>, len: usize) -> Vec<u8> {
+    bytes.resize(len, 0x36);
+    bytes
+}
+
+/// Độn 0 cho đủ bội số của khối
+fn pad(data: &[u8], block: usize) -> Vec<u8> {
+    let mut out = data.to_vec();
+    out.resize(data.len().div_ceil(block).max(1) * block, 0);
+    out
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    getrandom::fill(&mut buf).map_err(|e| anyhow!("không lấy được số ngẫu nhiên: {}", e))?;
+    Ok(buf)
+}
+
+/// AES-CBC không độn; `data` phải là bội số của 16 byte
+fn aes_cbc(key: &[u8], iv: &[u8], data: &[u8], encrypt: bool) -> Result<Vec<u8>> {
+    fn run<C: BlockEncrypt + BlockDecrypt + KeyInit + BlockSizeUser<BlockSize = U16>>(
+        key: &[u8],
+        iv: &[u8],
+        data: &[u8],
+        encrypt: bool,
+    ) -> Result<Vec<u8>> {
+        let cipher = C::new_from_slice(key).map_err(|_| anyhow!("khoá AES sai độ dài"))?;
+        let mut prev: [u8; 16] = iv.try_into().context("IV phải dài 16 byte")?;
+        let mut out = Vec::with_capacity(data.len());
+        for chunk in data.chunks_exact(16) {
+            let mut block: [u8; 16] = chunk.try_into().unwrap();
+            if encrypt {
+                block.iter_mut().zip(prev).for_each(|(b, p)| *b ^= p);
+                cipher.encrypt_block((&mut block).into());
+                prev = block;
+            } else {
+                cipher.decrypt_block((&mut block).into());
+                block.iter_mut().zip(prev).for_each(|(b, p)| *b ^= p);
+                prev.copy_from_slice(chunk);
+            }
+            out.extend_from_slice(&block);
+        }
+        Ok(out)
+    }
+    if !data.len().is_multiple_of(16) {
+        bail!("dữ liệu mã hoá không chia hết cho khối AES");
+    }
+    match key.len() {
+        16 => run::<Aes128>(key, iv, data, encrypt),
+        24 => run::<Aes192>(key, iv, data, encrypt),
+        32 => run::<Aes256>(key, iv, data, encrypt),
+        n => bail!("khoá AES {} byte không hợp lệ", n),
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for c in text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let v = BASE64
+            .iter()
+            .position(|&b| b == c)
+            .with_context(|| format!("base64 không hợp lệ: {}", text))?;
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// File mã hoá mẫu: chỉ cần EncryptionInfo để `encrypt` lấy thuật toán và số vòng lặp
+    fn template(hash: HashAlg, key_bytes: usize, spin_count: u32) -> CompoundFile {
+        let params = |salt: u8| KeyParams {
+            salt: vec![salt; 16],
+            block_size: 16,
+            key_bytes,
+            hash_size: hash.hash(&[]).len(),
+            hash,
+        };
+        let info = EncryptionInfo {
+            key_data: params(1),
+            encrypted_hmac_key: vec![0; 16],
+            encrypted_hmac_value: vec![0; 16],
+            password: params(2),
+            spin_count,
+            encrypted_verifier_input: vec![0; 16],
+            encrypted_verifier_value: vec![0; 32],
+            encrypted_key_value: vec![0; 32],
+        };
+        let data = cfb::build(&[
+            (ENCRYPTION_INFO.to_string(), info.to_stream()),
+            (ENCRYPTED_PACKAGE.to_string(), vec![0; 8]),
+            ("\u{6}DataSpaces/Version".to_string(), b"version".to_vec()),
+        ]);
+        CompoundFile::parse(data).unwrap()
+    }
+
+    fn package(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn encrypt_then_decrypt() {
+        for (hash, key_bytes) in [(HashAlg::Sha512, 32), (HashAlg::Sha1, 16)] {
+            // vài segment 4096 byte, segment cuối không đủ khối
+            let plain = package(3 * SEGMENT_SIZE + 1234);
+            let encrypted = encrypt(&template(hash, key_bytes, 50), &plain, "mật khẩu").unwrap();
+            let cf = CompoundFile::parse(encrypted).unwrap();
+            assert_eq!(decrypt(&cf, "mật khẩu").unwrap(), plain);
+            assert_eq!(
+                cf.read_stream("\u{6}DataSpaces/Version").unwrap(),
+                b"version"
+            );
+            let info = EncryptionInfo::parse(&cf.read_stream(ENCRYPTION_INFO).unwrap()).unwrap();
+            assert_eq!(info.spin_count, 50);
+            assert_ne!(info.key_data.salt, vec![1; 16], "salt phải được tạo mới");
+        }
+    }
+
+    #[test]
+    fn spin_count_is_bounded() {
+        let cf = template(HashAlg::Sha512, 32, MAX_SPIN_COUNT + 1);
+        let stream = cf.read_stream(ENCRYPTION_INFO).unwrap();
+        let err = EncryptionInfo::parse(&stream).unwrap_err();
+        assert!(err.to_string().contains("spinCount"), "{}", err);
+        let cf = template(HashAlg::Sha512, 32, MAX_SPIN_COUNT);
+        let stream = cf.read_stream(ENCRYPTION_INFO).unwrap();
+        assert_eq!(
+            EncryptionInfo::parse(&stream).unwrap().spin_count,
+            MAX_SPIN_COUNT
+        );
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let encrypted = encrypt(&template(HashAlg::Sha512, 32, 10), &package(100), "a").unwrap();
+        let cf = CompoundFile::parse(encrypted).unwrap();
+        let err = decrypt(&cf, "b").err().unwrap();
+        assert_eq!(err.to_string(), "sai mật khẩu");
+    }
+
+    #[test]
+    fn data_integrity_hmac_is_verified() {
+        let cf = CompoundFile::parse(
+            encrypt(&template(HashAlg::Sha256, 32, 10), &package(5000), "pw").unwrap(),
+        )
+        .unwrap();
+        let mut streams = cf.streams().unwrap();
+        let (_, stream) = streams
+            .iter_mut()
+            .find(|(p, _)| p == ENCRYPTED_PACKAGE)
+            .unwrap();
+        stream[100] ^= 1;
+        let tampered = CompoundFile::parse(cfb::build(&streams)).unwrap();
+        let err = decrypt(&tampered, "pw").err().unwrap();
+        assert!(err.to_string().contains("HMAC"), "{}", err);
+    }
+
+    #[test]
+    fn segment_iv_and_key_derivation() {
+        // IV của segment i = hash(salt || i LE) cắt còn 16 byte; blockKey ngắn hơn thì độn 0x36
+        let k = KeyParams {
+            salt: vec![0xAB; 16],
+            block_size: 16,
+            key_bytes: 32,
+            hash_size: 64,
+            hash: HashAlg::Sha512,
+        };
+        let expected = Sha512::digest([&[0xAB; 16][..], &3u32.to_le_bytes()].concat());
+        assert_eq!(k.iv(&3u32.to_le_bytes()), expected[..16]);
+        assert_eq!(fit(vec![1, 2], 4), [1, 2, 0x36, 0x36]);
+    }
+
+    #[test]
+    fn aes_cbc_known_vector() {
+        // NIST SP 800-38A F.2.1, CBC-AES128, khối đầu tiên
+        let hex = |s: &str| -> Vec<u8> {
+            (0..s.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+                .collect()
+        };
+        let key = hex("2b7e151628aed2a6abf7158809cf4f3c");
+        let iv = hex("000102030405060708090a0b0c0d0e0f");
+        let plain = hex("6bc1bee22e409f96e93d7e117393172a");
+        let cipher = hex("7649abac8119b246cee98e9b12e9197d");
+        assert_eq!(aes_cbc(&key, &iv, &plain, true).unwrap(), cipher);
+        assert_eq!(aes_cbc(&key, &iv, &cipher, false).unwrap(), plain);
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        for (plain, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64_encode(plain), text);
+            assert_eq!(base64_decode(text).unwrap(), plain);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cellref::CellRef;
+use crate::workbook::SheetEntry;
+use crate::{attr_value, local_name, replace_attr, UsedRange};
+
+/// Các phần tử đứng sau `<calcPr>` trong CT_Workbook
+const AFTER_CALC_PR: &[&str] = &[
+    "oleSize",
+    "customWorkbookViews",
+    "pivotCaches",
+    "smartTagPr",
+    "smartTagTypes",
+    "webPublishing",
+    "fileRecoveryPr",
+    "webPublishObjects",
+    "extLst",
+];
+
+/// Bỏ các `<c>` của calcChain trỏ tới ô nằm ngoài vùng còn lại của sheet.
+/// Thuộc tính `i` (sheetId) và `l` (bắt đầu mức mới) có tính "dính": mục bị bỏ mang chúng thì
+/// chuyển sang mục giữ lại kế tiếp. Trả về số mục còn lại.
+pub fn prune_calc_chain(
+    path: &Path,
+    sheets: &[SheetEntry],
+    trimmed: &HashMap<PathBuf, UsedRange>,
+) -> Result<usize> {
+    let used_by_id: HashMap<u32, UsedRange> = sheets
+        .iter()
+        .filter_map(|s| trimmed.get(&s.path).map(|u| (s.sheet_id, *u)))
+        .collect();
+    let keeps = |sheet_id: u32, e: &BytesStart| {
+        let Some(used) = used_by_id.get(&sheet_id) else {
+            // sheet không được cắt: giữ nguyên
+            return true;
+        };
+        match attr_value(e, b"r").and_then(|r| CellRef::parse(&r)) {
+            Some(c) => !used.is_empty() && c.col <= used.last_col && c.row <= used.last_row,
+            None => true,
+        }
+    };
+
+    let tmp_out = path.with_extension("xml.out");
+    let mut kept = 0usize;
+    {
+        let mut reader = Reader::from_file(path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        let mut sheet_id = 0u32;
+        // sheetId của mục giữ lại gần nhất (0 = chưa ghi mục nào)
+        let mut written_id = 0u32;
+        let mut pending_level = false;
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Empty(ref e) if local_name(e.name().as_ref()) == "c" => {
+                    if let Some(i) = attr_value(e, b"i").and_then(|v| v.parse().ok()) {
+                        sheet_id = i;
+                    }
+                    let new_level = attr_value(e, b"l").is_some_and(|v| v == "1" || v == "true");
+                    if !keeps(sheet_id, e) {
+                        pending_level |= new_level;
+                    } else {
+                        let mut el = e.to_owned();
+                        if sheet_id != written_id {
+                            el = replace_attr(&el, b"i", Some(&sheet_id.to_string()));
+                        }
+                        if pending_level && !new_level {
+                            el = replace_attr(&el, b"l", Some("1"));
+                        }
+                        writer.write_event(Event::Empty(el))?;
+                        written_id = sheet_id;
+                        pending_level = false;
+                        kept += 1;
+                    }
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, path)?;
+    Ok(kept)
+}
+
+/// Đặt `fullCalcOnLoad="1"` cho `<calcPr>` của workbook; chưa có thì thêm đúng vị trí
+pub fn set_full_calc_on_load(workbook_xml: &Path) -> Result<()> {
+    let tmp_out = workbook_xml.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(workbook_xml)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        let mut done = false;
+        let mut depth = 0usize;
+        loop {
+            let ev = reader.read_event_into(&mut buf)?;
+            match ev {
+                Event::Start(ref e) | Event::Empty(ref e)
+                    if depth == 1 && local_name(e.name().as_ref()) == "calcPr" =>
+                {
+                    let el = replace_attr(e, b"fullCalcOnLoad", Some("1"));
+                    if matches!(ev, Event::Start(_)) {
+                        depth += 1;
+                        writer.write_event(Event::Start(el))?;
+                    } else {
+                        writer.write_event(Event::Empty(el))?;
+                    }
+                    done = true;
+                }
+                Event::Start(ref e) | Event::Empty(ref e)
+                    if !done
+                        && depth == 1
+                        && AFTER_CALC_PR.contains(&local_name(e.name().as_ref())) =>
+                {
+                    write_calc_pr(&mut writer)?;
+                    done = true;
+                    if matches!(ev, Event::Start(_)) {
+                        depth += 1;
+                    }
+                    writer.write_event(ev)?;
+                }
+                Event::End(_) if !done && depth == 1 => {
+                    // </workbook>
+                    write_calc_pr(&mut writer)?;
+                    done = true;
+                    depth -= 1;
+                    writer.write_event(ev)?;
+                }
+                Event::Start(_) => {
+                    depth += 1;
+                    writer.write_event(ev)?;
+                }
+                Event::End(_) => {
+                    depth -= 1;
+                    writer.write_event(ev)?;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, workbook_xml)?;
+    Ok(())
+}
+
+fn write_calc_pr<W: Write>(writer: &mut Writer<W>) -> Result<()> {
+    let mut el = BytesStart::new("calcPr");
+    el.push_attribute(("fullCalcOnLoad", "1"));
+    writer.write_event(Event::Empty(el))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workbook::SheetKind;
+
+    fn used(last_col: u32, last_row: u32) -> UsedRange {
+        UsedRange {
+            first_row: 1,
+            first_col: 1,
+            last_row,
+            last_col,
+        }
+    }
+
+    #[test]
+    fn pruned_entries_pass_sheet_and_level_on() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("calcChain.xml");
+        fs::write(
+            &path,
+            concat!(
+                r#"<calcChain><c r="A1" i="1"/><c r="Z50"/><c r="B2"/>"#,
+                r#"<c r="Q9" i="2" l="1"/><c r="A1"/><c r="C3" i="3"/></calcChain>"#
+            ),
+        )
+        .unwrap();
+        let sheets: Vec<SheetEntry> = (1..=2)
+            .map(|i| SheetEntry {
+                name: format!("S{}", i),
+                kind: SheetKind::Worksheet,
+                sheet_id: i,
+                path: dir.path().join(format!("sheet{}.xml", i)),
+            })
+            .collect();
+        let trimmed = HashMap::from([
+            (sheets[0].path.clone(), used(3, 10)),
+            (sheets[1].path.clone(), used(2, 2)),
+        ]);
+
+        assert_eq!(prune_calc_chain(&path, &sheets, &trimmed).unwrap(), 4);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            concat!(
+                r#"<calcChain><c r="A1" i="1"/><c r="B2"/>"#,
+                r#"<c r="A1" i="2" l="1"/><c r="C3" i="3"/></calcChain>"#
+            )
+        );
+    }
+
+    #[test]
+    fn full_calc_on_load_goes_in_schema_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workbook.xml");
+        for (input, expected) in [
+            (
+                r#"<workbook><calcPr calcId="191029"/></workbook>"#,
+                r#"<workbook><calcPr calcId="191029" fullCalcOnLoad="1"/></workbook>"#,
+            ),
+            (
+                r#"<workbook><sheets/><extLst><ext/></extLst></workbook>"#,
+                r#"<workbook><sheets/><calcPr fullCalcOnLoad="1"/><extLst><ext/></extLst></workbook>"#,
+            ),
+            (
+                r#"<workbook><sheets/></workbook>"#,
+                r#"<workbook><sheets/><calcPr fullCalcOnLoad="1"/></workbook>"#,
+            ),
+        ] {
+            fs::write(&path, input).unwrap();
+            set_full_calc_on_load(&path).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::fmt;
+
+/// Cột cuối cùng của Excel (XFD)
+pub const MAX_COL: u32 = 16_384;
+/// Hàng cuối cùng của Excel
+pub const MAX_ROW: u32 = 1_048_576;
+
+/// "A" -> 1, "xfd" -> 16384; None nếu rỗng, có ký tự lạ hoặc vượt quá XFD
+pub fn col_from_letters(s: &str) -> Option<u32> {
+    // XFD là 3 chữ cái: chặn chuỗi dài trước khi nhân để không tràn u32
+    if s.is_empty() || s.len() > 3 {
+        return None;
+    }
+    let mut n: u32 = 0;
+    for ch in s.bytes() {
+        if !ch.is_ascii_alphabetic() {
+            return None;
+        }
+        n = n * 26 + (ch.to_ascii_uppercase() - b'A' + 1) as u32;
+    }
+    (n <= MAX_COL).then_some(n)
+}
+
+pub fn col_to_letters(mut idx: u32) -> String {
+    let mut s = Vec::new();
+    while idx > 0 {
+        let r = (idx - 1) % 26;
+        s.push((b'A' + (r as u8)) as char);
+        idx = (idx - 1) / 26;
+    }
+    s.into_iter().rev().collect()
+}
+
+fn parse_row(s: &str) -> Option<u32> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let row: u32 = s.parse().ok()?;
+    (1..=MAX_ROW).contains(&row).then_some(row)
+}
+
+/// Ô đơn: "B12", "$B$12", "b12"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRef {
+    pub col: u32,
+    pub row: u32,
+    pub col_abs: bool,
+    pub row_abs: bool,
+}
+
+impl CellRef {
+    pub fn new(col: u32, row: u32) -> Self {
+        CellRef {
+            col,
+            row,
+            col_abs: false,
+            row_abs: false,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<CellRef> {
+        let (col_abs, s) = strip_dollar(s);
+        let pos = s.find(|c: char| !c.is_ascii_alphabetic())?;
+        let (letters, rest) = s.split_at(pos);
+        let (row_abs, digits) = strip_dollar(rest);
+        Some(CellRef {
+            col: col_from_letters(letters)?,
+            row: parse_row(digits)?,
+            col_abs,
+            row_abs,
+        })
+    }
+}
+
+impl fmt::Display for CellRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}",
+            if self.col_abs { "$" } else { "" },
+            col_to_letters(self.col),
+            if self.row_abs { "$" } else { "" },
+            self.row
+        )
+    }
+}
+
+fn strip_dollar(s: &str) -> (bool, &str) {
+    match s.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeKind {
+    /// "A1" hoặc "A1:C10"
+    Cells,
+    /// cả hàng: "1:3", "$5:$5"
+    Rows,
+    /// cả cột: "A:C", "$B:$B"
+    Cols,
+}
+
+/// Vùng chữ nhật; `first` luôn là góc trên trái, `last` là góc dưới phải.
+/// Vùng cả hàng/cột được trải ra hết chiều còn lại (cột 1..XFD, hàng 1..1048576).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub first: CellRef,
+    pub last: CellRef,
+    pub kind: RangeKind,
+}
+
+impl Range {
+    pub fn new(c1: u32, r1: u32, c2: u32, r2: u32) -> Self {
+        Range {
+            first: CellRef::new(c1, r1),
+            last: CellRef::new(c2, r2),
+            kind: RangeKind::Cells,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Range> {
+        let (a, b) = s.split_once(':').unwrap_or((s, s));
+        if let (Some(first), Some(last)) = (CellRef::parse(a), CellRef::parse(b)) {
+            return Some(
+                Range {
+                    first,
+                    last,
+                    kind: RangeKind::Cells,
+                }
+                .normalized(),
+            );
+        }
+        // chỉ các dạng cả hàng/cột mới cần dấu ':' ("A:A", "1:1")
+        if !s.contains(':') {
+            return None;
+        }
+        let ((abs1, a), (abs2, b)) = (strip_dollar(a), strip_dollar(b));
+        if let (Some(c1), Some(c2)) = (col_from_letters(a), col_from_letters(b)) {
+            return Some(
+                Range {
+                    first: CellRef {
+                        col: c1,
+                        row: 1,
+                        col_abs: abs1,
+                        row_abs: false,
+                    },
+                    last: CellRef {
+                        col: c2,
+                        row: MAX_ROW,
+                        col_abs: abs2,
+                        row_abs: false,
+                    },
+                    kind: RangeKind::Cols,
+                }
+                .normalized(),
+            );
+        }
+        let (r1, r2) = (parse_row(a)?, parse_row(b)?);
+        Some(
+            Range {
+                first: CellRef {
+                    col: 1,
+                    row: r1,
+                    col_abs: false,
+                    row_abs: abs1,
+                },
+                last: CellRef {
+                    col: MAX_COL,
+                    row: r2,
+                    col_abs: false,
+                    row_abs: abs2,
+                },
+                kind: RangeKind::Rows,
+            }
+            .normalized(),
+        )
+    }
+
+    /// Đảo lại nếu ghi ngược ("C3:A1" -> "A1:C3")
+    fn normalized(mut self) -> Self {
+        if self.first.col > self.last.col {
+            std::mem::swap(&mut self.first.col, &mut self.last.col);
+            std::mem::swap(&mut self.first.col_abs, &mut self.last.col_abs);
+        }
+        if self.first.row > self.last.row {
+            std::mem::swap(&mut self.first.row, &mut self.last.row);
+            std::mem::swap(&mut self.first.row_abs, &mut self.last.row_abs);
+        }
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.last.col + 1 - self.first.col
+    }
+
+    /// Cắt vùng về góc dưới phải (last_col, last_row); None nếu vùng nằm hẳn ngoài.
+    /// Vùng cả hàng chỉ bị cắt theo hàng, vùng cả cột chỉ bị cắt theo cột.
+    pub fn clip(&self, last_col: u32, last_row: u32) -> Option<Range> {
+        if self.first.col > last_col || self.first.row > last_row {
+            return None;
+        }
+        let mut out = *self;
+        if self.kind != RangeKind::Rows {
+            out.last.col = out.last.col.min(last_col);
+        }
+        if self.kind != RangeKind::Cols {
+            out.last.row = out.last.row.min(last_row);
+        }
+        Some(out)
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = |abs: bool| if abs { "$" } else { "" };
+        match self.kind {
+            RangeKind::Cells if self.first == self.last => write!(f, "{}", self.first),
+            RangeKind::Cells => write!(f, "{}:{}", self.first, self.last),
+            RangeKind::Rows => write!(
+                f,
+                "{}{}:{}{}",
+                d(self.first.row_abs),
+                self.first.row,
+                d(self.last.row_abs),
+                self.last.row
+            ),
+            RangeKind::Cols => write!(
+                f,
+                "{}{}:{}{}",
+                d(self.first.col_abs),
+                col_to_letters(self.first.col),
+                d(self.last.col_abs),
+                col_to_letters(self.last.col)
+            ),
+        }
+    }
+}
+
+/// Danh sách vùng cách nhau bởi khoảng trắng (thuộc tính `sqref`): "A1 B2:C5"
+pub fn parse_sqref(s: &str) -> Option<Vec<Range>> {
+    s.split_whitespace().map(Range::parse).collect()
+}
+
+pub fn format_sqref(ranges: &[Range]) -> String {
+    ranges
+        .iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sqref(s: &str) -> Vec<String> {
+        parse_sqref(s)
+            .unwrap()
+            .iter()
+            .map(|r| r.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn col_letters_bounds() {
+        assert_eq!(col_from_letters("A"), Some(1));
+        assert_eq!(col_from_letters("xfd"), Some(MAX_COL));
+        assert_eq!(col_from_letters("XFE"), None);
+        assert_eq!(col_from_letters("AAAA"), None);
+        assert_eq!(col_from_letters("A1"), None);
+        assert_eq!(col_to_letters(MAX_COL), "XFD");
+        assert_eq!(col_to_letters(27), "AA");
+    }
+
+    #[test]
+    fn parse_sqref_forms() {
+        assert_eq!(sqref("A1 B2:C5"), ["A1", "B2:C5"]);
+        // ghi ngược được đảo lại, dấu $ đi theo góc tương ứng
+        assert_eq!(sqref("$C$3:A1"), ["A1:$C$3"]);
+        assert_eq!(sqref("  $2:$5   B:D "), ["$2:$5", "B:D"]);
+        assert_eq!(parse_sqref("").unwrap(), Vec::<Range>::new());
+
+        let rows = &parse_sqref("3:4").unwrap()[0];
+        assert_eq!(rows.kind, RangeKind::Rows);
+        assert_eq!((rows.first.col, rows.last.col), (1, MAX_COL));
+        let cols = &parse_sqref("B:B").unwrap()[0];
+        assert_eq!(cols.kind, RangeKind::Cols);
+        assert_eq!((cols.first.row, cols.last.row), (1, MAX_ROW));
+    }
+
+    #[test]
+    fn parse_sqref_rejects_bad_ranges() {
+        // một vùng hỏng thì cả danh sách không dùng được
+        assert!(parse_sqref("A1 B0").is_none());
+        assert!(parse_sqref("A1:XFE1").is_none());
+        assert!(parse_sqref("A1048577").is_none());
+        assert!(parse_sqref("A").is_none());
+        assert!(parse_sqref("1").is_none());
+        assert!(parse_sqref("#REF!").is_none());
+    }
+
+    #[test]
+    fn clip_cells() {
+        let rg = Range::parse("B2:F20").unwrap();
+        assert_eq!(rg.clip(4, 10).unwrap().to_string(), "B2:D10");
+        assert_eq!(rg.clip(100, 100), Some(rg));
+        // góc trên trái nằm ngoài: bỏ cả vùng
+        assert_eq!(rg.clip(1, 100), None);
+        assert_eq!(rg.clip(100, 1), None);
+        assert_eq!(rg.clip(2, 2).unwrap().to_string(), "B2");
+    }
+
+    #[test]
+    fn clip_whole_rows_and_cols() {
+        // cả hàng chỉ bị cắt theo hàng, cả cột chỉ bị cắt theo cột
+        let rows = Range::parse("$1:$50").unwrap();
+        assert_eq!(rows.clip(3, 10).unwrap().to_string(), "$1:$10");
+        let cols = Range::parse("A:Z").unwrap();
+        assert_eq!(cols.clip(3, 10).unwrap().to_string(), "A:C");
+        assert_eq!(Range::parse("E:F").unwrap().clip(3, 10), None);
+        assert_eq!(Range::parse("11:12").unwrap().clip(3, 10), None);
+    }
+}
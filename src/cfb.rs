@@ -0,0 +1,648 @@
+use anyhow::{bail, Context, Result};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Chữ ký đầu file OLE Compound File (MS-CFB)
+pub const SIGNATURE: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+const END_OF_CHAIN: u32 = 0xFFFF_FFFE;
+const FREE_SECT: u32 = 0xFFFF_FFFF;
+const FAT_SECT: u32 = 0xFFFF_FFFD;
+const DIF_SECT: u32 = 0xFFFF_FFFC;
+const NO_STREAM: u32 = 0xFFFF_FFFF;
+const DIR_ENTRY_SIZE: usize = 128;
+const HEADER_DIFAT_ENTRIES: usize = 109;
+/// Stream nhỏ hơn ngưỡng này nằm trong mini stream (sector 64 byte)
+const MINI_CUTOFF: usize = 4096;
+
+const KIND_STORAGE: u8 = 1;
+const KIND_STREAM: u8 = 2;
+const KIND_ROOT: u8 = 5;
+
+/// Một mục trong thư mục của compound file
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    /// 1 = storage, 2 = stream, 5 = root
+    pub kind: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    size: u64,
+}
+
+/// Compound file đã nạp vào bộ nhớ
+pub struct CompoundFile {
+    data: Vec<u8>,
+    sector_size: usize,
+    mini_sector_size: usize,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    entries: Vec<DirEntry>,
+}
+
+impl CompoundFile {
+    pub fn open(path: &Path) -> Result<CompoundFile> {
+        CompoundFile::parse(fs::read(path)?)
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<CompoundFile> {
+        if data.len() < 512 || data[..8] != SIGNATURE {
+            bail!("không phải OLE Compound File");
+        }
+        let sector_shift = u16_at(&data, 0x1E) as u32;
+        let mini_shift = u16_at(&data, 0x20) as u32;
+        if !(7..=16).contains(&sector_shift) || mini_shift >= sector_shift {
+            bail!("header OLE không hợp lệ");
+        }
+        let mut cf = CompoundFile {
+            sector_size: 1 << sector_shift,
+            mini_sector_size: 1 << mini_shift,
+            data,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        // DIFAT: 109 mục trong header, phần còn lại nằm trong chuỗi sector DIFAT
+        let mut fat_sectors: Vec<u32> = (0..HEADER_DIFAT_ENTRIES)
+            .map(|i| u32_at(&cf.data, 0x4C + i * 4))
+            .collect();
+        let per_sector = cf.sector_size / 4;
+        let difat_count = u32_at(&cf.data, 0x48) as usize;
+        let mut difat = u32_at(&cf.data, 0x44);
+        let mut seen = HashSet::new();
+        while difat != END_OF_CHAIN && difat != FREE_SECT {
+            if !seen.insert(difat) {
+                bail!("chuỗi sector DIFAT bị lặp");
+            }
+            if seen.len() > difat_count {
+                bail!(
+                    "chuỗi sector DIFAT dài hơn {} sector khai báo trong header",
+                    difat_count
+                );
+            }
+            let sector = cf.sector(difat)?;
+            fat_sectors.extend((0..per_sector - 1).map(|i| u32_at(sector, i * 4)));
+            difat = u32_at(sector, (per_sector - 1) * 4);
+        }
+        let fat_count = u32_at(&cf.data, 0x2C) as usize;
+        let mut fat = Vec::with_capacity(fat_count * per_sector);
+        for &s in fat_sectors
+            .iter()
+            .filter(|&&s| s != FREE_SECT)
+            .take(fat_count)
+        {
+            let sector = cf.sector(s)?;
+            fat.extend((0..per_sector).map(|i| u32_at(sector, i * 4)));
+        }
+        cf.fat = fat;
+
+        // bản 3 có thể để rác ở 32 bit cao của kích thước stream
+        let size_mask = match u16_at(&cf.data, 0x1A) {
+            3 => u32::MAX as u64,
+            _ => u64::MAX,
+        };
+        let dir = cf.read_chain(u32_at(&cf.data, 0x30))?;
+        cf.entries = dir
+            .chunks_exact(DIR_ENTRY_SIZE)
+            .map(|e| {
+                let name_len = (u16_at(e, 64) as usize).min(64).saturating_sub(2);
+                let units: Vec<u16> = e[..name_len]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                DirEntry {
+                    name: String::from_utf16_lossy(&units),
+                    kind: e[66],
+                    left: u32_at(e, 68),
+                    right: u32_at(e, 72),
+                    child: u32_at(e, 76),
+                    start: u32_at(e, 116),
+                    size: u64::from_le_bytes(e[120..128].try_into().unwrap()) & size_mask,
+                }
+            })
+            .collect();
+
+        // mini stream là stream của Root Entry, mini FAT nằm trong chuỗi sector riêng
+        if let Some(root) = cf.entries.first().filter(|e| e.kind == KIND_ROOT) {
+            let (start, size) = (root.start, root.size as usize);
+            let mut mini_stream = cf.read_chain(start)?;
+            mini_stream.truncate(size);
+            let mini_fat = cf.read_chain(u32_at(&cf.data, 0x3C))?;
+            cf.mini_stream = mini_stream;
+            cf.mini_fat = mini_fat.chunks_exact(4).map(|c| u32_at(c, 0)).collect();
+        }
+
+        Ok(cf)
+    }
+
+    /// Có stream/storage tên `name` (không phân biệt hoa thường, như MS-CFB)
+    pub fn has_entry(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.kind != 0 && e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Nội dung stream theo đường dẫn từ gốc, các storage cách nhau bằng `/`
+    pub fn read_stream(&self, path: &str) -> Result<Vec<u8>> {
+        let mut dir = 0u32;
+        for name in path.split('/') {
+            let parent = self.entry(dir)?;
+            dir = self
+                .siblings(parent.child)
+                .into_iter()
+                .find(|&i| self.entries[i as usize].name.eq_ignore_ascii_case(name))
+                .with_context(|| format!("không có stream {}", path))?;
+        }
+        let e = self.entry(dir)?;
+        if e.kind != KIND_STREAM {
+            bail!("{} không phải stream", path);
+        }
+        self.stream_data(e)
+    }
+
+    /// Mọi stream (đường dẫn, nội dung) trong file, duyệt cả storage con
+    pub fn streams(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut out = Vec::new();
+        let mut stack = vec![(self.entry(0)?.child, String::new())];
+        let mut seen = HashSet::new();
+        while let Some((child, prefix)) = stack.pop() {
+            for i in self.siblings(child) {
+                if !seen.insert(i) {
+                    bail!("thư mục OLE bị lặp");
+                }
+                let e = &self.entries[i as usize];
+                let path = format!("{}{}", prefix, e.name);
+                match e.kind {
+                    KIND_STORAGE => stack.push((e.child, format!("{}/", path))),
+                    KIND_STREAM => out.push((path, self.stream_data(e)?)),
+                    _ => {}
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn entry(&self, id: u32) -> Result<&DirEntry> {
+        match self.entries.get(id as usize) {
+            Some(e) => Ok(e),
+            None => bail!("mục thư mục {} không tồn tại", id),
+        }
+    }
+
+    /// Các mục cùng cấp: cây đỏ-đen bắt đầu tại `root`, duyệt qua left/right
+    fn siblings(&self, root: u32) -> Vec<u32> {
+        let mut out = Vec::new();
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            let Some(e) = self.entries.get(id as usize) else {
+                continue;
+            };
+            if out.contains(&id) || out.len() > self.entries.len() {
+                continue;
+            }
+            out.push(id);
+            stack.extend([e.left, e.right]);
+        }
+        out
+    }
+
+    fn stream_data(&self, e: &DirEntry) -> Result<Vec<u8>> {
+        let size = e.size as usize;
+        let mut data = if size < MINI_CUTOFF {
+            self.read_mini_chain(e.start)?
+        } else {
+            self.read_chain(e.start)?
+        };
+        if data.len() < size {
+            bail!("stream {} bị cắt cụt", e.name);
+        }
+        data.truncate(size);
+        Ok(data)
+    }
+
+    fn sector(&self, id: u32) -> Result<&[u8]> {
+        let start = (id as usize + 1) * self.sector_size;
+        match self.data.get(start..start + self.sector_size) {
+            Some(s) => Ok(s),
+            None => bail!("sector {} nằm ngoài file", id),
+        }
+    }
+
+    fn read_chain(&self, start: u32) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut s = start;
+        let mut guard = 0usize;
+        while s != END_OF_CHAIN && s != FREE_SECT {
+            out.extend_from_slice(self.sector(s)?);
+            s = self.fat.get(s as usize).copied().unwrap_or(END_OF_CHAIN);
+            guard += 1;
+            if guard > self.fat.len() {
+                bail!("chuỗi sector bị lặp");
+            }
+        }
+        Ok(out)
+    }
+
+    fn read_mini_chain(&self, start: u32) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut s = start;
+        let mut guard = 0usize;
+        while s != END_OF_CHAIN && s != FREE_SECT {
+            let begin = s as usize * self.mini_sector_size;
+            match self.mini_stream.get(begin..begin + self.mini_sector_size) {
+                Some(chunk) => out.extend_from_slice(chunk),
+                None => bail!("mini sector {} nằm ngoài mini stream", s),
+            }
+            s = self
+                .mini_fat
+                .get(s as usize)
+                .copied()
+                .unwrap_or(END_OF_CHAIN);
+            guard += 1;
+            if guard > self.mini_fat.len() {
+                bail!("chuỗi mini sector bị lặp");
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Một mục của file sắp ghi: cây storage dựng từ đường dẫn của các stream
+struct Node {
+    name: String,
+    kind: u8,
+    data: Option<usize>,
+    children: Vec<usize>,
+    left: u32,
+    right: u32,
+    child: u32,
+    red: bool,
+    start: u32,
+    size: u64,
+}
+
+impl Node {
+    fn new(name: &str, kind: u8) -> Node {
+        Node {
+            name: name.to_string(),
+            kind,
+            data: None,
+            children: Vec::new(),
+            left: NO_STREAM,
+            right: NO_STREAM,
+            child: NO_STREAM,
+            red: false,
+            start: END_OF_CHAIN,
+            size: 0,
+        }
+    }
+}
+
+/// Thứ tự tên trong thư mục MS-CFB: tên ngắn hơn đứng trước, cùng độ dài thì so chữ hoa
+fn compare_names(a: &str, b: &str) -> Ordering {
+    let len = |s: &str| s.encode_utf16().count();
+    len(a)
+        .cmp(&len(b))
+        .then_with(|| a.to_uppercase().cmp(&b.to_uppercase()))
+}
+
+/// Dựng cây cân bằng từ các mục đã sắp xếp; các nút dưới mức đầy đủ cuối cùng (`red_depth`)
+/// tô đỏ để mọi đường đi có cùng số nút đen. Gốc (mức 0) luôn đen. Trả về gốc của cây.
+fn link_siblings(nodes: &mut [Node], ids: &[usize], depth: u32, red_depth: u32) -> u32 {
+    if ids.is_empty() {
+        return NO_STREAM;
+    }
+    let mid = ids.len() / 2;
+    let left = link_siblings(nodes, &ids[..mid], depth + 1, red_depth);
+    let right = link_siblings(nodes, &ids[mid + 1..], depth + 1, red_depth);
+    let node = &mut nodes[ids[mid]];
+    node.left = left;
+    node.right = right;
+    node.red = depth > 0 && depth >= red_depth;
+    ids[mid] as u32
+}
+
+/// Ghi compound file (bản 3, sector 512 byte) chứa các stream theo đường dẫn `a/b/c`
+pub fn build(streams: &[(String, Vec<u8>)]) -> Vec<u8> {
+    const SECTOR: usize = 512;
+    const MINI_SECTOR: usize = 64;
+    const PER_SECTOR: usize = SECTOR / 4;
+
+    let mut nodes = vec![Node::new("Root Entry", KIND_ROOT)];
+    for (idx, (path, _)) in streams.iter().enumerate() {
+        let mut parent = 0usize;
+        let parts: Vec<&str> = path.split('/').collect();
+        for (depth, part) in parts.iter().enumerate() {
+            let leaf = depth + 1 == parts.len();
+            let existing = nodes[parent]
+                .children
+                .iter()
+                .copied()
+                .find(|&c| nodes[c].name.eq_ignore_ascii_case(part));
+            parent = match existing {
+                Some(c) => c,
+                None => {
+                    let kind = if leaf { KIND_STREAM } else { KIND_STORAGE };
+                    nodes.push(Node::new(part, kind));
+                    let id = nodes.len() - 1;
+                    nodes[parent].children.push(id);
+                    id
+                }
+            };
+            if leaf {
+                nodes[parent].data = Some(idx);
+            }
+        }
+    }
+    for i in 0..nodes.len() {
+        let mut children = std::mem::take(&mut nodes[i].children);
+        children.sort_by(|&a, &b| compare_names(&nodes[a].name, &nodes[b].name));
+        // n mục lấp đầy các mức 0..floor(log2(n+1)), phần dư nằm ở mức tiếp theo
+        let red_depth = (children.len() as u32 + 1).ilog2();
+        nodes[i].child = link_siblings(&mut nodes, &children, 0, red_depth);
+        nodes[i].children = children;
+    }
+
+    // mini stream và mini FAT cho stream nhỏ
+    let mut mini_stream = Vec::new();
+    let mut mini_fat: Vec<u32> = Vec::new();
+    for node in nodes.iter_mut() {
+        let Some(idx) = node.data else { continue };
+        let data = &streams[idx].1;
+        node.size = data.len() as u64;
+        if data.is_empty() || data.len() >= MINI_CUTOFF {
+            continue;
+        }
+        let first = mini_fat.len() as u32;
+        let count = data.len().div_ceil(MINI_SECTOR);
+        mini_fat.extend((1..=count as u32).map(|k| first + k));
+        *mini_fat.last_mut().unwrap() = END_OF_CHAIN;
+        mini_stream.extend_from_slice(data);
+        mini_stream.resize(mini_fat.len() * MINI_SECTOR, 0);
+        node.start = first;
+    }
+
+    // sector thường: stream lớn, mini stream, mini FAT, thư mục, rồi FAT và DIFAT
+    let mut body: Vec<u8> = Vec::new();
+    let mut chains: Vec<(u32, usize)> = Vec::new();
+    let mut append = |body: &mut Vec<u8>, bytes: &[u8]| -> u32 {
+        let start = (body.len() / SECTOR) as u32;
+        body.extend_from_slice(bytes);
+        body.resize(body.len().div_ceil(SECTOR) * SECTOR, 0);
+        chains.push((start, bytes.len().div_ceil(SECTOR)));
+        start
+    };
+    for node in nodes.iter_mut() {
+        let Some(idx) = node.data else { continue };
+        let data = &streams[idx].1;
+        if data.len() >= MINI_CUTOFF {
+            node.start = append(&mut body, data);
+        }
+    }
+    if !mini_stream.is_empty() {
+        nodes[0].start = append(&mut body, &mini_stream);
+        nodes[0].size = mini_stream.len() as u64;
+    }
+    let mini_fat_bytes: Vec<u8> = mini_fat.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let mini_fat_start = match mini_fat_bytes.is_empty() {
+        true => END_OF_CHAIN,
+        false => append(&mut body, &mini_fat_bytes),
+    };
+    let mut dir = Vec::with_capacity(nodes.len().div_ceil(4) * 4 * DIR_ENTRY_SIZE);
+    for node in &nodes {
+        dir.extend_from_slice(&dir_entry(node));
+    }
+    while dir.len() % SECTOR != 0 {
+        dir.extend_from_slice(&dir_entry(&Node::new("", 0)));
+    }
+    let dir_start = append(&mut body, &dir);
+
+    let data_sectors = body.len() / SECTOR;
+    let (mut fat_count, mut difat_count) = (0usize, 0usize);
+    loop {
+        let total = data_sectors + fat_count + difat_count;
+        let fat_need = total.div_ceil(PER_SECTOR);
+        let difat_need = fat_need
+            .saturating_sub(HEADER_DIFAT_ENTRIES)
+            .div_ceil(PER_SECTOR - 1);
+        if (fat_need, difat_need) == (fat_count, difat_count) {
+            break;
+        }
+        (fat_count, difat_count) = (fat_need, difat_need);
+    }
+    let mut fat = vec![FREE_SECT; fat_count * PER_SECTOR];
+    for &(start, count) in &chains {
+        for k in 0..count {
+            let s = start as usize + k;
+            fat[s] = if k + 1 == count {
+                END_OF_CHAIN
+            } else {
+                s as u32 + 1
+            };
+        }
+    }
+    let fat_ids: Vec<u32> = (0..fat_count).map(|k| (data_sectors + k) as u32).collect();
+    let difat_ids: Vec<u32> = (0..difat_count)
+        .map(|k| (data_sectors + fat_count + k) as u32)
+        .collect();
+    for &s in &fat_ids {
+        fat[s as usize] = FAT_SECT;
+    }
+    for &s in &difat_ids {
+        fat[s as usize] = DIF_SECT;
+    }
+    body.extend(fat.iter().flat_map(|v| v.to_le_bytes()));
+    let overflow: Vec<u32> = fat_ids.iter().skip(HEADER_DIFAT_ENTRIES).copied().collect();
+    for (k, chunk) in overflow.chunks(PER_SECTOR - 1).enumerate() {
+        let mut sector = vec![FREE_SECT; PER_SECTOR];
+        sector[..chunk.len()].copy_from_slice(chunk);
+        sector[PER_SECTOR - 1] = difat_ids.get(k + 1).copied().unwrap_or(END_OF_CHAIN);
+        body.extend(sector.iter().flat_map(|v| v.to_le_bytes()));
+    }
+
+    let mut header = vec![0u8; SECTOR];
+    header[..8].copy_from_slice(&SIGNATURE);
+    let put16 = |h: &mut [u8], at: usize, v: u16| h[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    let put32 = |h: &mut [u8], at: usize, v: u32| h[at..at + 4].copy_from_slice(&v.to_le_bytes());
+    put16(&mut header, 0x18, 0x003E);
+    put16(&mut header, 0x1A, 3);
+    put16(&mut header, 0x1C, 0xFFFE);
+    put16(&mut header, 0x1E, 9);
+    put16(&mut header, 0x20, 6);
+    put32(&mut header, 0x2C, fat_count as u32);
+    put32(&mut header, 0x30, dir_start);
+    put32(&mut header, 0x38, MINI_CUTOFF as u32);
+    put32(&mut header, 0x3C, mini_fat_start);
+    put32(
+        &mut header,
+        0x40,
+        mini_fat_bytes.len().div_ceil(SECTOR) as u32,
+    );
+    put32(
+        &mut header,
+        0x44,
+        difat_ids.first().copied().unwrap_or(END_OF_CHAIN),
+    );
+    put32(&mut header, 0x48, difat_count as u32);
+    for k in 0..HEADER_DIFAT_ENTRIES {
+        put32(
+            &mut header,
+            0x4C + k * 4,
+            fat_ids.get(k).copied().unwrap_or(FREE_SECT),
+        );
+    }
+    header.extend_from_slice(&body);
+    header
+}
+
+fn dir_entry(node: &Node) -> [u8; DIR_ENTRY_SIZE] {
+    let mut e = [0u8; DIR_ENTRY_SIZE];
+    let units: Vec<u16> = node.name.encode_utf16().take(31).collect();
+    for (k, u) in units.iter().enumerate() {
+        e[k * 2..k * 2 + 2].copy_from_slice(&u.to_le_bytes());
+    }
+    let name_len = if node.kind == 0 {
+        0
+    } else {
+        (units.len() + 1) * 2
+    };
+    e[64..66].copy_from_slice(&(name_len as u16).to_le_bytes());
+    e[66] = node.kind;
+    e[67] = if node.red { 0 } else { 1 };
+    e[68..72].copy_from_slice(&node.left.to_le_bytes());
+    e[72..76].copy_from_slice(&node.right.to_le_bytes());
+    e[76..80].copy_from_slice(&node.child.to_le_bytes());
+    let start = match node.kind {
+        KIND_STORAGE | 0 => 0,
+        _ => node.start,
+    };
+    e[116..120].copy_from_slice(&start.to_le_bytes());
+    e[120..128].copy_from_slice(&node.size.to_le_bytes());
+    e
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    fn round_trip(streams: &[(String, Vec<u8>)]) -> CompoundFile {
+        let cf = CompoundFile::parse(build(streams)).unwrap();
+        let mut read = cf.streams().unwrap();
+        read.sort();
+        let mut expected = streams.to_vec();
+        expected.sort();
+        assert_eq!(read, expected);
+        for (path, data) in streams {
+            assert_eq!(&cf.read_stream(path).unwrap(), data);
+        }
+        cf
+    }
+
+    #[test]
+    fn round_trip_mini_and_regular_streams() {
+        round_trip(&[
+            ("EncryptionInfo".to_string(), bytes(900, 1)),
+            ("EncryptedPackage".to_string(), bytes(MINI_CUTOFF + 17, 2)),
+            ("\u{6}DataSpaces/Version".to_string(), bytes(76, 3)),
+            (
+                "\u{6}DataSpaces/DataSpaceMap".to_string(),
+                bytes(MINI_CUTOFF - 1, 4),
+            ),
+            (
+                "\u{6}DataSpaces/TransformInfo/Primary".to_string(),
+                bytes(200, 5),
+            ),
+            ("Empty".to_string(), Vec::new()),
+        ]);
+    }
+
+    #[test]
+    fn round_trip_with_difat_sectors() {
+        // mỗi sector FAT quản lý 128 sector (64 KiB): 8 MiB cần hơn 109 sector FAT
+        let big = bytes(8 << 20, 7);
+        let cf = round_trip(&[
+            ("EncryptedPackage".to_string(), big),
+            ("EncryptionInfo".to_string(), bytes(300, 8)),
+        ]);
+        assert!(u32_at(&cf.data, 0x2C) as usize > HEADER_DIFAT_ENTRIES);
+        assert!(u32_at(&cf.data, 0x48) > 0);
+    }
+
+    /// Màu của mục `id` đọc thẳng từ thư mục (các sector thư mục do `build` ghi liền nhau)
+    fn is_red(cf: &CompoundFile, id: u32) -> bool {
+        let dir = (u32_at(&cf.data, 0x30) as usize + 1) * 512;
+        cf.data[dir + id as usize * DIR_ENTRY_SIZE + 67] == 0
+    }
+
+    /// Số nút đen trên mọi đường đi từ `id` xuống lá, kiểm tra thứ tự và không có hai nút đỏ
+    /// liền nhau
+    fn black_height(cf: &CompoundFile, id: u32, parent_red: bool) -> usize {
+        if id == NO_STREAM {
+            return 1;
+        }
+        let e = &cf.entries[id as usize];
+        let red = is_red(cf, id);
+        assert!(!(red && parent_red), "hai nút đỏ liền nhau");
+        for (side, ord) in [(e.left, Ordering::Less), (e.right, Ordering::Greater)] {
+            if side != NO_STREAM {
+                assert_eq!(compare_names(&cf.entries[side as usize].name, &e.name), ord);
+            }
+        }
+        let left = black_height(cf, e.left, red);
+        assert_eq!(left, black_height(cf, e.right, red));
+        left + usize::from(!red)
+    }
+
+    #[test]
+    fn sibling_trees_are_red_black() {
+        for n in 1..=40 {
+            let streams: Vec<(String, Vec<u8>)> =
+                (0..n).map(|i| (format!("s{}", i), vec![i as u8])).collect();
+            let cf = CompoundFile::parse(build(&streams)).unwrap();
+            let root = cf.entries[0].child;
+            assert!(!is_red(&cf, root), "gốc phải đen (n={})", n);
+            black_height(&cf, root, false);
+            assert_eq!(cf.streams().unwrap().len(), n);
+        }
+    }
+
+    #[test]
+    fn difat_cycle_is_rejected() {
+        let mut data = build(&[("a".to_string(), bytes(MINI_CUTOFF, 9))]);
+        // sector 0 làm sector DIFAT trỏ về chính nó
+        data[0x44..0x48].copy_from_slice(&0u32.to_le_bytes());
+        data[0x48..0x4C].copy_from_slice(&u32::MAX.to_le_bytes());
+        data[512 + 508..1024].copy_from_slice(&0u32.to_le_bytes());
+        let err = CompoundFile::parse(data).err().unwrap();
+        assert!(err.to_string().contains("bị lặp"), "{}", err);
+    }
+
+    #[test]
+    fn difat_chain_longer_than_header_count_is_rejected() {
+        let mut data = build(&[("a".to_string(), bytes(MINI_CUTOFF, 9))]);
+        data[0x44..0x48].copy_from_slice(&0u32.to_le_bytes());
+        data[0x48..0x4C].copy_from_slice(&0u32.to_le_bytes());
+        assert!(CompoundFile::parse(data).is_err());
+    }
+}
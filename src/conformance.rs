@@ -0,0 +1,146 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::path::Path;
+
+const TRANSITIONAL_REL_BASE: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/";
+const STRICT_REL_BASE: &str = "http://purl.oclc.org/ooxml/officeDocument/relationships/";
+const TRANSITIONAL_MAIN_NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const STRICT_MAIN_NS: &str = "http://purl.oclc.org/ooxml/spreadsheetml/main";
+/// Mọi namespace của Strict (ISO/IEC 29500 Strict) bắt đầu bằng tiền tố này
+const STRICT_PREFIX: &str = "http://purl.oclc.org/ooxml/";
+
+/// Biến thể Open XML của workbook: Transitional (Excel mặc định) hay Strict. Part và quan hệ
+/// thêm mới phải dùng namespace/Type cùng biến thể với file gốc.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Conformance {
+    #[default]
+    Transitional,
+    Strict,
+}
+
+impl Conformance {
+    /// Strict nếu `<workbook>` có `conformance="strict"` hoặc namespace của nó thuộc
+    /// `http://purl.oclc.org/ooxml/`
+    pub fn detect(workbook_xml: &Path) -> Result<Conformance> {
+        if !workbook_xml.exists() {
+            return Ok(Conformance::Transitional);
+        }
+        let mut reader = Reader::from_file(workbook_xml)?;
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) | Event::Empty(ref e) => {
+                    let name = e.name();
+                    let ns_key = match name.as_ref().iter().position(|&b| b == b':') {
+                        Some(pos) => [b"xmlns:", &name.as_ref()[..pos]].concat(),
+                        None => b"xmlns".to_vec(),
+                    };
+                    let strict = e.attributes().with_checks(false).flatten().any(|a| {
+                        let key = a.key.as_ref();
+                        (key == b"conformance" && a.value.as_ref() == b"strict")
+                            || (key == ns_key.as_slice()
+                                && a.value.starts_with(STRICT_PREFIX.as_bytes()))
+                    });
+                    return Ok(match strict {
+                        true => Conformance::Strict,
+                        false => Conformance::Transitional,
+                    });
+                }
+                Event::Eof => return Ok(Conformance::Transitional),
+                _ => {}
+            }
+            buf.clear();
+        }
+    }
+
+    /// Type đầy đủ của quan hệ, vd "sharedStrings" ->
+    /// "http://purl.oclc.org/ooxml/officeDocument/relationships/sharedStrings". `kind` là tên
+    /// Transitional; Strict viết liền các thuộc tính tài liệu ("extendedProperties"...)
+    pub fn rel_type(self, kind: &str) -> String {
+        match self {
+            Conformance::Transitional => format!("{}{}", TRANSITIONAL_REL_BASE, kind),
+            Conformance::Strict => format!("{}{}", STRICT_REL_BASE, strict_rel_kind(kind)),
+        }
+    }
+
+    /// Namespace của SpreadsheetML (`<sst>`, `<worksheet>`...)
+    pub fn main_ns(self) -> &'static str {
+        match self {
+            Conformance::Transitional => TRANSITIONAL_MAIN_NS,
+            Conformance::Strict => STRICT_MAIN_NS,
+        }
+    }
+}
+
+/// Tên quan hệ Strict ứng với tên Transitional, chỉ khác ở các thuộc tính tài liệu
+fn strict_rel_kind(kind: &str) -> &str {
+    match kind {
+        "extended-properties" => "extendedProperties",
+        "custom-properties" => "customProperties",
+        _ => kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn detect(xml: &str) -> Conformance {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workbook.xml");
+        fs::write(&path, xml).unwrap();
+        Conformance::detect(&path).unwrap()
+    }
+
+    #[test]
+    fn strict_is_detected_by_attribute_or_namespace() {
+        assert_eq!(
+            detect(
+                r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheets/></workbook>"#
+            ),
+            Conformance::Transitional
+        );
+        assert_eq!(
+            detect(
+                r#"<workbook xmlns="http://purl.oclc.org/ooxml/spreadsheetml/main"><sheets/></workbook>"#
+            ),
+            Conformance::Strict
+        );
+        assert_eq!(
+            detect(r#"<x:workbook xmlns:x="http://purl.oclc.org/ooxml/spreadsheetml/main"/>"#),
+            Conformance::Strict
+        );
+        assert_eq!(
+            detect(
+                r#"<workbook conformance="strict" xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"/>"#
+            ),
+            Conformance::Strict
+        );
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            Conformance::detect(&dir.path().join("missing.xml")).unwrap(),
+            Conformance::Transitional
+        );
+    }
+
+    #[test]
+    fn new_parts_use_matching_namespaces() {
+        assert_eq!(
+            Conformance::Strict.rel_type("sharedStrings"),
+            "http://purl.oclc.org/ooxml/officeDocument/relationships/sharedStrings"
+        );
+        assert_eq!(
+            Conformance::Transitional.rel_type("sharedStrings"),
+            "http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings"
+        );
+        assert_eq!(
+            Conformance::Strict.rel_type("extended-properties"),
+            "http://purl.oclc.org/ooxml/officeDocument/relationships/extendedProperties"
+        );
+        assert_eq!(Conformance::Strict.main_ns(), STRICT_MAIN_NS);
+        assert_eq!(Conformance::Transitional.main_ns(), TRANSITIONAL_MAIN_NS);
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{attr_value, local_name};
+
+pub const SHARED_STRINGS: &str =
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml";
+
+/// Tên part trong [Content_Types].xml: "/xl/tables/table1.xml"
+pub fn part_name(root: &Path, part: &Path) -> String {
+    let rel = part.strip_prefix(root).unwrap_or(part);
+    format!("/{}", rel.to_string_lossy().replace('\\', "/"))
+}
+
+/// Bỏ các `<Override>` của những part đã bị xoá khỏi package
+pub fn remove_overrides(root: &Path, parts: &[PathBuf]) -> Result<()> {
+    let path = root.join("[Content_Types].xml");
+    if parts.is_empty() || !path.exists() {
+        return Ok(());
+    }
+    let names: HashSet<String> = parts
+        .iter()
+        .map(|p| part_name(root, p).to_ascii_lowercase())
+        .collect();
+    let is_removed = |e: &quick_xml::events::BytesStart| {
+        local_name(e.name().as_ref()) == "Override"
+            && attr_value(e, b"PartName").is_some_and(|n| names.contains(&n.to_ascii_lowercase()))
+    };
+
+    let tmp_out = path.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(&path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Empty(ref e) if is_removed(e) => {}
+                Event::Start(ref e) if is_removed(e) => {
+                    reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, &path)?;
+    Ok(())
+}
+
+/// Khai báo `<Override>` cho part mới (bỏ qua nếu đã có)
+pub fn add_override(root: &Path, part: &Path, content_type: &str) -> Result<()> {
+    let path = root.join("[Content_Types].xml");
+    let name = part_name(root, part);
+    let mut exists = false;
+    {
+        let mut reader = Reader::from_file(&path)?;
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) | Event::Empty(ref e)
+                    if local_name(e.name().as_ref()) == "Override"
+                        && attr_value(e, b"PartName")
+                            .is_some_and(|n| n.eq_ignore_ascii_case(&name)) =>
+                {
+                    exists = true;
+                    break;
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+    }
+    if exists {
+        return Ok(());
+    }
+
+    let mut el = BytesStart::new("Override");
+    el.push_attribute(("PartName", name.as_str()));
+    el.push_attribute(("ContentType", content_type));
+    let tmp_out = path.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(&path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::End(e) if local_name(e.name().as_ref()) == "Types" => {
+                    writer.write_event(Event::Empty(el.borrow()))?;
+                    writer.write_event(Event::End(e))?;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, &path)?;
+    Ok(())
+}
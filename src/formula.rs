@@ -0,0 +1,297 @@
+use crate::cellref::{Range, RangeKind, MAX_COL, MAX_ROW};
+
+/// Một tham chiếu ô/vùng trong công thức
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormulaRef {
+    /// tên sheet ("Sheet1!A1"); None = sheet chứa công thức
+    pub sheet: Option<String>,
+    pub range: Range,
+}
+
+impl FormulaRef {
+    /// Tham chiếu sau khi công thức được chép lệch (dc, dr) ô (công thức shared):
+    /// chỉ phần tương đối (không có `$`) bị dời
+    pub fn shifted(&self, dc: u32, dr: u32) -> FormulaRef {
+        let mut rg = self.range;
+        for cell in [&mut rg.first, &mut rg.last] {
+            if rg.kind != RangeKind::Rows && !cell.col_abs {
+                cell.col = (cell.col + dc).min(MAX_COL);
+            }
+            if rg.kind != RangeKind::Cols && !cell.row_abs {
+                cell.row = (cell.row + dr).min(MAX_ROW);
+            }
+        }
+        FormulaRef {
+            sheet: self.sheet.clone(),
+            range: rg,
+        }
+    }
+}
+
+/// Vùng chạm tới hàng/cột cuối của Excel ("A1:A1048576") coi như cả cột/cả hàng
+pub fn bounded(rg: &Range) -> Range {
+    let mut out = *rg;
+    if rg.kind == RangeKind::Cells {
+        if rg.last.row == MAX_ROW {
+            out.kind = RangeKind::Cols;
+        } else if rg.last.col == MAX_COL {
+            out.kind = RangeKind::Rows;
+        }
+    }
+    out
+}
+
+/// Kết quả phân tích text một công thức
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub refs: Vec<FormulaRef>,
+    /// các từ không phải tham chiếu ô hay tên hàm: tên định nghĩa (có thể là TRUE/FALSE...)
+    pub names: Vec<String>,
+    /// có tham chiếu tới workbook ngoài ("[1]Sheet1!A1", "'[Book.xlsx]Sheet1'!A1")
+    pub external: bool,
+}
+
+/// Tách các tham chiếu ô/vùng trong text công thức (không có dấu '=' đầu).
+pub fn references(formula: &str) -> Vec<FormulaRef> {
+    parse(formula).refs
+}
+
+/// Bỏ qua chuỗi, mã lỗi, tên hàm, structured reference (Table1[Cột]) và tham chiếu
+/// tới workbook ngoài ([1]Sheet1!A1). Tham chiếu 3D "Sheet1:Sheet3!A1" tính cho hai sheet đầu/cuối.
+pub fn parse(formula: &str) -> Parsed {
+    let chars: Vec<char> = formula.chars().collect();
+    let mut out = Parsed::default();
+    let mut i = 0;
+    // token kế tiếp đứng ngay sau "[n]" -> thuộc workbook ngoài
+    let mut external = false;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == '"' {
+            i = skip_quoted(&chars, i).1;
+            external = false;
+        } else if ch == '#' {
+            // #REF!, #N/A, #DIV/0!...
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '/' | '!' | '?'))
+            {
+                i += 1;
+            }
+            external = false;
+        } else if ch == '[' {
+            let end = skip_brackets(&chars, i);
+            // "[1]" là chỉ số workbook ngoài; "[@Cột]" là structured reference trong bảng
+            external = end > i + 2 && chars[i + 1..end - 1].iter().all(|c| c.is_ascii_digit());
+            out.external |= external;
+            i = end;
+        } else if ch == '\'' || is_word_char(ch) {
+            let (sheet, body, next) = read_reference(&chars, i);
+            i = next;
+            let sheet_external = sheet.as_deref().is_some_and(|s| s.starts_with('[') || s.contains(']'));
+            out.external |= sheet_external;
+            if !external && !sheet_external {
+                match body.as_deref().map(|b| (b, Range::parse(b))) {
+                    Some((_, Some(range))) => match sheet {
+                        // 3D: "Sheet1:Sheet3"
+                        Some(s) if s.contains(':') => {
+                            for name in s.split(':') {
+                                out.refs.push(FormulaRef {
+                                    sheet: Some(name.to_string()),
+                                    range,
+                                });
+                            }
+                        }
+                        sheet => out.refs.push(FormulaRef { sheet, range }),
+                    },
+                    Some((b, None)) if b.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '\\') => {
+                        out.names.push(b.to_string());
+                    }
+                    _ => {}
+                }
+            }
+            external = false;
+        } else {
+            i += 1;
+            external = false;
+        }
+    }
+    out
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || matches!(ch, '_' | '.' | '$' | '\\')
+}
+
+/// Đọc một token bắt đầu ở `start`: -> (phần sheet trước '!', phần tham chiếu, vị trí tiếp theo).
+/// Phần tham chiếu là None nếu token là tên hàm hoặc structured reference.
+fn read_reference(chars: &[char], start: usize) -> (Option<String>, Option<String>, usize) {
+    let (mut sheet, mut i) = if chars[start] == '\'' {
+        let (name, next) = skip_quoted(chars, start);
+        if chars.get(next) != Some(&'!') {
+            return (None, None, next);
+        }
+        (Some(name), next + 1)
+    } else {
+        (None, start)
+    };
+
+    let (mut body, next) = read_words(chars, i);
+    i = next;
+    if sheet.is_none() && chars.get(i) == Some(&'!') {
+        sheet = Some(body);
+        let (b, next) = read_words(chars, i + 1);
+        body = b;
+        i = next;
+    }
+    match chars.get(i) {
+        // hàm: SUM(, LOG10(
+        Some('(') => (sheet, None, i),
+        // Table1[Cột], Table1[[#This Row],[Cột]]
+        Some('[') => (sheet, None, skip_brackets(chars, i)),
+        _ => (sheet, Some(body), i),
+    }
+}
+
+/// Các từ nối nhau bởi ':' ("A1:B2", "A:C", "Sheet1:Sheet3")
+fn read_words(chars: &[char], start: usize) -> (String, usize) {
+    let mut i = start;
+    let mut s = String::new();
+    loop {
+        while i < chars.len() && is_word_char(chars[i]) {
+            s.push(chars[i]);
+            i += 1;
+        }
+        if chars.get(i) == Some(&':') && chars.get(i + 1).is_some_and(|&c| is_word_char(c)) {
+            s.push(':');
+            i += 1;
+        } else {
+            return (s, i);
+        }
+    }
+}
+
+/// Chuỗi trong cặp nháy (`"..."` hoặc `'...'`, nháy kép bên trong là escape)
+/// -> (nội dung, vị trí sau dấu nháy đóng)
+fn skip_quoted(chars: &[char], start: usize) -> (String, usize) {
+    let quote = chars[start];
+    let mut s = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                s.push(quote);
+                i += 2;
+                continue;
+            }
+            return (s, i + 1);
+        }
+        s.push(chars[i]);
+        i += 1;
+    }
+    (s, i)
+}
+
+/// Vị trí sau dấu ']' khớp với '[' ở `start` (có thể lồng nhau)
+fn skip_brackets(chars: &[char], start: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fref(s: &str) -> FormulaRef {
+        FormulaRef {
+            sheet: None,
+            range: Range::parse(s).unwrap(),
+        }
+    }
+
+    #[test]
+    fn shifted_moves_relative_parts_only() {
+        assert_eq!(fref("A1:B2").shifted(2, 3).range.to_string(), "C4:D5");
+        assert_eq!(fref("$A1:B$2").shifted(2, 3).range.to_string(), "$A4:D$2");
+        assert_eq!(fref("$A$1").shifted(5, 5).range.to_string(), "$A$1");
+        // cả hàng chỉ dời theo hàng, cả cột chỉ dời theo cột
+        assert_eq!(fref("1:2").shifted(4, 1).range.to_string(), "2:3");
+        assert_eq!(fref("A:B").shifted(1, 4).range.to_string(), "B:C");
+    }
+
+    #[test]
+    fn shifted_stays_inside_sheet() {
+        let rg = fref("XFC1048575").shifted(10, 10).range;
+        assert_eq!((rg.first.col, rg.first.row), (MAX_COL, MAX_ROW));
+        let sheet = Some("Data".to_string());
+        let r = FormulaRef {
+            sheet: sheet.clone(),
+            ..fref("A1")
+        }
+        .shifted(1, 1);
+        assert_eq!(r.sheet, sheet);
+    }
+
+    #[test]
+    fn bounded_treats_sheet_edges_as_whole_lines() {
+        assert_eq!(
+            bounded(&Range::parse("A1:A1048576").unwrap()).kind,
+            RangeKind::Cols
+        );
+        assert_eq!(
+            bounded(&Range::parse("B3:XFD4").unwrap()).kind,
+            RangeKind::Rows
+        );
+        // chạm cả hai cạnh: coi như cả cột
+        assert_eq!(
+            bounded(&Range::parse("A1:XFD1048576").unwrap()).kind,
+            RangeKind::Cols
+        );
+        assert_eq!(
+            bounded(&Range::parse("A1:C10").unwrap()).kind,
+            RangeKind::Cells
+        );
+        assert_eq!(bounded(&Range::parse("2:5").unwrap()).kind, RangeKind::Rows);
+    }
+
+    #[test]
+    fn parse_refs_names_and_external() {
+        let p = parse("SUM('My Sheet'!$A$1:B2,Sheet2!C3)+Rate*2&\"D4\"");
+        let refs: Vec<_> = p
+            .refs
+            .iter()
+            .map(|r| (r.sheet.clone(), r.range.to_string()))
+            .collect();
+        assert_eq!(
+            refs,
+            [
+                (Some("My Sheet".to_string()), "$A$1:B2".to_string()),
+                (Some("Sheet2".to_string()), "C3".to_string()),
+            ]
+        );
+        assert_eq!(p.names, ["Rate"]);
+        assert!(!p.external);
+
+        let p = parse("[1]Sheet1!A1+Table1[Cột]+#REF!");
+        assert!(p.external);
+        assert!(p.refs.is_empty() && p.names.is_empty());
+
+        let p = parse("Sheet1:Sheet3!A1");
+        let sheets: Vec<_> = p.refs.iter().map(|r| r.sheet.as_deref()).collect();
+        assert_eq!(sheets, [Some("Sheet1"), Some("Sheet3")]);
+    }
+}
@@ -3,101 +3,372 @@ use quick_xml::events::{BytesEnd, BytesStart, Event};
 use quick_xml::{Reader, Writer};
 use rayon::prelude::*;
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 use walkdir::WalkDir;
 use zip::write::FileOptions;
 use zip::{ZipArchive, ZipWriter};
 
+mod agile;
+mod calc;
+mod cellref;
+mod cfb;
+mod conformance;
+mod content_types;
+mod formula;
+mod minify;
+mod names;
+mod objects;
+mod ods;
+mod orphans;
+mod rels;
+mod repair;
+mod rows;
+mod sniff;
+mod sst;
+mod styles;
+mod table;
+mod workbook;
+mod xlsb;
 
+use cellref::{CellRef, Range, RangeKind};
+use conformance::Conformance;
+use workbook::SheetKind;
 
-#[derive(Debug, Clone, Copy)]
+/// Cách xác định một ô là "đã dùng"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum RangeMode {
+    /// chỉ ô có giá trị / công thức
+    #[default]
+    Values,
+    /// thêm ô trống nhưng có định dạng nhìn thấy được (fill, viền, định dạng số)
+    Formatting,
+    /// mọi phần tử `<c>`
+    AnyCell,
+}
+
+impl std::str::FromStr for RangeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "values" => Ok(RangeMode::Values),
+            "formatting" => Ok(RangeMode::Formatting),
+            "any" => Ok(RangeMode::AnyCell),
+            _ => anyhow::bail!("Giá trị không hợp lệ cho --range-mode: '{}' (values|formatting|any)", s),
+        }
+    }
+}
+
+/// Các tuỳ chọn xử lý một workbook (lấy từ dòng lệnh)
+#[derive(Debug, Clone, Default)]
+struct TrimOptions {
+    /// Coi ô chuỗi rỗng/chỉ khoảng trắng (sharedStrings hoặc inline) là ô trống
+    blank_strings_empty: bool,
+    range_mode: RangeMode,
+    /// Xoá hình, biểu đồ, comment, bảng nằm ngoài vùng dữ liệu thay vì nới vùng để giữ chúng
+    drop_outside_objects: bool,
+    /// Nới vùng để giữ các ô mà công thức / tên định nghĩa trỏ tới (mặc định chỉ cảnh báo)
+    protect_refs: bool,
+    /// Dọn definedNames: xoá tên hỏng/ngoài/không dùng, cắt Print_Area/Print_Titles
+    clean_names: bool,
+    /// Khi xoá calcChain thì đặt `<calcPr fullCalcOnLoad="1"/>` để Excel tính lại khi mở
+    full_calc_on_load: bool,
+    /// Giữ calcChain, chỉ bỏ các mục của ô đã bị cắt
+    keep_calc_chain: bool,
+    /// Thay công thức bằng giá trị đã tính, bỏ calcChain và liên kết ngoài
+    values_only: bool,
+    /// Chuyển chuỗi inline (`t="inlineStr"`) sang sharedStrings.xml
+    share_strings: bool,
+    /// Thu gọn XML của worksheet sau khi ghi lại
+    minify: bool,
+    /// Bỏ các hàng trống chỉ mang chiều cao bằng `defaultRowHeight` của sheetFormatPr
+    collapse_rows: bool,
+    /// Bỏ ô trống có style trùng style mặc định của hàng/cột; hàng chỉ gồm ô trống cùng style
+    /// phủ hết vùng dữ liệu thì chuyển style đó lên hàng
+    drop_default_cells: bool,
+    /// Cứu file hỏng: zip mất central directory, sheet bị cắt giữa chừng
+    repair: bool,
+}
+
+impl TrimOptions {
+    /// Các tùy chọn đang bật nhưng chỉ áp dụng cho SpreadsheetML (XML), để cảnh báo với .xlsb/.ods
+    fn ooxml_only(&self) -> Vec<&'static str> {
+        [
+            (self.blank_strings_empty, "--ignore-blank-strings"),
+            (self.drop_outside_objects, "--drop-outside-objects"),
+            (self.protect_refs, "--protect-refs"),
+            (self.clean_names, "--clean-names"),
+            (self.full_calc_on_load, "--full-calc-on-load"),
+            (self.keep_calc_chain, "--keep-calc-chain"),
+            (self.values_only, "--values-only"),
+            (self.share_strings, "--share-strings"),
+            (self.minify, "--minify"),
+            (self.collapse_rows, "--collapse-rows"),
+            (self.drop_default_cells, "--drop-default-cells"),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect()
+    }
+}
+
+/// Dữ liệu dùng chung của workbook khi xác định vùng dùng của từng sheet
+#[derive(Debug, Default)]
+struct RangeContext {
+    mode: RangeMode,
+    /// blank_strings[i] = true nếu chuỗi chia sẻ thứ i rỗng; None = không xét chuỗi rỗng
+    blank_strings: Option<Vec<bool>>,
+    /// visible_styles[s] = true nếu style `s` có hiệu ứng nhìn thấy (chỉ nạp ở chế độ Formatting)
+    visible_styles: Vec<bool>,
+    /// ô trống trùng style mặc định sẽ bị bỏ khi ghi lại (--drop-default-cells): không tính
+    drop_default_cells: bool,
+}
+
+impl RangeContext {
+    /// Ô trống (không giá trị) có được tính là đã dùng theo chế độ hiện tại không
+    fn counts_empty_cell(&self, e: &BytesStart) -> bool {
+        match self.mode {
+            RangeMode::Values => false,
+            RangeMode::AnyCell => true,
+            RangeMode::Formatting => attr_value(e, b"s")
+                .and_then(|s| s.parse::<usize>().ok())
+                .and_then(|s| self.visible_styles.get(s).copied())
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
 struct UsedRange {
+    first_row: u32,
+    first_col: u32,
     last_row: u32,
     last_col: u32,
 }
 
+impl UsedRange {
+    fn is_empty(&self) -> bool {
+        self.last_row == 0 || self.last_col == 0
+    }
+
+    /// Mở rộng vùng để chứa ô (col, row)
+    fn include(&mut self, col: u32, row: u32) {
+        if col == 0 || row == 0 {
+            return;
+        }
+        if self.is_empty() {
+            *self = UsedRange {
+                first_row: row,
+                first_col: col,
+                last_row: row,
+                last_col: col,
+            };
+            return;
+        }
+        self.first_row = self.first_row.min(row);
+        self.first_col = self.first_col.min(col);
+        self.last_row = self.last_row.max(row);
+        self.last_col = self.last_col.max(col);
+    }
+
+    /// Mở rộng vùng để chứa cả vùng `rg` (vùng cả hàng/cột chỉ tính ô đầu)
+    fn include_range(&mut self, rg: &Range) {
+        self.include(rg.first.col, rg.first.row);
+        if rg.kind == RangeKind::Cells {
+            self.include(rg.last.col, rg.last.row);
+        }
+    }
+
+    /// ref cho `<dimension>` như Excel ghi: "C5:H200", một ô thì "C5", sheet trống thì "A1"
+    fn dimension_ref(&self) -> String {
+        if self.is_empty() {
+            "A1".to_string()
+        } else {
+            Range::new(self.first_col, self.first_row, self.last_col, self.last_row).to_string()
+        }
+    }
+}
+
 fn local_name(name: &[u8]) -> &str {
-    // name có thể dạng "{ns}tag" hoặc "tag"
+    // name có thể dạng "{ns}tag", "prefix:tag" (vd xdr:twoCellAnchor) hoặc "tag"
     let s = std::str::from_utf8(name).unwrap_or("");
-    match s.rsplit_once('}') {
+    match s.rsplit_once(['}', ':']) {
         Some((_, tag)) => tag,
         None => s,
     }
 }
 
-fn col_letters_to_index(s: &str) -> Option<u32> {
-    let mut n: u32 = 0;
-    for ch in s.chars() {
-        if !('A'..='Z').contains(&ch) {
-            return None;
+/// Giá trị (đã unescape) của thuộc tính `key`
+fn attr_value(e: &BytesStart, key: &[u8]) -> Option<String> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|a| a.key.as_ref() == key)
+        .map(|a| {
+            a.unescape_value()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&a.value).to_string())
+        })
+}
+
+/// Bản sao của thẻ với thuộc tính `key` được thay giá trị (giữ vị trí), thêm mới,
+/// hoặc bị xoá nếu `value` là None
+fn replace_attr(e: &BytesStart, key: &[u8], value: Option<&str>) -> BytesStart<'static> {
+    let key_str = std::str::from_utf8(key).unwrap_or("");
+    let mut el = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).to_string());
+    let mut found = false;
+    for a in e.attributes().with_checks(false).flatten() {
+        if a.key.as_ref() == key {
+            found = true;
+            if let Some(v) = value {
+                el.push_attribute((key_str, v));
+            }
+        } else {
+            el.push_attribute(a);
+        }
+    }
+    if !found {
+        if let Some(v) = value {
+            el.push_attribute((key_str, v));
         }
-        n = n * 26 + (ch as u8 - b'A' + 1) as u32;
     }
-    Some(n)
+    el
 }
 
-fn col_index_to_letters(mut idx: u32) -> String {
-    let mut s = Vec::new();
-    while idx > 0 {
-        let r = (idx - 1) % 26;
-        s.push((b'A' + (r as u8)) as char);
-        idx = (idx - 1) / 26;
+/// Vị trí hàng/ô hiện tại khi duyệt sheetData. Thuộc tính `r` của `<row>`/`<c>` là
+/// tuỳ chọn theo spec: thiếu thì hàng = hàng trước + 1, ô = ô trước + 1 trong cùng hàng.
+#[derive(Default)]
+struct Cursor {
+    row: u32,
+    col: u32,
+}
+
+impl Cursor {
+    /// -> (chỉ số hàng, có `r` tường minh hay không)
+    fn row(&mut self, e: &BytesStart) -> (u32, bool) {
+        let explicit = attr_value(e, b"r").and_then(|s| s.parse().ok());
+        self.row = explicit.unwrap_or(self.row + 1);
+        self.col = 0;
+        (self.row, explicit.is_some())
+    }
+
+    /// -> (cột, hàng, có `r` tường minh hay không)
+    fn cell(&mut self, e: &BytesStart) -> (u32, u32, bool) {
+        match attr_value(e, b"r").and_then(|s| CellRef::parse(&s)) {
+            Some(CellRef { col: c, row: r, .. }) => {
+                self.col = c;
+                (c, r, true)
+            }
+            None => {
+                self.col += 1;
+                (self.col, self.row, false)
+            }
+        }
     }
-    s.into_iter().rev().collect()
 }
 
-fn split_cell_ref(r: &str) -> Option<(u32, u32)> {
-    // "BC12" -> (55, 12)
-    let pos = r.find(|c: char| c.is_ascii_digit())?;
-    let (letters, digits) = r.split_at(pos);
-    let col = col_letters_to_index(letters)?;
-    let row: u32 = digits.parse().ok()?;
-    Some((col, row))
+/// Kết quả pass 1 của một sheet
+struct SheetScan {
+    used: UsedRange,
+    /// ref của các hyperlink trong sheet
+    hyperlinks: Vec<Range>,
+    /// tham chiếu trong các công thức của sheet (đã dời theo vùng của công thức shared),
+    /// kể cả công thức của data validation / conditional formatting
+    refs: Vec<formula::FormulaRef>,
+    /// tên định nghĩa được các công thức đó dùng (chữ thường)
+    names: HashSet<String>,
 }
 
-fn find_used_range_sheet(xml_path: &Path) -> Result<UsedRange> {
+fn find_used_range_sheet(xml_path: &Path, ctx: &RangeContext) -> Result<SheetScan> {
     let mut reader = Reader::from_file(xml_path)?;
     reader.config_mut().trim_text(true);
     let mut buf = Vec::new();
 
-    let mut last_row: u32 = 0;
-    let mut last_col: u32 = 0;
+    let mut used = UsedRange::default();
+    let mut hyperlinks = Vec::new();
+    let mut refs = Vec::new();
+    let mut names = HashSet::new();
+    let mut cursor = Cursor::default();
+    // style mặc định của hàng/cột, để biết ô trống nào sẽ bị --drop-default-cells bỏ
+    let mut col_styles: Vec<(u32, u32, u32)> = Vec::new();
+    let mut current_row_style = None;
+    let counts_blank = |e: &BytesStart, c: u32, row_style: Option<u32>, col_styles: &[(u32, u32, u32)]| {
+        ctx.counts_empty_cell(e)
+            && !(ctx.drop_default_cells && cell_style(e) == default_cell_style(row_style, c, col_styles))
+    };
 
     loop {
         match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) if local_name(e.name().as_ref()) == "c" => {
-                // lấy r attr
-                let mut r_attr: Option<String> = None;
-                for a in e.attributes().with_checks(false) {
-                    if let Ok(a) = a {
-                        if a.key.as_ref() == b"r" {
-                            r_attr = Some(String::from_utf8_lossy(&a.value).to_string());
-                        }
-                    }
+            Ok(Event::Empty(ref e)) if local_name(e.name().as_ref()) == "col" => {
+                let num = |k: &[u8]| attr_value(e, k).and_then(|v| v.parse::<u32>().ok());
+                if let (Some(min), Some(max)) = (num(b"min"), num(b"max")) {
+                    col_styles.push((min, max, num(b"style").unwrap_or(0)));
+                }
+            }
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if local_name(e.name().as_ref()) == "hyperlink" =>
+            {
+                hyperlinks.extend(attr_value(e, b"ref").and_then(|r| Range::parse(&r)));
+            }
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if local_name(e.name().as_ref()) == "row" =>
+            {
+                cursor.row(e);
+                current_row_style = row_style(e);
+            }
+            Ok(Event::Empty(ref e)) if local_name(e.name().as_ref()) == "c" => {
+                let (c, r, _) = cursor.cell(e);
+                if counts_blank(e, c, current_row_style, &col_styles) {
+                    used.include(c, r);
                 }
-                // Đọc đến </c>, kiểm tra có v/f/is
+            }
+            Ok(Event::Start(ref e)) if local_name(e.name().as_ref()) == "c" => {
+                let (c, r, _) = cursor.cell(e);
+                let cell_type = attr_value(e, b"t");
+                // Đọc đến </c>, kiểm tra có v/f/is; giữ lại text của v và của chuỗi inline
                 let mut depth = 1usize;
                 let mut seen_v = false;
                 let mut seen_f = false;
                 let mut seen_is = false;
+                let mut in_v = false;
+                let mut in_f = false;
+                let mut has_child = false;
+                let mut rph_depth = 0usize;
+                let mut v_text = String::new();
+                let mut is_text = String::new();
+                let mut f_text = String::new();
+                // công thức shared gốc: ref="C2:C100" -> các ô chép lệch tối đa (dc, dr)
+                let mut f_offset = (0, 0);
                 let mut inner = Vec::new();
                 loop {
                     match reader.read_event_into(&mut inner) {
                         Ok(Event::Start(se)) => {
+                            has_child = true;
                             let name = se.name();
                             let tag = local_name(name.as_ref());
                             if tag == "v" {
                                 seen_v = true;
+                                in_v = true;
                             } else if tag == "f" {
                                 seen_f = true;
+                                in_f = true;
+                                if attr_value(&se, b"t").as_deref() == Some("shared") {
+                                    if let Some(rg) = attr_value(&se, b"ref").and_then(|v| Range::parse(&v)) {
+                                        f_offset = (rg.last.col.saturating_sub(c), rg.last.row.saturating_sub(r));
+                                    }
+                                }
                             } else if tag == "is" {
                                 seen_is = true;
+                            } else if tag == "rPh" {
+                                rph_depth += 1;
                             }
                             depth += 1;
                         }
                         Ok(Event::Empty(se)) => {
+                            has_child = true;
                             let name = se.name();
                             let tag = local_name(name.as_ref());
                             if tag == "v" {
@@ -108,7 +379,31 @@ fn find_used_range_sheet(xml_path: &Path) -> Result<UsedRange> {
                                 seen_is = true;
                             }
                         }
-                        Ok(Event::End(_)) => {
+                        Ok(Event::Text(t)) => {
+                            if in_v {
+                                v_text.push_str(&t.decode()?);
+                            } else if in_f {
+                                f_text.push_str(&t.decode()?);
+                            } else if seen_is && rph_depth == 0 {
+                                is_text.push_str(&t.decode()?);
+                            }
+                        }
+                        Ok(Event::GeneralRef(ref gr)) => {
+                            if in_v {
+                                sst::push_ref(&mut v_text, gr);
+                            } else if in_f {
+                                sst::push_ref(&mut f_text, gr);
+                            } else if seen_is && rph_depth == 0 {
+                                sst::push_ref(&mut is_text, gr);
+                            }
+                        }
+                        Ok(Event::End(ee)) => {
+                            match local_name(ee.name().as_ref()) {
+                                "v" => in_v = false,
+                                "f" => in_f = false,
+                                "rPh" => rph_depth = rph_depth.saturating_sub(1),
+                                _ => {}
+                            }
                             depth -= 1;
                             if depth == 0 {
                                 break;
@@ -121,16 +416,61 @@ fn find_used_range_sheet(xml_path: &Path) -> Result<UsedRange> {
                     inner.clear();
                 }
 
-                if (seen_v || seen_f || seen_is) && r_attr.is_some() {
-                    if let Some((c, r)) = split_cell_ref(&r_attr.unwrap()) {
-                        if r > last_row {
-                            last_row = r;
+                // chuỗi rỗng (khi bật tuỳ chọn) không tính là có giá trị; công thức luôn tính
+                if let Some(blank) = &ctx.blank_strings {
+                    match cell_type.as_deref() {
+                        Some("s") if !seen_f => {
+                            let idx: Option<usize> = v_text.trim().parse().ok();
+                            if idx.and_then(|i| blank.get(i)).copied().unwrap_or(false) {
+                                seen_v = false;
+                            }
                         }
-                        if c > last_col {
-                            last_col = c;
+                        Some("inlineStr") if !seen_f && is_text.trim().is_empty() => {
+                            seen_is = false;
                         }
+                        _ => {}
+                    }
+                }
+
+                // <c s=".."></c> không có phần tử con được ghi lại như <c/>
+                let blank_counts = match has_child {
+                    true => ctx.counts_empty_cell(e),
+                    false => counts_blank(e, c, current_row_style, &col_styles),
+                };
+                if seen_v || seen_f || seen_is || blank_counts {
+                    used.include(c, r);
+                }
+                let parsed = formula::parse(&f_text);
+                for fr in parsed.refs {
+                    if f_offset != (0, 0) {
+                        refs.push(fr.shifted(f_offset.0, f_offset.1));
                     }
+                    refs.push(fr);
                 }
+                names.extend(parsed.names.iter().map(|n| n.to_lowercase()));
+            }
+            // extLst bị bỏ khi ghi lại nên DV/CF bản x14 trong đó không tính
+            Ok(Event::Start(ref e)) if local_name(e.name().as_ref()) == "extLst" => {
+                reader.read_to_end_into(e.name(), &mut Vec::new())?;
+            }
+            // công thức của data validation / conditional formatting
+            Ok(Event::Start(ref e))
+                if matches!(local_name(e.name().as_ref()), "formula" | "formula1" | "formula2" | "f") =>
+            {
+                let mut text = String::new();
+                let mut inner = Vec::new();
+                loop {
+                    match reader.read_event_into(&mut inner)? {
+                        Event::Text(t) => text.push_str(&t.decode()?),
+                        Event::GeneralRef(ref gr) => sst::push_ref(&mut text, gr),
+                        Event::End(_) | Event::Eof => break,
+                        _ => {}
+                    }
+                    inner.clear();
+                }
+                let parsed = formula::parse(&text);
+                refs.extend(parsed.refs);
+                names.extend(parsed.names.iter().map(|n| n.to_lowercase()));
             }
             Ok(Event::Eof) => break,
             Ok(_) => {}
@@ -139,21 +479,374 @@ fn find_used_range_sheet(xml_path: &Path) -> Result<UsedRange> {
         buf.clear();
     }
 
-    Ok(UsedRange { last_row, last_col })
+    Ok(SheetScan {
+        used,
+        hyperlinks,
+        refs,
+        names,
+    })
+}
+
+/// sqref mới của `<selection>` sau khi cắt về vùng dùng; None = giữ nguyên
+fn clipped_sqref(e: &BytesStart, used: UsedRange) -> Option<String> {
+    if used.is_empty() {
+        return None;
+    }
+    let ranges = cellref::parse_sqref(&attr_value(e, b"sqref")?)?;
+    let clipped: Vec<Range> = ranges
+        .iter()
+        .filter_map(|r| r.clip(used.last_col, used.last_row))
+        .collect();
+    (!clipped.is_empty() && clipped != ranges).then(|| cellref::format_sqref(&clipped))
+}
+
+/// `<conditionalFormatting>`/`<dataValidation>` sau khi cắt sqref về vùng dùng; None = nằm hẳn
+/// ngoài vùng. Công thức tương đối của quy tắc tính theo ô đầu của vùng đầu tiên, nên nếu vùng
+/// đó bị bỏ thì giữ nguyên sqref.
+fn clipped_rule(e: &BytesStart, used: UsedRange) -> Option<BytesStart<'static>> {
+    let ranges = attr_value(e, b"sqref").and_then(|sq| cellref::parse_sqref(&sq));
+    let Some(ranges) = ranges.filter(|_| !used.is_empty()) else {
+        return Some(e.clone().into_owned());
+    };
+    let clipped: Vec<Range> = ranges
+        .iter()
+        .filter_map(|r| r.clip(used.last_col, used.last_row))
+        .collect();
+    match clipped.first() {
+        None => None,
+        Some(first) if first.first != ranges[0].first => Some(e.clone().into_owned()),
+        Some(_) => Some(replace_attr(e, b"sqref", Some(&cellref::format_sqref(&clipped)))),
+    }
+}
+
+/// Ghi `r` suy ra được vào hàng/ô thiếu nó, để vị trí không bị lệch khi có phần tử
+/// đứng trước bị bỏ
+fn with_row_ref(e: &BytesStart, r: u32, explicit: bool) -> BytesStart<'static> {
+    if explicit {
+        e.clone().into_owned()
+    } else {
+        replace_attr(e, b"r", Some(&r.to_string()))
+    }
+}
+
+fn with_cell_ref(e: &BytesStart, c: u32, r: u32, explicit: bool) -> BytesStart<'static> {
+    if explicit {
+        e.clone().into_owned()
+    } else {
+        replace_attr(e, b"r", Some(&CellRef::new(c, r).to_string()))
+    }
+}
+
+/// style của ô (`s`, mặc định 0)
+fn cell_style(e: &BytesStart) -> u32 {
+    attr_value(e, b"s").and_then(|s| s.parse().ok()).unwrap_or(0)
+}
+
+/// style của hàng nếu có `customFormat`: ô vắng mặt hiển thị theo style này thay vì style cột
+fn row_style(e: &BytesStart) -> Option<u32> {
+    let custom = attr_value(e, b"customFormat").is_some_and(|v| v == "1" || v == "true");
+    custom.then(|| attr_value(e, b"s").and_then(|s| s.parse().ok()).unwrap_or(0))
+}
+
+/// Style ô trống tại cột `col` hiển thị khi không có `<c>`: style hàng, nếu không thì style cột.
+/// `col_styles` là (min, max, style) của các `<col>`.
+fn default_cell_style(row_style: Option<u32>, col: u32, col_styles: &[(u32, u32, u32)]) -> u32 {
+    row_style.unwrap_or_else(|| {
+        col_styles
+            .iter()
+            .find(|(min, max, _)| (*min..=*max).contains(&col))
+            .map_or(0, |c| c.2)
+    })
+}
+
+/// ref mới của autoFilter sau khi cắt về vùng dùng; None = giữ nguyên
+fn clipped_filter_ref(e: &BytesStart, used: UsedRange) -> Option<String> {
+    if used.is_empty() {
+        return None;
+    }
+    let rg = Range::parse(&attr_value(e, b"ref")?)?;
+    rg.clip(used.last_col, used.last_row).map(|r| r.to_string())
+}
+
+/// r:id của thẻ (thuộc tính `id` có prefix, thường là `r:id`)
+fn rel_id_attr(e: &BytesStart) -> Option<String> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|a| a.key.as_ref().ends_with(b":id"))
+        .map(|a| String::from_utf8_lossy(&a.value).to_string())
+}
+
+/// Lọc `<hyperlinks>`: bỏ link nằm ngoài vùng dùng, cắt ref nhiều ô.
+/// Trả về r:id của các link bị bỏ và của các link còn giữ.
+fn write_hyperlinks<R: BufRead, W: Write>(
+    reader: &mut Reader<R>,
+    writer: &mut Writer<W>,
+    e: &BytesStart,
+    used: UsedRange,
+) -> Result<(HashSet<String>, HashSet<String>)> {
+    let mut kept: Vec<BytesStart<'static>> = Vec::new();
+    let mut dropped_ids = HashSet::new();
+    let mut kept_ids = HashSet::new();
+    let mut buf = Vec::new();
+    loop {
+        let ev = reader.read_event_into(&mut buf)?;
+        let (Event::Start(ref h) | Event::Empty(ref h)) = ev else {
+            match ev {
+                Event::End(ref ee) if local_name(ee.name().as_ref()) == "hyperlinks" => break,
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+            continue;
+        };
+        if matches!(ev, Event::Start(_)) {
+            reader.read_to_end_into(h.name(), &mut Vec::new())?;
+        }
+        if local_name(h.name().as_ref()) == "hyperlink" {
+            let range = attr_value(h, b"ref").and_then(|r| Range::parse(&r));
+            let clipped = match (range, used.is_empty()) {
+                (_, true) | (None, _) => Some(h.clone().into_owned()),
+                (Some(rg), false) => rg
+                    .clip(used.last_col, used.last_row)
+                    .map(|rg| replace_attr(h, b"ref", Some(&rg.to_string()))),
+            };
+            let id = rel_id_attr(h);
+            match clipped {
+                Some(el) => {
+                    kept_ids.extend(id);
+                    kept.push(el);
+                }
+                None => dropped_ids.extend(id),
+            }
+        }
+        buf.clear();
+    }
+
+    if !kept.is_empty() {
+        writer.write_event(Event::Start(e.clone()))?;
+        for h in kept {
+            writer.write_event(Event::Empty(h))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new(
+            String::from_utf8_lossy(e.name().as_ref()).to_string(),
+        )))?;
+    }
+    Ok((dropped_ids, kept_ids))
 }
 
-/// Pass 2: ghi lại sheet, cắt hàng/cột vượt vùng dùng & dọn các khối phình size
-fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
+/// Lọc `<dataValidations>`: cắt sqref của từng dataValidation về vùng dùng, bỏ cái nằm hẳn
+/// ngoài vùng, cập nhật count.
+fn write_data_validations<R: BufRead, W: Write>(
+    reader: &mut Reader<R>,
+    writer: &mut Writer<W>,
+    e: &BytesStart,
+    used: UsedRange,
+) -> Result<()> {
+    let mut kept: Vec<Vec<Event<'static>>> = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        let ev = reader.read_event_into(&mut buf)?;
+        let (Event::Start(ref dv) | Event::Empty(ref dv)) = ev else {
+            match ev {
+                Event::End(ref ee) if local_name(ee.name().as_ref()) == "dataValidations" => break,
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+            continue;
+        };
+        let el = match local_name(dv.name().as_ref()) {
+            "dataValidation" => clipped_rule(dv, used),
+            _ => None,
+        };
+        match (el, matches!(ev, Event::Start(_))) {
+            (None, true) => {
+                reader.read_to_end_into(dv.name(), &mut Vec::new())?;
+            }
+            (None, false) => {}
+            (Some(el), false) => kept.push(vec![Event::Empty(el)]),
+            (Some(el), true) => {
+                // chép nguyên phần tử con (formula1, formula2...)
+                let mut events = vec![Event::Start(el)];
+                let mut depth = 1usize;
+                let mut inner = Vec::new();
+                while depth > 0 {
+                    let ev = reader.read_event_into(&mut inner)?;
+                    match ev {
+                        Event::Start(_) => depth += 1,
+                        Event::End(_) => depth -= 1,
+                        Event::Eof => break,
+                        _ => {}
+                    }
+                    events.push(ev.into_owned());
+                    inner.clear();
+                }
+                kept.push(events);
+            }
+        }
+        buf.clear();
+    }
+
+    if !kept.is_empty() {
+        let count = kept.len().to_string();
+        writer.write_event(Event::Start(replace_attr(e, b"count", Some(&count))))?;
+        for ev in kept.into_iter().flatten() {
+            writer.write_event(ev)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new(
+            String::from_utf8_lossy(e.name().as_ref()).to_string(),
+        )))?;
+    }
+    Ok(())
+}
+
+/// Lọc `<tableParts>`: bỏ các tablePart có r:id trong `drop`, cập nhật count.
+/// Trả về r:id của các tablePart bị bỏ.
+fn write_table_parts<R: BufRead, W: Write>(
+    reader: &mut Reader<R>,
+    writer: &mut Writer<W>,
+    e: &BytesStart,
+    drop: &HashSet<String>,
+) -> Result<HashSet<String>> {
+    let mut kept: Vec<BytesStart<'static>> = Vec::new();
+    let mut dropped = HashSet::new();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Empty(ref tp) if local_name(tp.name().as_ref()) == "tablePart" => {
+                match rel_id_attr(tp) {
+                    Some(id) if drop.contains(&id) => {
+                        dropped.insert(id);
+                    }
+                    _ => kept.push(tp.clone().into_owned()),
+                }
+            }
+            Event::End(ref ee) if local_name(ee.name().as_ref()) == "tableParts" => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    if !kept.is_empty() {
+        let count = kept.len().to_string();
+        writer.write_event(Event::Start(replace_attr(e, b"count", Some(&count))))?;
+        for tp in kept {
+            writer.write_event(Event::Empty(tp))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new(
+            String::from_utf8_lossy(e.name().as_ref()).to_string(),
+        )))?;
+    }
+    Ok(dropped)
+}
+
+/// Thẻ mở `<row>` đang chờ, cùng cột nhỏ nhất/lớn nhất của các ô được giữ trong hàng
+struct RowSpans {
+    start: BytesStart<'static>,
+    cols: Option<(u32, u32)>,
+    /// style của hàng nếu có `customFormat`: ô vắng mặt hiển thị theo style này thay vì style cột
+    style: Option<u32>,
+    /// style chung khi mọi ô trong hàng đều trống và cùng một style
+    blanks: Option<u32>,
+    /// cột của các ô trống đó
+    blank_cols: Vec<u32>,
+    uniform: bool,
+}
+
+impl RowSpans {
+    fn new(start: BytesStart<'static>) -> Self {
+        let style = row_style(&start);
+        RowSpans { start, cols: None, style, blanks: None, blank_cols: Vec::new(), uniform: true }
+    }
+
+    fn include(&mut self, col: u32) {
+        self.cols = Some(match self.cols {
+            Some((lo, hi)) => (lo.min(col), hi.max(col)),
+            None => (col, col),
+        });
+    }
+
+    fn note_value(&mut self) {
+        self.uniform = false;
+    }
+
+    fn note_blank(&mut self, style: u32, col: u32) {
+        match self.blanks {
+            None => self.blanks = Some(style),
+            Some(s) if s == style => {}
+            Some(_) => self.uniform = false,
+        }
+        self.blank_cols.push(col);
+    }
+
+    /// Style ô trống tại cột `col` hiển thị khi không có `<c>`
+    fn default_style(&self, col: u32, col_styles: &[(u32, u32, u32)]) -> u32 {
+        default_cell_style(self.style, col, col_styles)
+    }
+
+    /// Style chung để chuyển lên hàng: mọi ô là ô trống cùng style `s`, và style hàng (áp cho
+    /// mọi cột không có `<c>`, kể cả sau vùng dữ liệu) không đổi gì: mỗi cột từ A đến XFD hoặc
+    /// có ô, hoặc có style cột `s`. Hàng đã có style riêng thì không chuyển.
+    fn merged_style(&self, col_styles: &[(u32, u32, u32)]) -> Option<u32> {
+        let s = self.blanks.filter(|_| self.uniform && self.style.is_none())?;
+        let mut covered: Vec<(u32, u32)> = self.blank_cols.iter().map(|&c| (c, c)).collect();
+        covered.extend(col_styles.iter().filter(|c| c.2 == s).map(|c| (c.0, c.1)));
+        covered.sort_unstable();
+        let mut next = 1;
+        for (min, max) in covered {
+            if min > next {
+                return None;
+            }
+            next = next.max(max.saturating_add(1));
+        }
+        (next > cellref::MAX_COL).then_some(s)
+    }
+
+    /// `<row>` với spans="min:max" mới; hàng không còn ô thì bỏ spans
+    fn start(&self) -> BytesStart<'static> {
+        if attr_value(&self.start, b"spans").is_none() {
+            return self.start.clone();
+        }
+        let spans = self.cols.map(|(lo, hi)| format!("{}:{}", lo, hi));
+        replace_attr(&self.start, b"spans", spans.as_deref())
+    }
+}
+
+/// Pass 2: ghi lại sheet, cắt hàng/cột vượt vùng dùng & dọn các khối phình size.
+/// `drop_rels`: r:id của các bảng cần bỏ khỏi `<tableParts>`.
+/// Trả về r:id của các quan hệ không còn được sheet dùng tới (hyperlink, bảng bị bỏ).
+fn rewrite_sheet(
+    xml_in: &Path,
+    xml_out: &Path,
+    used: UsedRange,
+    drop_rels: &HashSet<String>,
+    opts: &TrimOptions,
+) -> Result<HashSet<String>> {
     let mut reader = Reader::from_file(xml_in)?;
     reader.config_mut().trim_text(false);
-    let mut writer = Writer::new(BufWriter::new(File::create(xml_out)?));
+    let mut file = BufWriter::new(File::create(xml_out)?);
+    // mọi event được ghi vào bộ đệm; nội dung một <row> được giữ lại đến </row> để tính
+    // lại `spans` từ các ô còn lại, ngoài ra bộ đệm được đổ ra file sau mỗi event
+    let mut writer = Writer::new(Vec::new());
+    let mut open_row: Option<RowSpans> = None;
+    // (min, max, style) của các <col>, để biết style mặc định của từng cột
+    let mut col_styles: Vec<(u32, u32, u32)> = Vec::new();
     let mut buf = Vec::new();
 
-    let mut _current_row_idx: Option<u32> = None;
-    let mut drop_this_row = false;
+    let mut cursor = Cursor::default();
+    let mut dropped_rels = HashSet::new();
+    let mut kept_rels = HashSet::new();
+    // event đã đọc trước (khi xem <c> có phần tử con không) nhưng chưa xử lý
+    let mut pending: Option<Event<'static>> = None;
 
     loop {
-        match reader.read_event_into(&mut buf) {
+        let ev = match pending.take() {
+            Some(ev) => Ok(ev),
+            None => reader.read_event_into(&mut buf),
+        };
+        match ev {
             Ok(Event::Start(ref e)) => {
                 let name = e.name();
                 let tag = local_name(name.as_ref());
@@ -170,28 +863,12 @@ fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
                                 ));
                             }
                         }
-                        let new_ref = if used.last_row == 0 || used.last_col == 0 {
-                            "A1:A1".to_string()
-                        } else {
-                            format!("A1:{}{}", col_index_to_letters(used.last_col), used.last_row)
-                        };
-                        el.push_attribute(("ref", new_ref.as_str()));
+                        el.push_attribute(("ref", used.dimension_ref().as_str()));
                         writer.write_event(Event::Start(el))?;
                     }
                     "row" => {
-                        // xác định row index
-                        let mut r_idx = None::<u32>;
-                        for a in e.attributes().with_checks(false).flatten() {
-                            if a.key.as_ref() == b"r" {
-                                r_idx = std::str::from_utf8(&a.value).ok().and_then(|s| s.parse().ok());
-                            }
-                        }
-                        _current_row_idx = r_idx;
-                        drop_this_row = r_idx
-                            .map(|r| used.last_row > 0 && r > used.last_row)
-                            .unwrap_or(false);
-
-                        if drop_this_row {
+                        let (r_idx, explicit) = cursor.row(e);
+                        if used.last_row > 0 && r_idx > used.last_row {
                             // ăn hết nội dung <row>…</row> mà không ghi
                             let mut depth = 1usize;
                             let mut inner = Vec::new();
@@ -210,30 +887,15 @@ fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
                                 }
                                 inner.clear();
                             }
-                            _current_row_idx = None;
                             continue;
                         } else {
-                            writer.write_event(Event::Start(e.clone()))?;
+                            open_row = Some(RowSpans::new(with_row_ref(e, r_idx, explicit)));
                         }
                     }
                     "c" => {
                         // kiểm tra cột của cell, nếu > last_col thì bỏ
-                        let mut r_attr: Option<String> = None;
-                        for a in e.attributes().with_checks(false).flatten() {
-                            if a.key.as_ref() == b"r" {
-                                r_attr =
-                                    Some(String::from_utf8_lossy(&a.value).to_string());
-                            }
-                        }
-                        let mut keep = true;
-                        if let Some(r) = r_attr.as_ref() {
-                            if let Some((c, _)) = split_cell_ref(r) {
-                                if used.last_col > 0 && c > used.last_col {
-                                    keep = false;
-                                }
-                            }
-                        }
-                        if !keep {
+                        let (c, r, explicit) = cursor.cell(e);
+                        if used.last_col > 0 && c > used.last_col {
                             // skip cả block <c>…</c>
                             let mut depth = 1usize;
                             let mut inner = Vec::new();
@@ -254,81 +916,84 @@ fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
                             }
                             continue;
                         } else {
-                            writer.write_event(Event::Start(e.clone()))?;
+                            // <c s=".."></c> không có phần tử con: xử lý như ô trống <c/>
+                            let mut spaces = Vec::new();
+                            let mut childless = false;
+                            if opts.drop_default_cells {
+                                let mut inner = Vec::new();
+                                loop {
+                                    match reader.read_event_into(&mut inner)? {
+                                        Event::Text(t) if t.iter().all(u8::is_ascii_whitespace) => {
+                                            spaces.push(Event::Text(t.into_owned()));
+                                        }
+                                        Event::End(_) => {
+                                            childless = true;
+                                            break;
+                                        }
+                                        ev => {
+                                            pending = Some(ev.into_owned());
+                                            break;
+                                        }
+                                    }
+                                    inner.clear();
+                                }
+                            }
+                            if let Some(row) = open_row.as_mut() {
+                                if childless {
+                                    let s = cell_style(e);
+                                    row.note_blank(s, c);
+                                    if s == row.default_style(c, &col_styles) {
+                                        buf.clear();
+                                        continue;
+                                    }
+                                } else {
+                                    row.note_value();
+                                }
+                                row.include(c);
+                            }
+                            let mut el = with_cell_ref(e, c, r, explicit);
+                            if opts.values_only {
+                                // cm trỏ tới metadata của công thức mảng động
+                                el = replace_attr(&el, b"cm", None);
+                            }
+                            writer.write_event(Event::Start(el))?;
+                            for ev in spaces {
+                                writer.write_event(ev)?;
+                            }
+                            if childless {
+                                writer.write_event(Event::End(BytesEnd::new(
+                                    String::from_utf8_lossy(e.name().as_ref()).to_string(),
+                                )))?;
+                            }
                         }
                     }
+                    "f" if opts.values_only => {
+                        // chỉ giữ giá trị đã tính (<v>)
+                        reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                    }
                     "mergeCells" => {
                         // bắt & lọc toàn bộ mergeCells rồi viết lại
-                        let mut kept: Vec<(u32, u32, u32, u32)> = Vec::new();
+                        let mut kept: Vec<Range> = Vec::new();
+                        let fits = |mc: &BytesStart| -> Option<Range> {
+                            let rg = Range::parse(&attr_value(mc, b"ref")?)?;
+                            let col_ok = used.last_col == 0 || rg.last.col <= used.last_col;
+                            let row_ok = used.last_row == 0 || rg.last.row <= used.last_row;
+                            (col_ok && row_ok).then_some(rg)
+                        };
                         let mut inner = Vec::new();
                         loop {
                             match reader.read_event_into(&mut inner) {
                                 Ok(Event::Empty(ref mc)) => {
                                     if local_name(mc.name().as_ref()) == "mergeCell" {
-                                        for a in mc.attributes().with_checks(false).flatten() {
-                                            if a.key.as_ref() == b"ref" {
-                                                if let Ok(s) = std::str::from_utf8(&a.value) {
-                                                    if let Some((a, b)) = s.split_once(':') {
-                                                        if let (Some((c1, r1)), Some((c2, r2))) =
-                                                            (split_cell_ref(a), split_cell_ref(b))
-                                                        {
-                                                            if c1 >= 1
-                                                                && c2 >= 1
-                                                                && r1 >= 1
-                                                                && r2 >= 1
-                                                                && (used.last_col == 0
-                                                                    || (c1 <= used.last_col
-                                                                        && c2 <= used.last_col))
-                                                                && (used.last_row == 0
-                                                                    || (r1 <= used.last_row
-                                                                        && r2 <= used.last_row))
-                                                            {
-                                                                kept.push((c1, r1, c2, r2));
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
+                                        kept.extend(fits(mc));
                                     }
                                 }
                                 Ok(Event::Start(ref mc)) => {
                                     if local_name(mc.name().as_ref()) == "mergeCell" {
-                                        // đọc attr rồi nhảy đến </mergeCell>
-                                        let mut ref_str: Option<String> = None;
-                                        for a in mc.attributes().with_checks(false).flatten() {
-                                            if a.key.as_ref() == b"ref" {
-                                                ref_str = Some(
-                                                    String::from_utf8_lossy(&a.value).to_string(),
-                                                );
-                                            }
-                                        }
-                                        if let Some(s) = ref_str {
-                                            if let Some((a, b)) = s.split_once(':') {
-                                                if let (Some((c1, r1)), Some((c2, r2))) =
-                                                    (split_cell_ref(a), split_cell_ref(b))
-                                                {
-                                                    if c1 >= 1
-                                                        && c2 >= 1
-                                                        && r1 >= 1
-                                                        && r2 >= 1
-                                                        && (used.last_col == 0
-                                                            || (c1 <= used.last_col
-                                                                && c2 <= used.last_col))
-                                                        && (used.last_row == 0
-                                                            || (r1 <= used.last_row
-                                                                && r2 <= used.last_row))
-                                                    {
-                                                        kept.push((c1, r1, c2, r2));
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        reader.read_to_end_into(mc.name(), &mut Vec::new())?;
-                                    } else {
-                                        // bỏ qua phần tử con khác
-                                        reader.read_to_end_into(mc.name(), &mut Vec::new())?;
+                                        kept.extend(fits(mc));
                                     }
+                                    // đọc attr rồi nhảy đến thẻ đóng, bỏ qua phần tử con khác
+                                    reader.read_to_end_into(mc.name(), &mut Vec::new())?;
                                 }
                                 Ok(Event::End(ref ee)) => {
                                     if local_name(ee.name().as_ref()) == "mergeCells" {
@@ -347,25 +1012,36 @@ fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
                             let mut mc_s = BytesStart::new("mergeCells");
                             mc_s.push_attribute(("count", kept.len().to_string().as_str()));
                             writer.write_event(Event::Start(mc_s))?;
-                            for (c1, r1, c2, r2) in kept.drain(..) {
+                            for rg in kept.drain(..) {
                                 let mut m = BytesStart::new("mergeCell");
-                                let r = format!(
-                                    "{}{}:{}{}",
-                                    col_index_to_letters(c1),
-                                    r1,
-                                    col_index_to_letters(c2),
-                                    r2
-                                );
-                                m.push_attribute(("ref", r.as_str()));
+                                m.push_attribute(("ref", rg.to_string().as_str()));
                                 writer.write_event(Event::Empty(m))?;
                             }
                             writer.write_event(Event::End(BytesEnd::new("mergeCells")))?;
                         }
                     }
+                    "autoFilter" => match clipped_filter_ref(e, used) {
+                        Some(r) => table::write_auto_filter(&mut reader, &mut writer, e, false, &r)?,
+                        None => writer.write_event(Event::Start(e.clone()))?,
+                    },
+                    "hyperlinks" => {
+                        let (dropped, kept) = write_hyperlinks(&mut reader, &mut writer, e, used)?;
+                        dropped_rels.extend(dropped);
+                        kept_rels.extend(kept);
+                    }
+                    "tableParts" => {
+                        dropped_rels.extend(write_table_parts(&mut reader, &mut writer, e, drop_rels)?);
+                    }
+                    // DV/CF: cắt sqref, bỏ cái nằm hẳn ngoài vùng dùng
+                    "conditionalFormatting" => match clipped_rule(e, used) {
+                        Some(el) => writer.write_event(Event::Start(el))?,
+                        None => {
+                            reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                        }
+                    },
+                    "dataValidations" => write_data_validations(&mut reader, &mut writer, e, used)?,
                     // Dọn các khối "nặng": skip toàn bộ
-                    "conditionalFormatting"
-                    | "dataValidations"
-                    | "pageBreaks"
+                    "pageBreaks"
                     | "ignoredErrors"
                     | "extLst"
                     | "phoneticPr" => {
@@ -391,14 +1067,53 @@ fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
                                 ));
                             }
                         }
-                        let new_ref = if used.last_row == 0 || used.last_col == 0 {
-                            "A1:A1".to_string()
-                        } else {
-                            format!("A1:{}{}", col_index_to_letters(used.last_col), used.last_row)
-                        };
-                        el.push_attribute(("ref", new_ref.as_str()));
+                        el.push_attribute(("ref", used.dimension_ref().as_str()));
                         writer.write_event(Event::Empty(el))?;
                     }
+                    "autoFilter" => match clipped_filter_ref(e, used) {
+                        Some(r) => table::write_auto_filter(&mut reader, &mut writer, e, true, &r)?,
+                        None => writer.write_event(Event::Empty(e.clone()))?,
+                    },
+                    "row" => {
+                        let (r_idx, explicit) = cursor.row(e);
+                        if used.last_row == 0 || r_idx <= used.last_row {
+                            let el = replace_attr(&with_row_ref(e, r_idx, explicit), b"spans", None);
+                            writer.write_event(Event::Empty(el))?;
+                        }
+                    }
+                    "c" => {
+                        let (c, r, explicit) = cursor.cell(e);
+                        if used.last_col == 0 || c <= used.last_col {
+                            if let Some(row) = open_row.as_mut() {
+                                if opts.drop_default_cells {
+                                    let s = cell_style(e);
+                                    row.note_blank(s, c);
+                                    if s == row.default_style(c, &col_styles) {
+                                        buf.clear();
+                                        continue;
+                                    }
+                                }
+                                row.include(c);
+                            }
+                            let mut el = with_cell_ref(e, c, r, explicit);
+                            if opts.values_only {
+                                el = replace_attr(&el, b"cm", None);
+                            }
+                            writer.write_event(Event::Empty(el))?;
+                        }
+                    }
+                    "f" if opts.values_only => {}
+                    "col" => {
+                        let num = |k: &[u8]| attr_value(e, k).and_then(|v| v.parse::<u32>().ok());
+                        if let (Some(min), Some(max)) = (num(b"min"), num(b"max")) {
+                            col_styles.push((min, max, num(b"style").unwrap_or(0)));
+                        }
+                        writer.write_event(Event::Empty(e.clone()))?;
+                    }
+                    "selection" => match clipped_sqref(e, used) {
+                        Some(sq) => writer.write_event(Event::Empty(replace_attr(e, b"sqref", Some(&sq))))?,
+                        None => writer.write_event(Event::Empty(e.clone()))?,
+                    },
                     // skip các singleton nặng nếu có
                     "conditionalFormatting"
                     | "dataValidations"
@@ -412,11 +1127,31 @@ fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
                 }
             }
             Ok(Event::End(e)) => {
+                if local_name(e.name().as_ref()) == "row" {
+                    if let Some(row) = open_row.take() {
+                        let mut row_writer = Writer::new(&mut file);
+                        match row.merged_style(&col_styles).filter(|_| opts.drop_default_cells) {
+                            Some(s) => {
+                                // hàng chỉ gồm ô trống cùng style: đưa style lên hàng, bỏ các ô
+                                let mut el = replace_attr(&row.start, b"spans", None);
+                                el = replace_attr(&el, b"s", Some(&s.to_string()));
+                                el = replace_attr(&el, b"customFormat", Some("1"));
+                                row_writer.write_event(Event::Start(el))?;
+                            }
+                            None => {
+                                row_writer.write_event(Event::Start(row.start()))?;
+                                file.write_all(writer.get_ref())?;
+                            }
+                        }
+                        writer.get_mut().clear();
+                    }
+                }
                 writer.write_event(Event::End(e))?;
             }
             Ok(Event::Text(t)) => {
                 writer.write_event(Event::Text(t))?;
             }
+            Ok(Event::GeneralRef(r)) => writer.write_event(Event::GeneralRef(r))?,
             Ok(Event::Decl(d)) => writer.write_event(Event::Decl(d))?,
             Ok(Event::PI(p)) => writer.write_event(Event::PI(p))?,
             Ok(Event::CData(c)) => writer.write_event(Event::CData(c))?,
@@ -426,96 +1161,463 @@ fn rewrite_sheet(xml_in: &Path, xml_out: &Path, used: UsedRange) -> Result<()> {
             _ => (), // Ignore other events
         }
         buf.clear();
+        if open_row.is_none() {
+            file.write_all(writer.get_ref())?;
+            writer.get_mut().clear();
+        }
+    }
+    file.write_all(writer.get_ref())?;
+    file.flush()?;
+
+    Ok(dropped_rels.difference(&kept_rels).cloned().collect())
+}
+
+/// Tên sheet trong báo cáo: "sheet1.xml" với worksheet, "macrosheets/sheet1.xml" với loại khác
+fn sheet_label(sheet_xml: &Path) -> String {
+    let file_name = sheet_xml.file_name().unwrap_or_default().to_string_lossy();
+    match sheet_xml.parent().and_then(|d| d.file_name()) {
+        Some(dir) if dir != "worksheets" => format!("{}/{}", dir.to_string_lossy(), file_name),
+        _ => file_name.to_string(),
     }
+}
+
+/// Kết quả xử lý một sheet, để in báo cáo
+struct SheetReport {
+    path: PathBuf,
+    name: String,
+    used: UsedRange,
+    removed_parts: Vec<PathBuf>,
+    /// vùng được tham chiếu vượt ra ngoài vùng đã cắt (khi không bật --protect-refs)
+    outside_refs: Option<String>,
+}
+
+/// Workbook mã hoá Agile: giải mã package ra file tạm, cắt như file thường rồi mã hoá lại
+/// bằng cùng mật khẩu
+fn trim_encrypted(input: &Path, output: &Path, opts: &TrimOptions, password: &str) -> Result<()> {
+    let cf = cfb::CompoundFile::open(input)?;
+    let package = agile::decrypt(&cf, password).context("giải mã")?;
+    let tmp = tempdir()?;
+    let ext = input.extension().unwrap_or_default().to_string_lossy();
+    let plain_in = tmp.path().join(format!("in.{}", ext));
+    let plain_out = tmp.path().join(format!("out.{}", ext));
+    fs::write(&plain_in, package)?;
+    trim_one_xlsx(&plain_in, &plain_out, opts)?;
+    let encrypted = agile::encrypt(&cf, &fs::read(&plain_out)?, password).context("mã hoá lại")?;
+    fs::write(output, encrypted)?;
+    Ok(())
+}
 
+fn unzip(input: &Path, dest: &Path) -> Result<()> {
+    let f = File::open(input)?;
+    let mut zin = ZipArchive::new(f)?;
+    for i in 0..zin.len() {
+        let mut file = zin.by_index(i)?;
+        // entry có đường dẫn tuyệt đối hoặc ".." sẽ ghi ra ngoài thư mục tạm: bỏ qua
+        let Some(name) = file.enclosed_name() else {
+            continue;
+        };
+        let out_path = dest.join(name);
+        if file.is_dir() {
+            fs::create_dir_all(&out_path)?;
+        } else {
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut out = BufWriter::new(File::create(&out_path)?);
+            std::io::copy(&mut file, &mut out)?;
+            out.flush()?;
+        }
+    }
     Ok(())
 }
 
-fn trim_one_xlsx(input: &Path, output: &Path) -> Result<()> {
+fn trim_one_xlsx(input: &Path, output: &Path, opts: &TrimOptions) -> Result<()> {
     // 1) extract zip vào thư mục tạm
     let tmp = tempdir()?;
     let tmpdir = tmp.path();
 
-    // unzip
-    {
-        let f = File::open(input)?;
-        let mut zin = ZipArchive::new(f)?;
-        for i in 0..zin.len() {
-            let mut file = zin.by_index(i)?;
-            let out_path = tmpdir.join(file.name());
-            if file.is_dir() {
-                fs::create_dir_all(&out_path)?;
-            } else {
-                if let Some(parent) = out_path.parent() {
-                    fs::create_dir_all(parent)?;
+    // unzip; với --repair, zip hỏng thì quét local file header và sửa package
+    let mut repaired = Vec::new();
+    if let Err(e) = unzip(input, tmpdir) {
+        if !opts.repair {
+            return Err(e);
+        }
+        eprintln!("   ⚠ zip hỏng ({}), quét lại các local file header", e);
+        fs::remove_dir_all(tmpdir)?;
+        fs::create_dir_all(tmpdir)?;
+        repaired.extend(repair::salvage_zip(input, tmpdir)?);
+    }
+    if opts.repair && !ods::is_ods(tmpdir) {
+        repaired.extend(repair::repair_package(tmpdir)?);
+    }
+    if opts.repair {
+        match repaired.is_empty() {
+            true => eprintln!("   ✓ Không phát hiện hỏng"),
+            false => {
+                eprintln!("   ⚠ Đã sửa, phần bị mất:");
+                for line in &repaired {
+                    eprintln!("     - {}", line);
                 }
-                let mut out = BufWriter::new(File::create(&out_path)?);
-                std::io::copy(&mut file, &mut out)?;
             }
         }
     }
 
-    // 2) xử lý xl/worksheets/*.xml song song
-    let ws_dir = tmpdir.join("xl/worksheets");
-    if ws_dir.exists() {
-        let sheets: Vec<PathBuf> = fs::read_dir(&ws_dir)?
-            .filter_map(|e| e.ok().map(|e| e.path()))
-            .filter(|p| p.extension().map(|x| x == "xml").unwrap_or(false))
-            .collect();
+    // 2) xử lý các worksheet/macrosheet song song
+    let mut ctx = RangeContext {
+        mode: opts.range_mode,
+        drop_default_cells: opts.drop_default_cells,
+        ..Default::default()
+    };
+    if ods::is_ods(tmpdir) {
+        ods::trim_content(tmpdir, opts)?;
+        return zip_dir(tmpdir, output);
+    }
+    let workbook_xml = rels::workbook_part(tmpdir)?;
+    if xlsb::is_binary_workbook(&workbook_xml) {
+        xlsb::trim_workbook(tmpdir, &workbook_xml, opts)?;
+        return zip_dir(tmpdir, output);
+    }
+    let workbook_rels = rels::read_rels(&workbook_xml)?;
+    // Strict: part/quan hệ thêm mới dùng namespace purl.oclc.org
+    let conformance = Conformance::detect(&workbook_xml)?;
+    let workbook_target = |kind: &str| {
+        workbook_rels
+            .iter()
+            .find(|r| r.is(kind) && !r.external)
+            .and_then(|r| rels::resolve_target(tmpdir, &workbook_xml, &r.target))
+            .filter(|p| p.exists())
+    };
+    if opts.blank_strings_empty {
+        let blank = match workbook_target("sharedStrings") {
+            Some(sst_xml) => sst::load_blank_flags(&sst_xml)
+                .with_context(|| format!("sharedStrings {}", sst_xml.display()))?,
+            None => Vec::new(),
+        };
+        ctx.blank_strings = Some(blank);
+    }
+    if opts.range_mode == RangeMode::Formatting {
+        if let Some(styles_xml) = workbook_target("styles") {
+            ctx.visible_styles = styles::load_visible_xfs(&styles_xml)
+                .with_context(|| format!("styles {}", styles_xml.display()))?;
+        }
+    }
 
-        sheets.par_iter().try_for_each(|sheet_xml| -> Result<()> {
-            let used = find_used_range_sheet(sheet_xml)
-                .with_context(|| format!("find_used_range {}", sheet_xml.display()))?;
-            let tmp_out = sheet_xml.with_extension("xml.out");
-            rewrite_sheet(sheet_xml, &tmp_out, used)
-                .with_context(|| format!("rewrite_sheet {}", sheet_xml.display()))?;
-            fs::rename(&tmp_out, sheet_xml)?;
-            Ok(())
-        })?;
+    let sst_xml = workbook_target("sharedStrings");
+    let mut strings = match opts.share_strings {
+        true => Some(sst::StringTable::load(sst_xml.as_deref())?),
+        false => None,
+    };
+
+    let wb_sheets = workbook::read_sheets(tmpdir, &workbook_xml)?;
+    // worksheet và macrosheet (cùng cấu trúc ô) được cắt theo vùng dữ liệu
+    let mut sheets: Vec<PathBuf> = Vec::new();
+    for s in wb_sheets.iter().filter(|s| s.kind.has_cells() && s.path.is_file()) {
+        if !sheets.contains(&s.path) {
+            sheets.push(s.path.clone());
+        }
     }
+    let macro_sheets: HashSet<&Path> = wb_sheets
+        .iter()
+        .filter(|s| s.kind == SheetKind::Macrosheet)
+        .map(|s| s.path.as_path())
+        .collect();
+    // vùng còn lại của từng sheet sau khi cắt
+    let mut trimmed = HashMap::new();
+    if !sheets.is_empty() {
 
-    // 3) xoá calcChain.xml (Excel tự rebuild)
-    let _ = fs::remove_file(tmpdir.join("xl/calcChain.xml"));
+        let scans = sheets
+            .par_iter()
+            .map(|sheet_xml| {
+                find_used_range_sheet(sheet_xml, &ctx)
+                    .with_context(|| format!("find_used_range {}", sheet_xml.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-    // 4) re-zip
-    {
-        let f = File::create(output)?;
-        let mut zw = ZipWriter::new(BufWriter::new(f));
-        let options: FileOptions<()> = FileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated);
-
-        for entry in WalkDir::new(tmpdir).into_iter().filter_map(|e| e.ok()) {
-            let path = entry.path();
-            if path == tmpdir {
-                continue;
+        // vùng mà công thức (mọi sheet) và tên định nghĩa trỏ tới, theo từng sheet
+        let mut referenced: HashMap<&Path, UsedRange> = HashMap::new();
+        let mut add_ref = |own: Option<&Path>, fr: &formula::FormulaRef| {
+            let target = match &fr.sheet {
+                Some(name) => workbook::find_sheet(&wb_sheets, name).map(|s| s.path.as_path()),
+                None => own,
+            };
+            if let Some(target) = target.and_then(|t| sheets.iter().find(|p| p.as_path() == t)) {
+                referenced
+                    .entry(target.as_path())
+                    .or_default()
+                    .include_range(&formula::bounded(&fr.range));
             }
-            let name = path
-                .strip_prefix(tmpdir)
-                .unwrap()
-                .to_string_lossy()
-                .replace('\\', "/");
-            if path.is_dir() {
-                zw.add_directory(name, options)?;
-            } else {
-                zw.start_file(name, options)?;
-                let mut f = BufReader::new(File::open(path)?);
-                std::io::copy(&mut f, &mut zw)?;
+        };
+        for (sheet_xml, scan) in sheets.iter().zip(&scans) {
+            for fr in &scan.refs {
+                add_ref(Some(sheet_xml), fr);
+            }
+        }
+        // Print_Area, Print_Titles, _FilterDatabase chỉ mô tả vùng in/lọc, không cần giữ ô
+        for d in workbook::read_defined_names(&workbook_xml)?
+            .iter()
+            .filter(|d| !d.name.starts_with("_xlnm."))
+        {
+            for fr in formula::references(&d.formula) {
+                add_ref(None, &fr);
             }
         }
-        zw.finish()?;
+        let used_names: HashSet<String> = scans.iter().flat_map(|s| s.names.iter().cloned()).collect();
+
+        let mut report = sheets
+            .par_iter()
+            .zip(scans.into_par_iter())
+            .map(|(sheet_xml, scan)| -> Result<SheetReport> {
+                let mut used = scan.used;
+
+                // ô được trỏ tới từ công thức/tên định nghĩa nhưng nằm ngoài vùng dữ liệu
+                let mut outside_refs = None;
+                if let Some(refd) = referenced.get(sheet_xml.as_path()) {
+                    if used.is_empty() || refd.last_col > used.last_col || refd.last_row > used.last_row {
+                        if opts.protect_refs {
+                            used.include(refd.first_col, refd.first_row);
+                            used.include(refd.last_col, refd.last_row);
+                        } else {
+                            outside_refs = Some(refd.dimension_ref());
+                        }
+                    }
+                }
+
+                // hình/biểu đồ, comment, hyperlink, bảng: nới vùng để giữ, hoặc xoá nếu nằm ngoài.
+                // Hai cách loại trừ nhau: mặc định vùng được nới nên rewrite_sheet không bỏ
+                // hyperlink/đối tượng nào; với --drop-outside-objects vùng chỉ tính theo ô và
+                // mọi thứ nằm ngoài bị bỏ
+                let drop_outside = (opts.drop_outside_objects && !used.is_empty()).then_some(used);
+                let objs = objects::process_sheet_objects(tmpdir, sheet_xml, drop_outside)
+                    .with_context(|| format!("objects {}", sheet_xml.display()))?;
+                if drop_outside.is_none() {
+                    for rg in scan.hyperlinks.iter().chain(&objs.ranges) {
+                        used.include_range(rg);
+                    }
+                }
+
+                // công thức của macrosheet chính là macro: không đổi thành giá trị
+                let sheet_opts = TrimOptions {
+                    values_only: opts.values_only && !macro_sheets.contains(sheet_xml.as_path()),
+                    ..opts.clone()
+                };
+                let tmp_out = sheet_xml.with_extension("xml.out");
+                let orphan_rels = rewrite_sheet(sheet_xml, &tmp_out, used, &objs.dropped_tables, &sheet_opts)
+                    .with_context(|| format!("rewrite_sheet {}", sheet_xml.display()))?;
+                fs::rename(&tmp_out, sheet_xml)?;
+                rels::remove_rels(sheet_xml, &orphan_rels)?;
+                if opts.collapse_rows {
+                    rows::collapse_default_rows(sheet_xml)
+                        .with_context(|| format!("collapse_rows {}", sheet_xml.display()))?;
+                }
+                if opts.minify {
+                    minify::minify_sheet(sheet_xml)
+                        .with_context(|| format!("minify {}", sheet_xml.display()))?;
+                }
+
+                // table của sheet: cắt ref/autoFilter theo vùng dùng
+                for rel in rels::read_rels(sheet_xml)?.iter().filter(|r| r.is("table") && !r.external) {
+                    let Some(table_xml) = rels::resolve_target(tmpdir, sheet_xml, &rel.target) else {
+                        continue;
+                    };
+                    if table_xml.exists() {
+                        table::rewrite_table(&table_xml, used)
+                            .with_context(|| format!("rewrite_table {}", table_xml.display()))?;
+                    }
+                }
+                Ok(SheetReport {
+                    path: sheet_xml.clone(),
+                    name: sheet_label(sheet_xml),
+                    used,
+                    removed_parts: objs.removed_parts,
+                    outside_refs,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // chuỗi inline -> sharedStrings: chạy tuần tự theo thứ tự sheet để chỉ số của chuỗi
+        // mới không phụ thuộc vào thứ tự các luồng
+        if let Some(table) = strings.as_mut() {
+            for sheet_xml in &sheets {
+                table
+                    .share_inline(sheet_xml)
+                    .with_context(|| format!("share_strings {}", sheet_xml.display()))?;
+            }
+        }
+
+        report.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut removed_parts = Vec::new();
+        for r in report {
+            eprintln!("   • {}: {}", r.name, r.used.dimension_ref());
+            if let Some(refd) = r.outside_refs {
+                eprintln!(
+                    "     ⚠ công thức/tên định nghĩa trỏ tới {} (ngoài vùng), dùng --protect-refs để giữ",
+                    refd
+                );
+            }
+            removed_parts.extend(r.removed_parts);
+            trimmed.insert(r.path, r.used);
+        }
+        content_types::remove_overrides(tmpdir, &removed_parts)?;
+
+        if opts.clean_names {
+            // macro có thể gọi tên theo chuỗi: workbook có VBA thì không xoá tên "không dùng"
+            let has_vba = workbook_rels.iter().any(|r| r.is("vbaProject"));
+            let stats = names::clean_defined_names(tmpdir, &workbook_xml, &wb_sheets, &trimmed, used_names, has_vba)
+                .context("definedNames")?;
+            if stats.removed > 0 || stats.clipped > 0 {
+                eprintln!("   • definedNames: xoá {}, cắt vùng {}", stats.removed, stats.clipped);
+            }
+        }
+    }
+
+    // chartsheet/dialogsheet không có ô: chỉ dọn quan hệ và part không còn dùng
+    let mut orphan_parts = Vec::new();
+    for s in wb_sheets.iter().filter(|s| !s.kind.has_cells() && s.path.is_file()) {
+        let parts = orphans::clean_sheet_parts(tmpdir, &s.path)
+            .with_context(|| format!("clean_sheet_parts {}", s.path.display()))?;
+        if !parts.is_empty() {
+            eprintln!("   • {}: xoá {} part không dùng", sheet_label(&s.path), parts.len());
+        }
+        orphan_parts.extend(parts);
+    }
+    content_types::remove_overrides(tmpdir, &orphan_parts)?;
+
+    // chuỗi inline đã chuyển: ghi vào sharedStrings.xml (tạo mới và khai báo nếu chưa có)
+    if let Some(table) = &strings {
+        let (added, cells) = table.stats();
+        if cells > 0 {
+            let path = match &sst_xml {
+                Some(p) => p.clone(),
+                None => {
+                    let p = workbook_xml.with_file_name("sharedStrings.xml");
+                    rels::add_rel(&workbook_xml, &conformance.rel_type("sharedStrings"), "sharedStrings.xml")?;
+                    content_types::add_override(tmpdir, &p, content_types::SHARED_STRINGS)?;
+                    p
+                }
+            };
+            table.write(&path, conformance.main_ns()).with_context(|| format!("sharedStrings {}", path.display()))?;
+            eprintln!("   • sharedStrings: {} ô inline, thêm {} chuỗi", cells, added);
+        }
     }
 
+    // 3) calcChain: mặc định xoá (Excel tự rebuild); --keep-calc-chain thì chỉ bỏ các ô không còn
+    if let Some(calc_xml) = workbook_target("calcChain") {
+        let keep = opts.keep_calc_chain
+            && !opts.values_only
+            && calc::prune_calc_chain(&calc_xml, &wb_sheets, &trimmed)
+                .with_context(|| format!("calcChain {}", calc_xml.display()))?
+                > 0;
+        if !keep {
+            fs::remove_file(&calc_xml)?;
+            let ids: HashSet<String> = workbook_rels
+                .iter()
+                .filter(|r| r.is("calcChain"))
+                .map(|r| r.id.clone())
+                .collect();
+            rels::remove_rels(&workbook_xml, &ids)?;
+            content_types::remove_overrides(tmpdir, &[calc_xml])?;
+            if opts.full_calc_on_load {
+                calc::set_full_calc_on_load(&workbook_xml)?;
+            }
+        }
+    }
+
+    // không còn công thức: bỏ liên kết tới workbook ngoài
+    if opts.values_only {
+        let links: Vec<_> = workbook_rels.iter().filter(|r| r.is("externalLink") && !r.external).collect();
+        if !links.is_empty() {
+            let mut removed = Vec::new();
+            for rel in &links {
+                let Some(part) = rels::resolve_target(tmpdir, &workbook_xml, &rel.target) else {
+                    continue;
+                };
+                let _ = fs::remove_file(rels::rels_path_for(&part));
+                if part.exists() {
+                    fs::remove_file(&part)?;
+                }
+                removed.push(part);
+            }
+            // thư mục xl/externalLinks (và _rels của nó) giờ đã rỗng
+            if let Some(dir) = removed.first().and_then(|p| p.parent()) {
+                let _ = fs::remove_dir(dir.join("_rels"));
+                let _ = fs::remove_dir(dir);
+            }
+            let ids: HashSet<String> = links.iter().map(|r| r.id.clone()).collect();
+            rels::remove_rels(&workbook_xml, &ids)?;
+            content_types::remove_overrides(tmpdir, &removed)?;
+            workbook::remove_external_references(&workbook_xml)?;
+            names::remove_external_names(&workbook_xml)?;
+            eprintln!("   • xoá {} liên kết ngoài", links.len());
+        }
+    }
+
+    // 4) re-zip
+    zip_dir(tmpdir, output)
+}
+
+/// Nén thư mục đã giải nén thành file đầu ra
+fn zip_dir(tmpdir: &Path, output: &Path) -> Result<()> {
+    let f = File::create(output)?;
+    let mut zw = ZipWriter::new(BufWriter::new(f));
+    let options: FileOptions<()> = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    // gói OpenDocument: `mimetype` phải là entry đầu tiên và không nén
+    let mimetype = tmpdir.join("mimetype");
+    if mimetype.is_file() {
+        let stored: FileOptions<()> =
+            FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zw.start_file("mimetype", stored)?;
+        zw.write_all(&fs::read(&mimetype)?)?;
+    }
+
+    for entry in WalkDir::new(tmpdir).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path == tmpdir || path == mimetype {
+            continue;
+        }
+        let name = path
+            .strip_prefix(tmpdir)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        if path.is_dir() {
+            zw.add_directory(name, options)?;
+        } else {
+            zw.start_file(name, options)?;
+            let mut f = BufReader::new(File::open(path)?);
+            std::io::copy(&mut f, &mut zw)?;
+        }
+    }
+    zw.finish()?;
     Ok(())
 }
 
-fn process_path(input: &Path, out_dir: Option<&Path>, threshold_mb: u64, suffix: &str) -> Result<()> {
+/// Các định dạng bảng tính dạng zip: Excel (workbook, có macro, template, add-in, nhị phân)
+/// và OpenDocument
+const SPREADSHEET_EXTENSIONS: &[&str] = &["xlsx", "xlsm", "xltx", "xltm", "xlam", "xlsb", "ods", "ots"];
+
+fn is_spreadsheet(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|e| SPREADSHEET_EXTENSIONS.contains(&e.as_str()))
+}
+
+fn process_path(
+    input: &Path,
+    out_dir: Option<&Path>,
+    threshold_mb: u64,
+    suffix: &str,
+    opts: &TrimOptions,
+    password: Option<&str>,
+) -> Result<()> {
     let mut files: Vec<PathBuf> = Vec::new();
-    if input.is_file() && input.extension().map(|e| e == "xlsx").unwrap_or(false) {
+    if input.is_file() && is_spreadsheet(input) {
         files.push(input.to_path_buf());
     } else if input.is_dir() {
         for e in WalkDir::new(input).into_iter().filter_map(|e| e.ok()) {
             let p = e.path();
-            if p.is_file() && p.extension().map(|e| e == "xlsx").unwrap_or(false) {
+            if p.is_file() && is_spreadsheet(p) {
                 files.push(p.to_path_buf());
             }
         }
@@ -527,6 +1629,8 @@ fn process_path(input: &Path, out_dir: Option<&Path>, threshold_mb: u64, suffix:
         fs::create_dir_all(od)?;
     }
 
+    // file bị bỏ qua vì định dạng, theo loại (chỉ khi xử lý cả thư mục)
+    let mut skipped: Vec<(&str, usize)> = Vec::new();
     for p in files {
         let sz = fs::metadata(&p)?.len();
         let sz_mb = sz / (1024 * 1024);
@@ -534,26 +1638,45 @@ fn process_path(input: &Path, out_dir: Option<&Path>, threshold_mb: u64, suffix:
             eprintln!("Bỏ qua {} ({} MB <= {} MB)", p.display(), sz_mb, threshold_mb);
             continue;
         }
-        let out = if let Some(od) = out_dir {
-            od.join(format!(
-                "{}{}{}",
-                p.file_stem().unwrap().to_string_lossy(),
-                suffix,
-                ".xlsx"
-            ))
-        } else {
-            p.with_file_name(format!(
-                "{}{}{}",
-                p.file_stem().unwrap().to_string_lossy(),
-                suffix,
-                ".xlsx"
-            ))
+        let encrypted = match sniff::check_signature(&p) {
+            Ok(()) => false,
+            Err(sniff::FormatError::Encrypted) if password.is_some() => true,
+            Err(e) => {
+                if !input.is_dir() {
+                    return Err(anyhow::Error::new(e).context(p.display().to_string()));
+                }
+                eprintln!("⚠ Bỏ qua {}: {}", p.display(), e);
+                match skipped.iter_mut().find(|(label, _)| *label == e.label()) {
+                    Some((_, n)) => *n += 1,
+                    None => skipped.push((e.label(), 1)),
+                }
+                continue;
+            }
+        };
+        // giữ nguyên phần mở rộng: đổi .xlsm thành .xlsx thì Excel từ chối mở file có macro
+        let file_name = format!(
+            "{}{}.{}",
+            p.file_stem().unwrap().to_string_lossy(),
+            suffix,
+            p.extension().unwrap().to_string_lossy()
+        );
+        let out = match out_dir {
+            Some(od) => od.join(file_name),
+            None => p.with_file_name(file_name),
         };
         eprintln!("▶ Xử lý: {} ({} MB) → {}", p.display(), sz_mb, out.display());
-        trim_one_xlsx(&p, &out)?;
+        match password.filter(|_| encrypted) {
+            Some(pw) => trim_encrypted(&p, &out, opts, pw)
+                .with_context(|| p.display().to_string())?,
+            None => trim_one_xlsx(&p, &out, opts)?,
+        }
         let new_sz = fs::metadata(&out)?.len() / (1024 * 1024);
         eprintln!("   ✓ Mới: {} MB (giảm {} MB)", new_sz, (sz_mb as i64 - new_sz as i64));
     }
+    if !skipped.is_empty() {
+        let parts: Vec<String> = skipped.iter().map(|(label, n)| format!("{} {}", n, label)).collect();
+        eprintln!("Bỏ qua vì định dạng: {}", parts.join(", "));
+    }
 
     Ok(())
 }
@@ -565,7 +1688,12 @@ fn main() -> Result<()> {
         eprintln!(
             "Cách dùng:
   xlsx-trimmer <đường-dẫn-file-hoặc-thư-mục>
-    [-o <output-dir>] [--threshold-mb 10] [--suffix _trimmed]"
+    [-o <output-dir>] [--threshold-mb 10] [--suffix _trimmed]
+    [--ignore-blank-strings] [--range-mode values|formatting|any]
+    [--drop-outside-objects] [--protect-refs] [--clean-names]
+    [--full-calc-on-load] [--keep-calc-chain] [--values-only]
+    [--share-strings] [--minify] [--collapse-rows] [--drop-default-cells] [--repair]
+    [--password <mật-khẩu> | --password-file <file>]"
         );
         std::process::exit(1);
     }
@@ -573,6 +1701,8 @@ fn main() -> Result<()> {
     let mut out_dir = None::<PathBuf>;
     let mut threshold: u64 = 10;
     let mut suffix = String::from("_trimmed");
+    let mut opts = TrimOptions::default();
+    let mut password = None::<String>;
 
     let mut i = 1;
     while i < args.len() {
@@ -601,6 +1731,42 @@ fn main() -> Result<()> {
                 }
                 suffix = args[i].clone();
             }
+            "--password" => {
+                i += 1;
+                if i >= args.len() {
+                    anyhow::bail!("Thiếu giá trị cho tham số '{}'", arg);
+                }
+                password = Some(args[i].clone());
+            }
+            "--password-file" => {
+                i += 1;
+                if i >= args.len() {
+                    anyhow::bail!("Thiếu giá trị cho tham số '{}'", arg);
+                }
+                let text = fs::read_to_string(&args[i])
+                    .with_context(|| format!("Không đọc được file mật khẩu '{}'", args[i]))?;
+                // chỉ bỏ xuống dòng cuối file, khoảng trắng có thể là một phần của mật khẩu
+                password = Some(text.trim_end_matches(['\r', '\n']).to_string());
+            }
+            "--ignore-blank-strings" => opts.blank_strings_empty = true,
+            "--drop-outside-objects" => opts.drop_outside_objects = true,
+            "--protect-refs" => opts.protect_refs = true,
+            "--clean-names" => opts.clean_names = true,
+            "--full-calc-on-load" => opts.full_calc_on_load = true,
+            "--keep-calc-chain" => opts.keep_calc_chain = true,
+            "--values-only" => opts.values_only = true,
+            "--share-strings" => opts.share_strings = true,
+            "--minify" => opts.minify = true,
+            "--collapse-rows" => opts.collapse_rows = true,
+            "--drop-default-cells" => opts.drop_default_cells = true,
+            "--repair" => opts.repair = true,
+            "--range-mode" => {
+                i += 1;
+                if i >= args.len() {
+                    anyhow::bail!("Thiếu giá trị cho tham số '{}'", arg);
+                }
+                opts.range_mode = args[i].parse()?;
+            }
             _ if input.is_none() && !arg.starts_with('-') => {
                 input = Some(PathBuf::from(arg));
             }
@@ -612,9 +1778,677 @@ fn main() -> Result<()> {
     }
 
     if let Some(input_path) = input {
-        process_path(&input_path, out_dir.as_deref(), threshold, &suffix)?;
+        process_path(
+            &input_path,
+            out_dir.as_deref(),
+            threshold,
+            &suffix,
+            &opts,
+            password.as_deref(),
+        )?;
     } else {
         anyhow::bail!("Thiếu đường dẫn file hoặc thư mục đầu vào.");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    const HEAD: &str =
+        r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#;
+
+    /// -> (sheet đã ghi lại, r:id của các quan hệ không còn dùng)
+    fn rewrite_with(body: &str, used: UsedRange, opts: &TrimOptions) -> (String, HashSet<String>) {
+        let dir = tempfile::tempdir().unwrap();
+        let (xml_in, xml_out) = (dir.path().join("in.xml"), dir.path().join("out.xml"));
+        fs::write(&xml_in, format!("{}{}</worksheet>", HEAD, body)).unwrap();
+        let orphans = rewrite_sheet(&xml_in, &xml_out, used, &HashSet::new(), opts).unwrap();
+        (fs::read_to_string(&xml_out).unwrap(), orphans)
+    }
+
+    fn rewrite_with_rels(body: &str, used: UsedRange) -> (String, HashSet<String>) {
+        rewrite_with(body, used, &TrimOptions::default())
+    }
+
+    fn rewrite(body: &str, used: UsedRange) -> String {
+        rewrite_with_rels(body, used).0
+    }
+
+    /// vùng A1:(last_col, last_row)
+    fn used(last_col: u32, last_row: u32) -> UsedRange {
+        UsedRange {
+            first_row: 1,
+            first_col: 1,
+            last_row,
+            last_col,
+        }
+    }
+
+    fn scan_with(body: &str, ctx: &RangeContext) -> UsedRange {
+        let dir = tempfile::tempdir().unwrap();
+        let xml = dir.path().join("sheet1.xml");
+        fs::write(&xml, format!("{}{}</worksheet>", HEAD, body)).unwrap();
+        find_used_range_sheet(&xml, ctx).unwrap().used
+    }
+
+    fn scan(body: &str) -> UsedRange {
+        scan_with(body, &RangeContext::default())
+    }
+
+    #[test]
+    fn auto_filter_is_clipped_with_its_columns() {
+        let body = concat!(
+            r#"<sheetData><row r="1"><c r="A1"><v>1</v></c></row></sheetData>"#,
+            r#"<autoFilter ref="A1:F500"><filterColumn colId="1"/><filterColumn colId="5"/>"#,
+            r#"<sortState ref="A2:F500"><sortCondition ref="E2:E500"/><sortCondition ref="B2:B500"/></sortState>"#,
+            r#"</autoFilter>"#
+        );
+        let out = rewrite(body, used(3, 10));
+        assert!(out.contains(r#"<autoFilter ref="A1:C10">"#), "{}", out);
+        assert!(
+            out.contains(r#"colId="1""#) && !out.contains(r#"colId="5""#),
+            "{}",
+            out
+        );
+        assert!(out.contains(r#"<sortState ref="A2:C10">"#), "{}", out);
+        assert!(
+            out.contains(r#"<sortCondition ref="B2:B10"/>"#) && !out.contains("E2"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn hyperlinks_outside_used_range_are_dropped_with_their_rels() {
+        let body = concat!(
+            r#"<sheetData><row r="1"><c r="A1"><v>1</v></c></row></sheetData>"#,
+            r#"<hyperlinks><hyperlink ref="A1" r:id="rId1"/><hyperlink ref="B2:Z2" r:id="rId2"/>"#,
+            r#"<hyperlink ref="X50" r:id="rId3"/><hyperlink ref="Y60" r:id="rId1"/>"#,
+            r#"<hyperlink ref="C1" location="'Sheet 2'!A1"/></hyperlinks>"#
+        );
+        let (out, orphans) = rewrite_with_rels(body, used(3, 5));
+        assert!(
+            out.contains(r#"<hyperlink ref="A1" r:id="rId1"/>"#),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(r#"<hyperlink ref="B2:C2" r:id="rId2"/>"#),
+            "{}",
+            out
+        );
+        assert!(out.contains("location=") && !out.contains("X50"), "{}", out);
+        // rId1 vẫn được link A1 dùng
+        assert_eq!(orphans, HashSet::from(["rId3".to_string()]));
+
+        let (out, orphans) = rewrite_with_rels(body, used(0, 0));
+        assert!(out.contains("X50") && orphans.is_empty(), "{}", out);
+    }
+
+    #[test]
+    fn hyperlinks_block_disappears_when_nothing_is_kept() {
+        let body = r#"<sheetData/><hyperlinks><hyperlink ref="K9" r:id="rId7"/></hyperlinks><pageMargins/>"#;
+        let (out, orphans) = rewrite_with_rels(body, used(2, 2));
+        assert!(
+            !out.contains("hyperlink") && out.contains("<pageMargins/>"),
+            "{}",
+            out
+        );
+        assert_eq!(orphans, HashSet::from(["rId7".to_string()]));
+    }
+
+    #[test]
+    fn used_range_tracks_top_left_corner() {
+        let body = concat!(
+            r#"<dimension ref="A1:Z900"/><sheetData>"#,
+            r#"<row r="5"><c r="D5"><v>1</v></c><c r="H5"><v>2</v></c></row>"#,
+            r#"<row r="9"><c r="C9" s="3"/><c r="E9"><v>3</v></c></row>"#,
+            r#"<row r="200"><c r="F200"><v>4</v></c></row></sheetData>"#
+        );
+        let u = scan(body);
+        assert_eq!(
+            (u.first_col, u.first_row, u.last_col, u.last_row),
+            (4, 5, 8, 200)
+        );
+        assert_eq!(u.dimension_ref(), "D5:H200");
+        let out = rewrite(body, u);
+        assert!(out.contains(r#"<dimension ref="D5:H200"/>"#), "{}", out);
+    }
+
+    #[test]
+    fn dimension_ref_of_single_cell_and_empty_sheet() {
+        let u = scan(r#"<sheetData><row r="7"><c r="C7"><v>1</v></c></row></sheetData>"#);
+        assert_eq!(u.dimension_ref(), "C7");
+        let u = scan(r#"<sheetData><row r="7"><c r="C7" s="1"/></row></sheetData>"#);
+        assert!(u.is_empty());
+        assert_eq!(u.dimension_ref(), "A1");
+    }
+
+    #[test]
+    fn rows_and_cells_without_r_are_positioned_by_order() {
+        // hàng 1, 2 (thiếu r), hàng 6, rồi hàng 7 (thiếu r); ô thiếu r nối tiếp ô trước
+        let body = concat!(
+            r#"<sheetData><row><c><v>1</v></c><c><v>2</v></c></row>"#,
+            r#"<row><c r="C2"><v>3</v></c><c><v>4</v></c><c><v>5</v></c></row>"#,
+            r#"<row r="6"><c r="B6"><v>6</v></c></row>"#,
+            r#"<row><c><v>7</v></c></row></sheetData>"#
+        );
+        let u = scan(body);
+        assert_eq!(u.dimension_ref(), "A1:E7");
+
+        // cắt còn A1:D6: r suy ra được ghi vào để vị trí không lệch
+        let out = rewrite(body, used(4, 6));
+        assert!(
+            out.contains(r#"<row r="1"><c r="A1"><v>1</v></c><c r="B1">"#),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(r#"<row r="2"><c r="C2"><v>3</v></c><c r="D2"><v>4</v></c></row>"#),
+            "{}",
+            out
+        );
+        assert!(
+            !out.contains("<v>5</v>") && !out.contains("<v>7</v>"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn blank_strings_count_as_empty_only_when_enabled() {
+        // chuỗi chia sẻ 0 = "x", 1 = "  "; inline chỉ có khoảng trắng; công thức luôn tính
+        let body = concat!(
+            r#"<sheetData><row r="1"><c r="A1" t="s"><v>0</v></c></row>"#,
+            r#"<row r="3"><c r="C3" t="s"><v>1</v></c></row>"#,
+            r#"<row r="4"><c r="D4" t="inlineStr"><is><t> </t></is></c></row>"#,
+            r#"<row r="5"><c r="B5" t="str"><f>""</f><v></v></c></row></sheetData>"#
+        );
+        assert_eq!(scan(body).dimension_ref(), "A1:D5");
+        let ctx = RangeContext {
+            blank_strings: Some(vec![false, true]),
+            ..Default::default()
+        };
+        assert_eq!(scan_with(body, &ctx).dimension_ref(), "A1:B5");
+    }
+
+    #[test]
+    fn range_mode_decides_which_empty_cells_count() {
+        // style 1 nhìn thấy được (fill/viền), style 2 thì không
+        let body = concat!(
+            r#"<sheetData><row r="2"><c r="B2"><v>1</v></c></row>"#,
+            r#"<row r="4"><c r="D4" s="1"/><c r="F4" s="2"/></row>"#,
+            r#"<row r="9"><c r="A9"/></row></sheetData>"#
+        );
+        let ctx = |mode| RangeContext {
+            mode,
+            visible_styles: vec![false, true, false],
+            ..Default::default()
+        };
+        assert_eq!(
+            scan_with(body, &ctx(RangeMode::Values)).dimension_ref(),
+            "B2"
+        );
+        assert_eq!(
+            scan_with(body, &ctx(RangeMode::Formatting)).dimension_ref(),
+            "B2:D4"
+        );
+        assert_eq!(
+            scan_with(body, &ctx(RangeMode::AnyCell)).dimension_ref(),
+            "A2:F9"
+        );
+        assert!("cells".parse::<RangeMode>().is_err());
+    }
+
+    #[test]
+    fn rules_are_clipped_to_used_range() {
+        let body = concat!(
+            r#"<sheetData><row r="1"><c r="A1"><v>1</v></c></row></sheetData>"#,
+            r#"<conditionalFormatting sqref="A1:A50 Z1:Z9"><cfRule type="expression" priority="1"><formula>A1&gt;0</formula></cfRule></conditionalFormatting>"#,
+            r#"<conditionalFormatting sqref="K20"><cfRule type="expression" priority="2"><formula>K20</formula></cfRule></conditionalFormatting>"#,
+            r#"<conditionalFormatting sqref="Z1 B2:B40"><cfRule type="expression" priority="3"><formula>Z1</formula></cfRule></conditionalFormatting>"#,
+            r#"<dataValidations count="3"><dataValidation type="list" sqref="A1:A100"><formula1>"a,b"</formula1></dataValidation>"#,
+            r#"<dataValidation type="whole" sqref="M5"/><dataValidation type="whole" sqref="B2"/></dataValidations>"#
+        );
+        let out = rewrite(body, used(3, 10));
+        assert!(
+            out.contains(r#"<conditionalFormatting sqref="A1:A10">"#),
+            "{}",
+            out
+        );
+        assert!(!out.contains("K20"), "{}", out);
+        // ô đầu của vùng đầu tiên nằm ngoài: công thức tương đối phụ thuộc nó nên giữ nguyên
+        assert!(
+            out.contains(r#"<conditionalFormatting sqref="Z1 B2:B40">"#),
+            "{}",
+            out
+        );
+        assert!(out.contains(r#"<dataValidations count="2">"#), "{}", out);
+        assert!(
+            out.contains(r#"sqref="A1:A10"><formula1>"#) && !out.contains("M5"),
+            "{}",
+            out
+        );
+
+        let body = r#"<sheetData/><dataValidations count="1"><dataValidation sqref="X9"/></dataValidations><pageMargins/>"#;
+        let out = rewrite(body, used(2, 2));
+        assert!(!out.contains("dataValidation"), "{}", out);
+    }
+
+    #[test]
+    fn dropped_tables_leave_table_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let (xml_in, xml_out) = (dir.path().join("in.xml"), dir.path().join("out.xml"));
+        let body = r#"<sheetData/><tableParts count="2"><tablePart r:id="rId1"/><tablePart r:id="rId2"/></tableParts>"#;
+        fs::write(&xml_in, format!("{}{}</worksheet>", HEAD, body)).unwrap();
+        let drop = HashSet::from(["rId2".to_string()]);
+        let orphans = rewrite_sheet(
+            &xml_in,
+            &xml_out,
+            used(2, 2),
+            &drop,
+            &TrimOptions::default(),
+        )
+        .unwrap();
+        let out = fs::read_to_string(&xml_out).unwrap();
+        assert!(
+            out.contains(r#"<tableParts count="1"><tablePart r:id="rId1"/></tableParts>"#),
+            "{}",
+            out
+        );
+        assert_eq!(orphans, drop);
+    }
+
+    fn write_xlsx(path: &Path, entries: &[(&str, &str)]) {
+        let mut zw = ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in entries {
+            zw.start_file(*name, FileOptions::<()>::default()).unwrap();
+            zw.write_all(data.as_bytes()).unwrap();
+        }
+        zw.finish().unwrap();
+    }
+
+    fn read_entry(path: &Path, name: &str) -> Option<String> {
+        let mut zin = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut f = zin.by_name(name).ok()?;
+        let mut s = String::new();
+        f.read_to_string(&mut s).unwrap();
+        Some(s)
+    }
+
+    const REL: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+    /// quan hệ của WORKBOOK: một worksheet xl/worksheets/sheet1.xml
+    fn workbook_rels() -> String {
+        format!(
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="{0}/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#
+            ),
+            REL
+        )
+    }
+
+    const WORKBOOK: &str =
+        r#"<workbook><sheets><sheet name="Data" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+    #[test]
+    fn objects_extend_range_unless_dropped() {
+        let sheet = format!(
+            concat!(
+                r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="{0}">"#,
+                r#"<dimension ref="A1:Z999"/><sheetData><row r="1"><c r="A1"><v>1</v></c></row>"#,
+                r#"<row r="2"><c r="B2"><v>2</v></c></row></sheetData>"#,
+                r#"<hyperlinks><hyperlink ref="H40" r:id="rId1"/></hyperlinks><drawing r:id="rId2"/></worksheet>"#
+            ),
+            REL
+        );
+        let sheet_rels = format!(
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="{0}/hyperlink" Target="https://example.com" TargetMode="External"/>"#,
+                r#"<Relationship Id="rId2" Type="{0}/drawing" Target="../drawings/drawing1.xml"/></Relationships>"#
+            ),
+            REL
+        );
+        let drawing = format!(
+            concat!(
+                r#"<xdr:wsDr xmlns:xdr="http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing" xmlns:r="{0}">"#,
+                r#"<xdr:twoCellAnchor><xdr:from><xdr:col>4</xdr:col><xdr:row>9</xdr:row></xdr:from>"#,
+                r#"<xdr:to><xdr:col>6</xdr:col><xdr:row>19</xdr:row></xdr:to><xdr:graphicFrame><c:chart r:id="rId1"/></xdr:graphicFrame>"#,
+                r#"<xdr:clientData/></xdr:twoCellAnchor></xdr:wsDr>"#
+            ),
+            REL
+        );
+        let drawing_rels = format!(
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="{0}/chart" Target="../charts/chart1.xml"/></Relationships>"#
+            ),
+            REL
+        );
+        let workbook_rels = workbook_rels();
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.xlsx");
+        write_xlsx(
+            &input,
+            &[
+                ("xl/workbook.xml", WORKBOOK),
+                ("xl/_rels/workbook.xml.rels", &workbook_rels),
+                ("xl/worksheets/sheet1.xml", &sheet),
+                ("xl/worksheets/_rels/sheet1.xml.rels", &sheet_rels),
+                ("xl/drawings/drawing1.xml", &drawing),
+                ("xl/drawings/_rels/drawing1.xml.rels", &drawing_rels),
+                ("xl/charts/chart1.xml", "<chartSpace/>"),
+            ],
+        );
+
+        // mặc định: vùng được nới tới hyperlink H40 và hình E10:G20, không bỏ gì
+        let output = dir.path().join("keep.xlsx");
+        trim_one_xlsx(&input, &output, &TrimOptions::default()).unwrap();
+        let out = read_entry(&output, "xl/worksheets/sheet1.xml").unwrap();
+        assert!(
+            out.contains(r#"<dimension ref="A1:H40"/>"#) && out.contains("H40"),
+            "{}",
+            out
+        );
+        assert!(read_entry(&output, "xl/charts/chart1.xml").is_some());
+
+        // --drop-outside-objects: vùng chỉ tính theo ô, mọi thứ ngoài A1:B2 bị bỏ
+        let output = dir.path().join("drop.xlsx");
+        let opts = TrimOptions {
+            drop_outside_objects: true,
+            ..Default::default()
+        };
+        trim_one_xlsx(&input, &output, &opts).unwrap();
+        let out = read_entry(&output, "xl/worksheets/sheet1.xml").unwrap();
+        assert!(
+            out.contains(r#"<dimension ref="A1:B2"/>"#) && !out.contains("hyperlink"),
+            "{}",
+            out
+        );
+        let rels = read_entry(&output, "xl/worksheets/_rels/sheet1.xml.rels").unwrap();
+        assert!(!rels.contains("rId1") && rels.contains("rId2"), "{}", rels);
+        let drawing = read_entry(&output, "xl/drawings/drawing1.xml").unwrap();
+        assert!(!drawing.contains("twoCellAnchor"), "{}", drawing);
+        assert!(read_entry(&output, "xl/charts/chart1.xml").is_none());
+    }
+
+    #[test]
+    fn values_only_keeps_cached_values() {
+        let body = concat!(
+            r#"<sheetData><row r="1"><c r="A1" cm="1"><f t="array" ref="A1">SUM(B1:B2)</f><v>3</v></c>"#,
+            r#"<c r="B1"><f t="shared" si="0"/><v>1</v></c><c r="C1" t="str"><f>"a"&amp;"b"</f><v>a&amp;b</v></c>"#,
+            r#"</row></sheetData>"#
+        );
+        let opts = TrimOptions {
+            values_only: true,
+            ..Default::default()
+        };
+        let (out, _) = rewrite_with(body, used(3, 1), &opts);
+        assert!(
+            out.contains(concat!(
+                r#"<c r="A1"><v>3</v></c><c r="B1"><v>1</v></c>"#,
+                r#"<c r="C1" t="str"><v>a&amp;b</v></c>"#
+            )),
+            "{}",
+            out
+        );
+        // không bật: công thức (kể cả tham chiếu thực thể) giữ nguyên
+        let out = rewrite(body, used(3, 1));
+        assert!(
+            out.contains(r#"<c r="A1" cm="1"><f t="array" ref="A1">"#)
+                && out.contains(r#"<f>"a"&amp;"b"</f>"#),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn row_spans_follow_kept_cells() {
+        let body = concat!(
+            r#"<sheetData><row r="1" spans="1:10"><c r="B1"><v>1</v></c><c r="H1"><v>2</v></c></row>"#,
+            r#"<row r="2" spans="5:9" ht="20"><c r="H2"><v>3</v></c></row>"#,
+            r#"<row r="3"><c r="C3"><v>4</v></c></row></sheetData>"#
+        );
+        let out = rewrite(body, used(3, 3));
+        assert!(
+            out.contains(r#"<row r="1" spans="2:2"><c r="B1">"#),
+            "{}",
+            out
+        );
+        assert!(out.contains(r#"<row r="2" ht="20"></row>"#), "{}", out);
+        assert!(out.contains(r#"<row r="3"><c r="C3">"#), "{}", out);
+    }
+
+    #[test]
+    fn default_cells_do_not_count_and_are_dropped() {
+        // cột C mang style 2; D4 là <c> rỗng có thẻ đóng, cùng style mặc định 0
+        let body = concat!(
+            r#"<dimension ref="A1:Z99"/><cols><col min="3" max="3" width="9" style="2"/></cols>"#,
+            r#"<sheetData><row r="1"><c r="A1"><v>1</v></c></row>"#,
+            r#"<row r="4"><c r="B4" s="2"/><c r="C4" s="2"/><c r="D4"> </c></row></sheetData>"#
+        );
+        let ctx = |drop| RangeContext {
+            mode: RangeMode::AnyCell,
+            drop_default_cells: drop,
+            ..Default::default()
+        };
+        assert_eq!(scan_with(body, &ctx(false)).dimension_ref(), "A1:D4");
+        let u = scan_with(body, &ctx(true));
+        assert_eq!(u.dimension_ref(), "A1:B4");
+
+        let opts = TrimOptions {
+            drop_default_cells: true,
+            ..Default::default()
+        };
+        let (out, _) = rewrite_with(body, used(4, 4), &opts);
+        assert!(
+            out.contains(r#"<row r="4"><c r="B4" s="2"/></row>"#),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn uniform_blank_row_style_moves_to_the_row() {
+        let opts = TrimOptions {
+            drop_default_cells: true,
+            ..Default::default()
+        };
+        // mọi cột A..XFD hoặc có ô style 7, hoặc có style cột 7
+        let body = concat!(
+            r#"<cols><col min="3" max="16384" style="7"/></cols><sheetData>"#,
+            r#"<row r="6"><c r="A6" s="7"/><c r="B6" s="7"/></row></sheetData>"#
+        );
+        let (out, _) = rewrite_with(body, used(2, 6), &opts);
+        assert!(
+            out.contains(r#"<row r="6" s="7" customFormat="1"></row>"#),
+            "{}",
+            out
+        );
+
+        // cột D không có style 7: đưa style lên hàng sẽ tô cả D, nên giữ các ô
+        let body = body.replace(r#"min="3""#, r#"min="5""#);
+        let (out, _) = rewrite_with(&body, used(2, 6), &opts);
+        assert!(
+            out.contains(r#"<row r="6"><c r="A6" s="7"/><c r="B6" s="7"/></row>"#),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn macrosheet_formulas_survive_values_only() {
+        let workbook = concat!(
+            r#"<workbook><sheets><sheet name="Data" sheetId="1" r:id="rId1"/>"#,
+            r#"<sheet name="Macro1" sheetId="2" r:id="rId2"/></sheets></workbook>"#
+        );
+        let workbook_rels = format!(
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="{0}/worksheet" Target="worksheets/sheet1.xml"/>"#,
+                r#"<Relationship Id="rId2" Type="http://schemas.microsoft.com/office/2006/relationships/xlMacrosheet" "#,
+                r#"Target="macrosheets/sheet1.xml"/></Relationships>"#
+            ),
+            REL
+        );
+        let cells = concat!(
+            r#"<dimension ref="A1:K50"/><sheetData><row r="1"><c r="A1"><f>RETURN(1)</f><v>1</v></c></row>"#,
+            r#"<row r="50"><c r="K50" s="3"/></row></sheetData>"#
+        );
+        let sheet = format!("{}{}</worksheet>", HEAD, cells);
+        let macro_sheet = format!(
+            r#"<xm:macrosheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:xm="http://schemas.microsoft.com/office/excel/2006/main">{}</xm:macrosheet>"#,
+            cells
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.xlsm");
+        write_xlsx(
+            &input,
+            &[
+                ("xl/workbook.xml", workbook),
+                ("xl/_rels/workbook.xml.rels", &workbook_rels),
+                ("xl/worksheets/sheet1.xml", &sheet),
+                ("xl/macrosheets/sheet1.xml", &macro_sheet),
+            ],
+        );
+        let output = dir.path().join("out.xlsm");
+        let opts = TrimOptions {
+            values_only: true,
+            ..Default::default()
+        };
+        trim_one_xlsx(&input, &output, &opts).unwrap();
+
+        let out = read_entry(&output, "xl/worksheets/sheet1.xml").unwrap();
+        assert!(
+            out.contains(r#"<dimension ref="A1"/>"#) && !out.contains("<f>"),
+            "{}",
+            out
+        );
+        // macrosheet cũng được cắt, nhưng công thức (macro) giữ nguyên
+        let out = read_entry(&output, "xl/macrosheets/sheet1.xml").unwrap();
+        assert!(
+            out.contains(r#"<dimension ref="A1"/>"#) && out.contains("<f>RETURN(1)</f>"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn spreadsheet_extensions_are_kept_in_output_names() {
+        assert!(is_spreadsheet(Path::new("a/Book.XLSM")));
+        assert!(is_spreadsheet(Path::new("Tpl.xltx")) && is_spreadsheet(Path::new("x.xlam")));
+        assert!(!is_spreadsheet(Path::new("old.xls")) && !is_spreadsheet(Path::new("xlsx")));
+
+        let dir = tempfile::tempdir().unwrap();
+        let (src, out) = (dir.path().join("in"), dir.path().join("out"));
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("notes.txt"), "x").unwrap();
+        let workbook_rels = workbook_rels();
+        let sheet = format!("{}<sheetData/></worksheet>", HEAD);
+        write_xlsx(
+            &src.join("Book.XLSM"),
+            &[
+                ("xl/workbook.xml", WORKBOOK),
+                ("xl/_rels/workbook.xml.rels", &workbook_rels),
+                ("xl/worksheets/sheet1.xml", &sheet),
+                ("xl/vbaProject.bin", "VBA"),
+            ],
+        );
+
+        process_path(&src, Some(&out), 0, "_t", &TrimOptions::default(), None).unwrap();
+        let names: Vec<_> = fs::read_dir(&out)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["Book_t.XLSM"]);
+        assert_eq!(
+            read_entry(&out.join("Book_t.XLSM"), "xl/vbaProject.bin").as_deref(),
+            Some("VBA")
+        );
+    }
+
+    #[test]
+    fn strict_workbook_gets_strict_shared_strings() {
+        let strict_rel = "http://purl.oclc.org/ooxml/officeDocument/relationships";
+        let workbook = format!(
+            concat!(
+                r#"<workbook xmlns="http://purl.oclc.org/ooxml/spreadsheetml/main" xmlns:r="{0}" conformance="strict">"#,
+                r#"<sheets><sheet name="Data" sheetId="1" r:id="rId1"/></sheets></workbook>"#
+            ),
+            strict_rel
+        );
+        let workbook_rels = format!(
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="{0}/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#
+            ),
+            strict_rel
+        );
+        let sheet = concat!(
+            r#"<worksheet xmlns="http://purl.oclc.org/ooxml/spreadsheetml/main"><sheetData>"#,
+            r#"<row r="1"><c r="A1" t="inlineStr"><is><t>x</t></is></c></row></sheetData></worksheet>"#
+        );
+        let content_types = concat!(
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+            r#"<Default Extension="xml" ContentType="application/xml"/></Types>"#
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.xlsx");
+        write_xlsx(
+            &input,
+            &[
+                ("[Content_Types].xml", content_types),
+                ("xl/workbook.xml", &workbook),
+                ("xl/_rels/workbook.xml.rels", &workbook_rels),
+                ("xl/worksheets/sheet1.xml", sheet),
+            ],
+        );
+        let output = dir.path().join("out.xlsx");
+        let opts = TrimOptions {
+            share_strings: true,
+            ..TrimOptions::default()
+        };
+        trim_one_xlsx(&input, &output, &opts).unwrap();
+        let sst = read_entry(&output, "xl/sharedStrings.xml").unwrap();
+        assert!(
+            sst.contains(r#"xmlns="http://purl.oclc.org/ooxml/spreadsheetml/main""#),
+            "{}",
+            sst
+        );
+        let rels = read_entry(&output, "xl/_rels/workbook.xml.rels").unwrap();
+        assert!(
+            rels.contains(&format!("{}/sharedStrings", strict_rel)),
+            "{}",
+            rels
+        );
+        assert!(!rels.contains("schemas.openxmlformats.org/officeDocument"));
+        let sheet = read_entry(&output, "xl/worksheets/sheet1.xml").unwrap();
+        assert!(sheet.contains(r#"t="s""#), "{}", sheet);
+    }
+    #[test]
+    fn files_with_wrong_signature_are_skipped_in_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, out) = (dir.path().join("in"), dir.path().join("out"));
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("Report.xlsx"), "%PDF-1.7").unwrap();
+        fs::write(src.join("Old.xlsx"), b"\x09\x08\x10\x00\x00\x06\x05\x00").unwrap();
+
+        process_path(&src, Some(&out), 0, "_t", &TrimOptions::default(), None).unwrap();
+        assert_eq!(fs::read_dir(&out).unwrap().count(), 0);
+
+        let err = process_path(
+            &src.join("Old.xlsx"),
+            Some(&out),
+            0,
+            "_t",
+            &TrimOptions::default(),
+            None,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("BIFF"), "{:#}", err);
+    }
+}
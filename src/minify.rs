@@ -0,0 +1,228 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::local_name;
+
+const MC_NS: &[u8] = b"http://schemas.openxmlformats.org/markup-compatibility/2006";
+
+/// Thu gọn XML của worksheet: bỏ khoảng trắng giữa các thẻ, các thuộc tính mặc định
+/// (`t="n"`, `s="0"` của ô, `spans` của hàng) và các khai báo namespace không còn dùng
+/// (cập nhật cả `mc:Ignorable`). Text trong phạm vi `xml:space="preserve"` giữ nguyên.
+pub fn minify_sheet(path: &Path) -> Result<()> {
+    let used = used_prefixes(path)?;
+    let tmp_out = path.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        // xml:space="preserve" theo từng mức lồng
+        let mut preserve: Vec<bool> = Vec::new();
+        // khoảng trắng ngay sau thẻ mở: chỉ ghi nếu nó là toàn bộ nội dung (<t> </t>)
+        let mut pending: Option<BytesText<'static>> = None;
+        let mut last = Last::Other;
+        loop {
+            let ev = reader.read_event_into(&mut buf)?;
+            match ev {
+                Event::Text(ref t) if t.iter().all(u8::is_ascii_whitespace) => {
+                    if preserve.last().copied().unwrap_or(false) || last == Last::Content {
+                        writer.write_event(Event::Text(t.borrow()))?;
+                    } else if last == Last::Start {
+                        pending = Some(t.clone().into_owned());
+                    }
+                    buf.clear();
+                    continue;
+                }
+                Event::End(_) => {
+                    if let Some(t) = pending.take() {
+                        writer.write_event(Event::Text(t))?;
+                    }
+                    preserve.pop();
+                    last = Last::Other;
+                    writer.write_event(ev)?;
+                }
+                Event::Start(ref e) => {
+                    pending = None;
+                    let inherited = preserve.last().copied().unwrap_or(false);
+                    preserve.push(match attr_raw(e, b"xml:space").as_deref() {
+                        Some(b"preserve") => true,
+                        Some(_) => false,
+                        None => inherited,
+                    });
+                    writer.write_event(Event::Start(minified(e, &used)))?;
+                    last = Last::Start;
+                }
+                Event::Empty(ref e) => {
+                    pending = None;
+                    writer.write_event(Event::Empty(minified(e, &used)))?;
+                    last = Last::Other;
+                }
+                // text, &amp;, CDATA: khoảng trắng đứng cạnh chúng là nội dung
+                Event::Text(_) | Event::GeneralRef(_) | Event::CData(_) => {
+                    if let Some(t) = pending.take() {
+                        writer.write_event(Event::Text(t))?;
+                    }
+                    last = Last::Content;
+                    writer.write_event(ev)?;
+                }
+                Event::Eof => break,
+                _ => {
+                    pending = None;
+                    last = Last::Other;
+                    writer.write_event(ev)?;
+                }
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, path)?;
+    Ok(())
+}
+
+/// Các prefix namespace thực sự xuất hiện trong tên thẻ/thuộc tính; prefix của
+/// markup-compatibility chỉ tính khi còn prefix nào khác trong `Ignorable` được dùng
+fn used_prefixes(path: &Path) -> Result<HashSet<Vec<u8>>> {
+    let mut reader = Reader::from_file(path)?;
+    let mut buf = Vec::new();
+    let mut used = HashSet::new();
+    let mut mc_prefixes = HashSet::new();
+    let mut ignorable: Vec<Vec<u8>> = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                if let Some(p) = prefix(e.name().as_ref()) {
+                    used.insert(p.to_vec());
+                }
+                for a in e.attributes().with_checks(false).flatten() {
+                    let key = a.key.as_ref();
+                    if let Some(p) = key.strip_prefix(b"xmlns:") {
+                        if a.value.as_ref() == MC_NS {
+                            mc_prefixes.insert(p.to_vec());
+                        }
+                    } else if local_name(key) == "Ignorable" && prefix(key).is_some() {
+                        ignorable
+                            .extend(a.value.split(u8::is_ascii_whitespace).map(<[u8]>::to_vec));
+                    } else if let Some(p) = prefix(key).filter(|p| *p != b"xml") {
+                        used.insert(p.to_vec());
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    if ignorable.iter().any(|p| used.contains(p)) {
+        used.extend(mc_prefixes);
+    }
+    Ok(used)
+}
+
+fn minified(e: &BytesStart, used: &HashSet<Vec<u8>>) -> BytesStart<'static> {
+    let tag = local_name(e.name().as_ref()).to_string();
+    let mut el = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).to_string());
+    for a in e.attributes().with_checks(false).flatten() {
+        let key = a.key.as_ref();
+        let value = a.value.as_ref();
+        let redundant = match (tag.as_str(), key) {
+            ("c", b"t") => value == b"n",
+            ("c", b"s") => value == b"0",
+            ("row", b"spans") => true,
+            _ => false,
+        };
+        if redundant {
+            continue;
+        }
+        if let Some(p) = key.strip_prefix(b"xmlns:") {
+            if !used.contains(p) {
+                continue;
+            }
+        }
+        if local_name(key) == "Ignorable" && prefix(key).is_some() {
+            let kept: Vec<&[u8]> = value
+                .split(u8::is_ascii_whitespace)
+                .filter(|p| !p.is_empty() && used.contains(*p))
+                .collect();
+            if !kept.is_empty() {
+                el.push_attribute((key, kept.join(&b' ').as_slice()));
+            }
+            continue;
+        }
+        el.push_attribute(a);
+    }
+    el
+}
+
+/// Loại event vừa ghi, để quyết định giữ khoảng trắng kế tiếp hay không
+#[derive(PartialEq, Eq)]
+enum Last {
+    Start,
+    Content,
+    Other,
+}
+
+fn prefix(name: &[u8]) -> Option<&[u8]> {
+    let pos = name.iter().position(|&b| b == b':')?;
+    Some(&name[..pos])
+}
+
+fn attr_raw(e: &BytesStart, key: &[u8]) -> Option<Vec<u8>> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|a| a.key.as_ref() == key)
+        .map(|a| a.value.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minify(xml: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sheet1.xml");
+        fs::write(&path, xml).unwrap();
+        minify_sheet(&path).unwrap();
+        fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn drops_whitespace_defaults_and_unused_namespaces() {
+        let xml = concat!(
+            r#"<worksheet xmlns="main" xmlns:r="rels" xmlns:mc="http://schemas.openxmlformats.org/markup-compatibility/2006" "#,
+            r#"xmlns:x14ac="x14ac" xmlns:xr="xr" mc:Ignorable="x14ac xr" xr:uid="{1}">"#,
+            "\n  <sheetData>\n    <row r=\"1\" spans=\"1:3\">\n      ",
+            r#"<c r="A1" t="n" s="0"><v>1</v></c>"#,
+            "\n      ",
+            r#"<c r="B1" t="inlineStr" s="2"><is><t xml:space="preserve">  a  </t></is></c>"#,
+            r#"<c r="C1" t="inlineStr"><is><t> </t></is></c>"#,
+            "\n    </row>\n  </sheetData>\n</worksheet>"
+        );
+        assert_eq!(
+            minify(xml),
+            concat!(
+                r#"<worksheet xmlns="main" xmlns:mc="http://schemas.openxmlformats.org/markup-compatibility/2006" "#,
+                r#"xmlns:xr="xr" mc:Ignorable="xr" xr:uid="{1}"><sheetData><row r="1">"#,
+                r#"<c r="A1"><v>1</v></c><c r="B1" t="inlineStr" s="2"><is><t xml:space="preserve">  a  </t></is></c>"#,
+                r#"<c r="C1" t="inlineStr"><is><t> </t></is></c></row></sheetData></worksheet>"#
+            )
+        );
+    }
+
+    #[test]
+    fn markup_compatibility_goes_with_last_ignorable_prefix() {
+        let xml = concat!(
+            r#"<worksheet xmlns="main" xmlns:mc="http://schemas.openxmlformats.org/markup-compatibility/2006" "#,
+            r#"xmlns:x14ac="x14ac" mc:Ignorable="x14ac"><sheetData/></worksheet>"#
+        );
+        assert_eq!(
+            minify(xml),
+            r#"<worksheet xmlns="main"><sheetData/></worksheet>"#
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use anyhow::Result;
+use quick_xml::events::{BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cellref::Range;
+use crate::workbook::{self, DefinedName, SheetEntry};
+use crate::{attr_value, formula, local_name, UsedRange};
+
+/// Số tên bị xoá / bị cắt lại vùng
+#[derive(Debug, Default)]
+pub struct NameStats {
+    pub removed: usize,
+    pub clipped: usize,
+}
+
+/// Dọn `<definedNames>` của workbook sau khi các sheet đã được cắt:
+/// - xoá tên hỏng (`#REF!`), tên trỏ sang workbook ngoài, `_xlnm._FilterDatabase` của sheet
+///   không còn autoFilter, và tên không công thức nào dùng tới (trừ khi `keep_unused`);
+///   tên hỏng/hết vùng mà vẫn được dùng (ô, DV/CF, biểu đồ, tên khác) thì giữ, trỏ về `#REF!`;
+/// - cắt `_xlnm.Print_Area` / `_xlnm.Print_Titles` về vùng còn lại của sheet.
+///
+/// `used_names`: tên (chữ thường) mà công thức trong các sheet dùng tới.
+pub fn clean_defined_names(
+    root: &Path,
+    workbook_xml: &Path,
+    sheets: &[SheetEntry],
+    trimmed: &HashMap<PathBuf, UsedRange>,
+    mut used_names: HashSet<String>,
+    keep_unused: bool,
+) -> Result<NameStats> {
+    let names = workbook::read_defined_names(workbook_xml)?;
+    if names.is_empty() {
+        return Ok(NameStats::default());
+    }
+    used_names.extend(chart_and_pivot_names(root)?);
+
+    let mut stats = NameStats::default();
+    // None = xoá, Some(công thức mới)
+    let mut decisions: Vec<Option<String>> = Vec::with_capacity(names.len());
+    for d in &names {
+        let decision = decide(d, sheets, trimmed)?;
+        if decision.as_deref().is_some_and(|f| f != d.formula) {
+            stats.clipped += 1;
+        }
+        decisions.push(decision);
+    }
+
+    // tên đang được dùng, kể cả dùng gián tiếp qua một tên khác còn giữ
+    let kept = |i: usize, d: &DefinedName, used_names: &HashSet<String>| {
+        decisions[i].is_some()
+            && (keep_unused
+                || d.name.starts_with("_xl")
+                || used_names.contains(&d.name.to_lowercase()))
+    };
+    let parsed: Vec<formula::Parsed> = names.iter().map(|d| formula::parse(&d.formula)).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, d) in names.iter().enumerate() {
+            if kept(i, d, &used_names) {
+                for n in &parsed[i].names {
+                    changed |= used_names.insert(n.to_lowercase());
+                }
+            }
+        }
+    }
+    // tên hỏng/vùng bị cắt hết nhưng vẫn được ô, DV/CF, biểu đồ hay tên khác dùng: giữ tên
+    // và trỏ về #REF! để các công thức đó báo lỗi tham chiếu thay vì #NAME?
+    for (i, d) in names.iter().enumerate() {
+        if decisions[i].is_none() && used_names.contains(&d.name.to_lowercase()) {
+            let keep_as = match d.formula.contains("#REF!") || parsed[i].external {
+                true => d.formula.clone(),
+                false => {
+                    stats.clipped += 1;
+                    "#REF!".to_string()
+                }
+            };
+            decisions[i] = Some(keep_as);
+        }
+    }
+    if !keep_unused {
+        for (i, d) in names.iter().enumerate() {
+            // _xlnm. (Print_Area...), _xlfn. (hàm mới) là tên dành riêng của Excel
+            if !d.name.starts_with("_xl") && !used_names.contains(&d.name.to_lowercase()) {
+                decisions[i] = None;
+            }
+        }
+    }
+    stats.removed = decisions.iter().filter(|d| d.is_none()).count();
+    if stats.removed == 0 && stats.clipped == 0 {
+        return Ok(stats);
+    }
+
+    write_defined_names(workbook_xml, &decisions)?;
+    Ok(stats)
+}
+
+/// Chỉ xoá các tên trỏ sang workbook ngoài (dùng khi đã bỏ các part externalLink)
+pub fn remove_external_names(workbook_xml: &Path) -> Result<usize> {
+    let names = workbook::read_defined_names(workbook_xml)?;
+    let decisions: Vec<Option<String>> = names
+        .iter()
+        .map(|d| (!formula::parse(&d.formula).external).then(|| d.formula.clone()))
+        .collect();
+    let removed = decisions.iter().filter(|d| d.is_none()).count();
+    if removed > 0 {
+        write_defined_names(workbook_xml, &decisions)?;
+    }
+    Ok(removed)
+}
+
+fn decide(
+    d: &DefinedName,
+    sheets: &[SheetEntry],
+    trimmed: &HashMap<PathBuf, UsedRange>,
+) -> Result<Option<String>> {
+    if d.formula.contains("#REF!") || formula::parse(&d.formula).external {
+        return Ok(None);
+    }
+    let local = d.local_sheet.and_then(|i| sheets.get(i));
+    match d.name.as_str() {
+        "_xlnm._FilterDatabase" => match local {
+            Some(s) if s.path.exists() && !has_auto_filter(&s.path)? => Ok(None),
+            _ => Ok(Some(d.formula.clone())),
+        },
+        "_xlnm.Print_Area" | "_xlnm.Print_Titles" => Ok(clip_areas(&d.formula, |sheet| {
+            let entry = match sheet {
+                Some(name) => workbook::find_sheet(sheets, name),
+                None => local,
+            };
+            entry.and_then(|s| trimmed.get(&s.path)).copied()
+        })),
+        _ => Ok(Some(d.formula.clone())),
+    }
+}
+
+/// "'Sheet 1'!$A$1:$Z$500,'Sheet 1'!$AB$1:$AC$9" -> cắt từng vùng theo vùng còn lại của
+/// sheet tương ứng; vùng nằm hẳn ngoài bị bỏ. None nếu không còn vùng nào.
+fn clip_areas(
+    formula: &str,
+    trimmed: impl Fn(Option<&str>) -> Option<UsedRange>,
+) -> Option<String> {
+    let mut kept = Vec::new();
+    for area in split_areas(formula) {
+        let (prefix, r) = match area.rsplit_once('!') {
+            Some((p, r)) => (Some(p), r),
+            None => (None, area),
+        };
+        let Some(rg) = Range::parse(r) else {
+            kept.push(area.to_string());
+            continue;
+        };
+        let sheet = prefix.map(|p| {
+            p.strip_prefix('\'')
+                .and_then(|p| p.strip_suffix('\''))
+                .map(|p| p.replace("''", "'"))
+                .unwrap_or_else(|| p.to_string())
+        });
+        let clipped = match trimmed(sheet.as_deref()) {
+            Some(used) if !used.is_empty() => rg.clip(used.last_col, used.last_row),
+            Some(_) => None,
+            // sheet không bị cắt (hoặc không tìm thấy): giữ nguyên
+            None => Some(rg),
+        };
+        if let Some(c) = clipped {
+            kept.push(match prefix {
+                Some(p) => format!("{}!{}", p, c),
+                None => c.to_string(),
+            });
+        }
+    }
+    (!kept.is_empty()).then(|| kept.join(","))
+}
+
+/// Tách theo dấu ',' không nằm trong cặp nháy đơn
+fn split_areas(formula: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, ch) in formula.char_indices() {
+        match ch {
+            '\'' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(formula[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(formula[start..].trim());
+    parts
+}
+
+fn has_auto_filter(sheet_xml: &Path) -> Result<bool> {
+    let mut reader = Reader::from_file(sheet_xml)?;
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e)
+                if local_name(e.name().as_ref()) == "autoFilter" =>
+            {
+                return Ok(true);
+            }
+            Event::Eof => return Ok(false),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Tên dùng trong công thức của biểu đồ (`<c:f>`) và nguồn pivot (`worksheetSource name`)
+fn chart_and_pivot_names(root: &Path) -> Result<HashSet<String>> {
+    let mut names = HashSet::new();
+    for dir in ["xl/charts", "xl/pivotCache"] {
+        let Ok(entries) = fs::read_dir(root.join(dir)) else {
+            continue;
+        };
+        for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+            if path.extension().is_none_or(|x| x != "xml") {
+                continue;
+            }
+            let mut reader = Reader::from_file(&path)?;
+            let mut buf = Vec::new();
+            let mut in_f = false;
+            loop {
+                match reader.read_event_into(&mut buf)? {
+                    Event::Start(ref e) if local_name(e.name().as_ref()) == "f" => in_f = true,
+                    Event::End(ref e) if local_name(e.name().as_ref()) == "f" => in_f = false,
+                    Event::Text(ref t) if in_f => {
+                        let parsed = formula::parse(&t.decode()?);
+                        names.extend(parsed.names.iter().map(|n| n.to_lowercase()));
+                    }
+                    Event::Start(ref e) | Event::Empty(ref e)
+                        if local_name(e.name().as_ref()) == "worksheetSource" =>
+                    {
+                        names.extend(attr_value(e, b"name").map(|n| n.to_lowercase()));
+                    }
+                    Event::Eof => break,
+                    _ => {}
+                }
+                buf.clear();
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Ghi lại workbook.xml theo quyết định cho từng `<definedName>` (cùng thứ tự với
+/// `read_defined_names`); không còn tên nào thì bỏ luôn `<definedNames>`
+fn write_defined_names(workbook_xml: &Path, decisions: &[Option<String>]) -> Result<()> {
+    let all_removed = decisions.iter().all(|d| d.is_none());
+    let tmp_out = workbook_xml.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(workbook_xml)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        let mut idx = 0usize;
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e)
+                    if all_removed && local_name(e.name().as_ref()) == "definedNames" =>
+                {
+                    reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                }
+                Event::Start(e) if local_name(e.name().as_ref()) == "definedName" => {
+                    reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                    if let Some(Some(f)) = decisions.get(idx) {
+                        let end = e.to_end().into_owned();
+                        writer.write_event(Event::Start(e))?;
+                        writer.write_event(Event::Text(BytesText::new(f)))?;
+                        writer.write_event(Event::End(end))?;
+                    }
+                    idx += 1;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, workbook_xml)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn used(last_col: u32, last_row: u32) -> UsedRange {
+        UsedRange {
+            first_row: 1,
+            first_col: 1,
+            last_row,
+            last_col,
+        }
+    }
+
+    #[test]
+    fn print_areas_are_clipped_per_sheet() {
+        let trimmed = |sheet: Option<&str>| match sheet {
+            Some("Sheet 1") | None => Some(used(5, 20)),
+            Some("Empty") => Some(UsedRange::default()),
+            _ => None,
+        };
+        assert_eq!(
+            clip_areas("'Sheet 1'!$A$1:$Z$500,'Sheet 1'!$AB$1:$AC$9", trimmed).as_deref(),
+            Some("'Sheet 1'!$A$1:$E$20")
+        );
+        assert_eq!(
+            clip_areas("Other!$A$1:$Z$500", trimmed).as_deref(),
+            Some("Other!$A$1:$Z$500")
+        );
+        assert_eq!(clip_areas("Empty!$A$1:$B$2", trimmed), None);
+        assert_eq!(split_areas("'a,b'!A1, C2"), ["'a,b'!A1", "C2"]);
+    }
+
+    #[test]
+    fn unused_and_broken_names_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let sheet_xml = root.join("xl/worksheets/sheet1.xml");
+        fs::create_dir_all(sheet_xml.parent().unwrap()).unwrap();
+        fs::write(&sheet_xml, "<worksheet><sheetData/></worksheet>").unwrap();
+        let workbook_xml = root.join("xl/workbook.xml");
+        fs::write(
+            &workbook_xml,
+            concat!(
+                r#"<workbook><sheets><sheet name="Data" sheetId="1" r:id="rId1"/></sheets><definedNames>"#,
+                r#"<definedName name="Rate">Base*2</definedName>"#,
+                r#"<definedName name="Base">Data!$A$1</definedName>"#,
+                r#"<definedName name="Unused">Data!$B$1</definedName>"#,
+                r#"<definedName name="Broken">#REF!$A$1</definedName>"#,
+                r#"<definedName name="Lost">[2]Data!$A$1</definedName>"#,
+                r#"<definedName name="_xlnm.Print_Area" localSheetId="0">Data!$A$1:$Z$900</definedName>"#,
+                r#"<definedName name="_xlnm._FilterDatabase" localSheetId="0" hidden="1">Data!$A$1:$C$9</definedName>"#,
+                r#"</definedNames></workbook>"#
+            ),
+        )
+        .unwrap();
+        let sheets = vec![SheetEntry {
+            name: "Data".to_string(),
+            kind: workbook::SheetKind::Worksheet,
+            sheet_id: 1,
+            path: sheet_xml.clone(),
+        }];
+        let trimmed = HashMap::from([(sheet_xml, used(3, 10))]);
+        let used_names = HashSet::from(["rate".to_string(), "lost".to_string()]);
+
+        let stats =
+            clean_defined_names(root, &workbook_xml, &sheets, &trimmed, used_names, false).unwrap();
+        assert_eq!((stats.removed, stats.clipped), (3, 1));
+        let out = fs::read_to_string(&workbook_xml).unwrap();
+        // Base được dùng gián tiếp qua Rate; Lost hỏng nhưng vẫn được dùng nên giữ nguyên
+        for kept in ["Rate", "Base", "Lost", "Data!$A$1:$C$10"] {
+            assert!(out.contains(kept), "{} {}", kept, out);
+        }
+        for gone in ["Unused", "Broken", "_FilterDatabase"] {
+            assert!(!out.contains(gone), "{} {}", gone, out);
+        }
+    }
+
+    #[test]
+    fn only_external_names_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workbook.xml");
+        fs::write(
+            &path,
+            concat!(
+                r#"<workbook><definedNames><definedName name="Rate">[1]Sheet1!$A$1</definedName>"#,
+                r#"<definedName name="Unused">Data!$B$1</definedName></definedNames></workbook>"#
+            ),
+        )
+        .unwrap();
+        assert_eq!(remove_external_names(&path).unwrap(), 1);
+        let out = fs::read_to_string(&path).unwrap();
+        assert!(!out.contains("Rate") && out.contains("Unused"), "{}", out);
+    }
+}
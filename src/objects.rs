@@ -0,0 +1,382 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::{Reader, Writer};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cellref::{Range, MAX_COL, MAX_ROW};
+use crate::{attr_value, local_name, orphans, rels, table, UsedRange};
+
+/// Đối tượng gắn vào sheet qua quan hệ của nó
+pub struct SheetObjects {
+    /// vùng các đối tượng chiếm giữ (hàng tiêu đề của bảng, anchor hình/biểu đồ, comment)
+    pub ranges: Vec<Range>,
+    /// r:id của các bảng nằm hẳn ngoài vùng dùng (chỉ khi xoá đối tượng ngoài vùng)
+    pub dropped_tables: HashSet<String>,
+    /// các part đã bị xoá khỏi package
+    pub removed_parts: Vec<PathBuf>,
+}
+
+/// Duyệt bảng, drawing, comment của sheet. `drop_outside` = Some(vùng dùng) thì xoá các
+/// đối tượng bắt đầu ngoài vùng đó thay vì chỉ báo lại vùng của chúng.
+pub fn process_sheet_objects(
+    root: &Path,
+    sheet_xml: &Path,
+    drop_outside: Option<UsedRange>,
+) -> Result<SheetObjects> {
+    let mut objs = SheetObjects {
+        ranges: Vec::new(),
+        dropped_tables: HashSet::new(),
+        removed_parts: Vec::new(),
+    };
+    let outside = |rg: &Range| match drop_outside {
+        Some(used) => rg.clip(used.last_col, used.last_row).is_none(),
+        None => false,
+    };
+
+    for rel in rels::read_rels(sheet_xml)?.iter().filter(|r| !r.external) {
+        let Some(part) = rels::resolve_target(root, sheet_xml, &rel.target) else {
+            continue;
+        };
+        if !part.exists() {
+            continue;
+        }
+        if rel.is("table") {
+            let Some((r, header_rows, _)) = table::read_table_header(&part)? else {
+                continue;
+            };
+            let Some(rg) = Range::parse(&r) else {
+                continue;
+            };
+            if outside(&rg) {
+                objs.dropped_tables.insert(rel.id.clone());
+                fs::remove_file(&part)?;
+                objs.removed_parts.push(part);
+                continue;
+            }
+            // chỉ cần giữ hàng tiêu đề + một hàng dữ liệu; phần còn lại do rewrite_table cắt
+            let mut head = rg;
+            head.last.row = rg.last.row.min(rg.first.row.saturating_add(header_rows));
+            objs.ranges.push(head);
+        } else if rel.is("drawing") {
+            let (ranges, dropped) = filter_parts(&part, &ANCHORS, drop_outside)?;
+            objs.ranges.extend(ranges);
+            objs.removed_parts.extend(drop_rels(root, &part, &dropped)?);
+        } else if rel.is("comments") || rel.is("threadedComment") {
+            let (ranges, dropped) = filter_parts(&part, &COMMENTS, drop_outside)?;
+            objs.ranges.extend(ranges);
+            objs.removed_parts.extend(drop_rels(root, &part, &dropped)?);
+        } else if rel.is("vmlDrawing") && drop_outside.is_some() {
+            // VML hỏng tới mức không đọc được: để nguyên part thay vì bỏ cả file
+            if let Ok((_, dropped)) = filter_parts(&part, &NOTE_SHAPES, drop_outside) {
+                objs.removed_parts.extend(drop_rels(root, &part, &dropped)?);
+            }
+        }
+    }
+    Ok(objs)
+}
+
+/// Bỏ các quan hệ `ids` của part rồi xoá chart/hình/media chỉ chúng dùng.
+/// Trả về các part đã bị xoá khỏi package.
+fn drop_rels(root: &Path, part: &Path, ids: &HashSet<String>) -> Result<Vec<PathBuf>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let all = rels::read_rels(part)?;
+    let dropped: Vec<&rels::Relationship> = all.iter().filter(|r| ids.contains(&r.id)).collect();
+    rels::remove_rels(part, ids)?;
+    orphans::remove_unreferenced(root, part, &dropped)
+}
+
+/// Cách nhận diện và định vị một loại phần tử con trong part
+struct PartKind {
+    /// tên (local) của phần tử cần xét
+    tags: &'static [&'static str],
+    /// vùng của phần tử, tính từ các event đã gom
+    locate: fn(&[Event<'static>]) -> Option<Range>,
+}
+
+const ANCHORS: PartKind = PartKind {
+    tags: &["twoCellAnchor", "oneCellAnchor"],
+    locate: anchor_range,
+};
+
+const COMMENTS: PartKind = PartKind {
+    tags: &["comment", "threadedComment"],
+    locate: comment_range,
+};
+
+const NOTE_SHAPES: PartKind = PartKind {
+    tags: &["shape"],
+    locate: note_range,
+};
+
+/// Gom từng phần tử loại `kind` rồi tính vùng của nó; khi `drop_outside` có giá trị thì
+/// ghi lại part, bỏ các phần tử bắt đầu ngoài vùng dùng.
+/// Trả về vùng của các phần tử được giữ và r:id chỉ các phần tử bị bỏ dùng.
+/// Part không đọc được thì giữ nguyên file và trả lỗi.
+fn filter_parts(
+    path: &Path,
+    kind: &PartKind,
+    drop_outside: Option<UsedRange>,
+) -> Result<(Vec<Range>, HashSet<String>)> {
+    let tmp_out = path.with_extension("out");
+    let mut writer = match drop_outside {
+        Some(_) => Some(Writer::new(BufWriter::new(File::create(&tmp_out)?))),
+        None => None,
+    };
+    let filtered = filter_events(path, kind, drop_outside, writer.as_mut());
+    let (ranges, dropped_ids, kept_ids) = match filtered {
+        Ok(r) => r,
+        Err(e) => {
+            drop(writer);
+            let _ = fs::remove_file(&tmp_out);
+            return Err(e);
+        }
+    };
+    if let Some(w) = writer {
+        w.into_inner().flush()?;
+        fs::rename(&tmp_out, path)?;
+    }
+    let orphans = dropped_ids.difference(&kept_ids).cloned().collect();
+    Ok((ranges, orphans))
+}
+
+/// Phần đọc của `filter_parts`: trả về vùng giữ lại, r:id của phần tử bị bỏ và của phần tử giữ
+fn filter_events<W: Write>(
+    path: &Path,
+    kind: &PartKind,
+    drop_outside: Option<UsedRange>,
+    mut writer: Option<&mut Writer<W>>,
+) -> Result<(Vec<Range>, HashSet<String>, HashSet<String>)> {
+    let mut reader = Reader::from_file(path)?;
+    // VML cũ thường không đóng thẻ chặt chẽ
+    reader.config_mut().check_end_names = false;
+
+    let mut ranges = Vec::new();
+    let mut dropped_ids = HashSet::new();
+    let mut kept_ids = HashSet::new();
+    let mut pending: Vec<Event<'static>> = Vec::new();
+    let mut depth = 0usize;
+    let mut buf = Vec::new();
+
+    loop {
+        let ev = reader.read_event_into(&mut buf)?.into_owned();
+        if matches!(ev, Event::Eof) {
+            break;
+        }
+        let starts = match &ev {
+            Event::Start(e) => depth == 0 && kind.tags.contains(&local_name(e.name().as_ref())),
+            _ => false,
+        };
+        if starts || depth > 0 {
+            match &ev {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+            pending.push(ev);
+            if depth == 0 {
+                let rg = (kind.locate)(&pending);
+                let drop = match (rg, drop_outside) {
+                    (Some(rg), Some(used)) => rg.clip(used.last_col, used.last_row).is_none(),
+                    _ => false,
+                };
+                let ids = rel_ids(&pending);
+                if drop {
+                    dropped_ids.extend(ids);
+                } else {
+                    kept_ids.extend(ids);
+                    ranges.extend(rg);
+                    if let Some(w) = writer.as_mut() {
+                        for pe in pending.drain(..) {
+                            w.write_event(pe)?;
+                        }
+                    }
+                }
+                pending.clear();
+            }
+        } else if let Some(w) = writer.as_mut() {
+            w.write_event(ev)?;
+        }
+        buf.clear();
+    }
+
+    Ok((ranges, dropped_ids, kept_ids))
+}
+
+/// Giá trị của các thuộc tính quan hệ (r:id, r:embed, r:link...) trong các event
+fn rel_ids(events: &[Event]) -> HashSet<String> {
+    let mut ids = HashSet::new();
+    for ev in events {
+        if let Event::Start(e) | Event::Empty(e) = ev {
+            for a in e.attributes().with_checks(false).flatten() {
+                let key = a.key.as_ref();
+                if !key.starts_with(b"xmlns")
+                    && [&b":id"[..], b":embed", b":link", b":pict"]
+                        .iter()
+                        .any(|k| key.ends_with(k))
+                {
+                    ids.insert(String::from_utf8_lossy(&a.value).to_string());
+                }
+            }
+        }
+    }
+    ids
+}
+
+/// Text của các phần tử con, theo đường dẫn tên local (vd ["from", "col"])
+fn child_texts<'a>(events: &'a [Event<'static>]) -> impl Iterator<Item = (Vec<&'a str>, String)> {
+    let mut path: Vec<&'a str> = Vec::new();
+    events.iter().filter_map(move |ev| match ev {
+        Event::Start(e) => {
+            path.push(local_name(e.name().into_inner()));
+            None
+        }
+        Event::End(_) => {
+            path.pop();
+            None
+        }
+        Event::Text(t) => t
+            .decode()
+            .ok()
+            .map(|s| (path.clone(), s.trim().to_string())),
+        _ => None,
+    })
+}
+
+/// xdr:from / xdr:to (chỉ số từ 0) -> vùng ô
+fn anchor_range(events: &[Event<'static>]) -> Option<Range> {
+    let (mut from, mut to) = ((None, None), (None, None));
+    for (path, text) in child_texts(events) {
+        // chỉ số từ 0 -> cột/hàng từ 1, chặn ở XFD / hàng cuối
+        let n: Option<u32> = text.parse::<u32>().ok().map(|n| n.saturating_add(1));
+        match path.as_slice() {
+            [.., "from", "col"] => from.0 = n,
+            [.., "from", "row"] => from.1 = n,
+            [.., "to", "col"] => to.0 = n,
+            [.., "to", "row"] => to.1 = n,
+            _ => {}
+        }
+    }
+    let (c1, r1) = (from.0?.min(MAX_COL), from.1?.min(MAX_ROW));
+    let (c2, r2) = (
+        to.0.unwrap_or(c1).min(MAX_COL),
+        to.1.unwrap_or(r1).min(MAX_ROW),
+    );
+    Some(Range::new(c1, r1, c2.max(c1), r2.max(r1)))
+}
+
+fn comment_range(events: &[Event<'static>]) -> Option<Range> {
+    match events.first()? {
+        Event::Start(e) => Range::parse(&attr_value(e, b"ref")?),
+        _ => None,
+    }
+}
+
+/// Shape ghi chú trong VML: `<x:ClientData ObjectType="Note"><x:Row>4</x:Row><x:Column>1</x:Column>`
+fn note_range(events: &[Event<'static>]) -> Option<Range> {
+    let is_note = events.iter().any(|ev| match ev {
+        Event::Start(e) => {
+            local_name(e.name().as_ref()) == "ClientData"
+                && attr_value(e, b"ObjectType").as_deref() == Some("Note")
+        }
+        _ => false,
+    });
+    if !is_note {
+        return None;
+    }
+    let (mut row, mut col) = (None, None);
+    for (path, text) in child_texts(events) {
+        match path.last().copied() {
+            Some("Row") => row = text.parse::<u32>().ok(),
+            Some("Column") => col = text.parse::<u32>().ok(),
+            _ => {}
+        }
+    }
+    let (c, r) = (
+        col?.saturating_add(1).min(MAX_COL),
+        row?.saturating_add(1).min(MAX_ROW),
+    );
+    Some(Range::new(c, r, c, r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(xml: &str) -> Vec<Event<'static>> {
+        let mut reader = Reader::from_str(xml);
+        let mut out = Vec::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Eof => break,
+                ev => out.push(ev.into_owned()),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn anchor_range_is_one_based_and_clamped() {
+        let ev = events(concat!(
+            "<xdr:twoCellAnchor><xdr:from><xdr:col>1</xdr:col><xdr:row>4</xdr:row></xdr:from>",
+            "<xdr:to><xdr:col>3</xdr:col><xdr:row>9</xdr:row></xdr:to></xdr:twoCellAnchor>"
+        ));
+        assert_eq!(anchor_range(&ev), Some(Range::new(2, 5, 4, 10)));
+
+        // chỉ số khổng lồ không được tràn số
+        let ev = events(concat!(
+            "<xdr:oneCellAnchor><xdr:from><xdr:col>4294967295</xdr:col>",
+            "<xdr:row>4294967295</xdr:row></xdr:from></xdr:oneCellAnchor>"
+        ));
+        assert_eq!(
+            anchor_range(&ev),
+            Some(Range::new(MAX_COL, MAX_ROW, MAX_COL, MAX_ROW))
+        );
+    }
+
+    #[test]
+    fn note_range_reads_vml_client_data() {
+        let ev = events(concat!(
+            r#"<v:shape><x:ClientData ObjectType="Note"><x:Row>3</x:Row>"#,
+            "<x:Column>4294967295</x:Column></x:ClientData></v:shape>"
+        ));
+        assert_eq!(note_range(&ev), Some(Range::new(MAX_COL, 4, MAX_COL, 4)));
+
+        let ev = events(
+            r#"<v:shape><x:ClientData ObjectType="Button"><x:Row>3</x:Row></x:ClientData></v:shape>"#,
+        );
+        assert_eq!(note_range(&ev), None);
+    }
+
+    #[test]
+    fn comments_outside_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("comments1.xml");
+        fs::write(
+            &path,
+            concat!(
+                r#"<comments><commentList><comment ref="B2"><text>a</text></comment>"#,
+                r#"<comment ref="Q80"><text>b</text></comment></commentList></comments>"#
+            ),
+        )
+        .unwrap();
+        let used = UsedRange {
+            first_row: 1,
+            first_col: 1,
+            last_row: 10,
+            last_col: 5,
+        };
+
+        let (ranges, _) = filter_parts(&path, &COMMENTS, None).unwrap();
+        assert_eq!(ranges.len(), 2);
+
+        let (ranges, _) = filter_parts(&path, &COMMENTS, Some(used)).unwrap();
+        assert_eq!(ranges, vec![Range::new(2, 2, 2, 2)]);
+        let out = fs::read_to_string(&path).unwrap();
+        assert!(out.contains("B2") && !out.contains("Q80"), "{}", out);
+    }
+}
@@ -0,0 +1,381 @@
+use anyhow::{Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::cellref::{MAX_COL, MAX_ROW};
+use crate::{local_name, RangeMode, TrimOptions, UsedRange};
+
+const ODS_MIMETYPES: &[&str] = &[
+    "application/vnd.oasis.opendocument.spreadsheet",
+    "application/vnd.oasis.opendocument.spreadsheet-template",
+];
+
+/// Gói OpenDocument spreadsheet: file `mimetype` ở gốc
+pub fn is_ods(root: &Path) -> bool {
+    fs::read_to_string(root.join("mimetype")).is_ok_and(|m| ODS_MIMETYPES.contains(&m.trim()))
+}
+
+/// Cắt các `table:table` trong content.xml theo vùng dữ liệu: bỏ/cắt các hàng, cột, ô lặp
+/// (`number-rows-repeated`, `number-columns-repeated`) nằm sau ô cuối cùng có dữ liệu
+pub fn trim_content(root: &Path, opts: &TrimOptions) -> Result<()> {
+    let ignored = opts.ooxml_only();
+    if !ignored.is_empty() {
+        eprintln!("   ⚠ .ods: bỏ qua {}", ignored.join(", "));
+    }
+    let content = root.join("content.xml");
+    let tables =
+        find_used_ranges(&content, opts.range_mode).context("find_used_range content.xml")?;
+    for (name, used) in &tables {
+        eprintln!("   • {}: {}", name, used.dimension_ref());
+    }
+    let used: Vec<UsedRange> = tables.into_iter().map(|(_, u)| u).collect();
+    let tmp_out = content.with_extension("xml.out");
+    rewrite_content(&content, &tmp_out, &used).context("rewrite content.xml")?;
+    fs::rename(&tmp_out, &content)?;
+    Ok(())
+}
+
+/// Giá trị thuộc tính theo tên local (bỏ prefix `table:`, `office:`...)
+fn attr_local(e: &BytesStart, name: &str) -> Option<String> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|a| local_name(a.key.as_ref()) == name)
+        .map(|a| String::from_utf8_lossy(&a.value).to_string())
+}
+
+fn repeat(e: &BytesStart, name: &str) -> u32 {
+    attr_local(e, name)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Vị trí sau `rep` phần tử lặp bắt đầu ở `pos`; số lặp lấy từ file nên chặn ở `limit + 1`
+/// (hàng/cột cuối của LibreOffice/Excel) thay vì để tràn u32
+fn advance(pos: u32, rep: u32, limit: u32) -> u32 {
+    pos.saturating_add(rep).min(limit + 1)
+}
+
+fn is_cell(tag: &str) -> bool {
+    tag == "table-cell" || tag == "covered-table-cell"
+}
+
+/// Ô "đã dùng" theo chế độ: có kiểu giá trị, công thức hoặc nội dung con (text, ghi chú,
+/// hình); ở chế độ formatting/any thêm ô có `table:style-name`
+fn cell_counts(e: &BytesStart, has_children: bool, mode: RangeMode) -> bool {
+    has_children
+        || attr_local(e, "value-type").is_some()
+        || attr_local(e, "formula").is_some()
+        || (mode != RangeMode::Values && attr_local(e, "style-name").is_some())
+}
+
+/// Vùng dữ liệu (tên bảng, vùng) của từng `table:table` theo thứ tự trong file.
+/// Bảng trống được coi là A1 vì bảng phải còn ít nhất một hàng và một cột.
+fn find_used_ranges(content: &Path, mode: RangeMode) -> Result<Vec<(String, UsedRange)>> {
+    let mut reader = Reader::from_file(content)?;
+    let mut buf = Vec::new();
+    let mut tables = Vec::new();
+    let mut current: Option<(String, UsedRange)> = None;
+    let (mut row, mut col) = (1u32, 1u32);
+    let mut row_rep = 1u32;
+    // ô đang mở: (thẻ, đã thấy phần tử con)
+    let mut cell: Option<(BytesStart<'static>, bool)> = None;
+    let mut cell_depth = 0usize;
+
+    let mark = |used: &mut UsedRange, e: &BytesStart, row: u32, col: u32, row_rep: u32| {
+        let col_rep = repeat(e, "number-columns-repeated");
+        let spanned_cols = repeat(e, "number-columns-spanned");
+        let spanned_rows = repeat(e, "number-rows-spanned");
+        used.include(col.min(MAX_COL), row.min(MAX_ROW));
+        used.include(
+            advance(col, col_rep.max(spanned_cols) - 1, MAX_COL).min(MAX_COL),
+            advance(row, row_rep.max(spanned_rows) - 1, MAX_ROW).min(MAX_ROW),
+        );
+    };
+
+    loop {
+        let ev = reader.read_event_into(&mut buf)?;
+        if let Some((_, children)) = cell.as_mut() {
+            match &ev {
+                Event::Start(_) => {
+                    *children = true;
+                    cell_depth += 1;
+                }
+                Event::Empty(_) => *children = true,
+                Event::End(_) if cell_depth > 0 => cell_depth -= 1,
+                Event::End(_) => {
+                    let (e, children) = cell.take().unwrap();
+                    let used = &mut current.as_mut().unwrap().1;
+                    if cell_counts(&e, children, mode) {
+                        mark(used, &e, row, col, row_rep);
+                    }
+                    col = advance(col, repeat(&e, "number-columns-repeated"), MAX_COL);
+                }
+                _ => {}
+            }
+            buf.clear();
+            continue;
+        }
+        match ev {
+            Event::Start(ref e) if local_name(e.name().as_ref()) == "table" => {
+                current = Some((
+                    attr_local(e, "name").unwrap_or_default(),
+                    UsedRange::default(),
+                ));
+                row = 1;
+            }
+            Event::End(ref e) if local_name(e.name().as_ref()) == "table" => {
+                if let Some((name, mut used)) = current.take() {
+                    if used.is_empty() {
+                        used.include(1, 1);
+                    }
+                    tables.push((name, used));
+                }
+            }
+            Event::Start(ref e)
+                if current.is_some() && local_name(e.name().as_ref()) == "table-row" =>
+            {
+                row_rep = repeat(e, "number-rows-repeated");
+                col = 1;
+            }
+            Event::End(ref e)
+                if current.is_some() && local_name(e.name().as_ref()) == "table-row" =>
+            {
+                row = advance(row, row_rep, MAX_ROW);
+            }
+            Event::Empty(ref e)
+                if current.is_some() && local_name(e.name().as_ref()) == "table-row" =>
+            {
+                row = advance(row, repeat(e, "number-rows-repeated"), MAX_ROW);
+            }
+            Event::Start(ref e) if current.is_some() && is_cell(local_name(e.name().as_ref())) => {
+                cell = Some((e.to_owned().into_owned(), false));
+                cell_depth = 0;
+            }
+            Event::Empty(ref e) if current.is_some() && is_cell(local_name(e.name().as_ref())) => {
+                if cell_counts(e, false, mode) {
+                    mark(&mut current.as_mut().unwrap().1, e, row, col, row_rep);
+                }
+                col = advance(col, repeat(e, "number-columns-repeated"), MAX_COL);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(tables)
+}
+
+/// Bản sao thẻ với số lần lặp `attr` cắt còn `keep` (1 thì bỏ thuộc tính)
+fn clip_repeat(e: &BytesStart, attr: &str, keep: u32) -> BytesStart<'static> {
+    let mut el = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).to_string());
+    for a in e.attributes().with_checks(false).flatten() {
+        if local_name(a.key.as_ref()) == attr {
+            if keep > 1 {
+                el.push_attribute((a.key.as_ref(), keep.to_string().as_bytes()));
+            }
+        } else {
+            el.push_attribute(a);
+        }
+    }
+    el
+}
+
+/// Phần tử lặp bắt đầu tại `pos` (từ 1), lặp `rep` lần, so với giới hạn `last`:
+/// None = nằm hẳn ngoài, Some(số lần lặp còn giữ)
+fn kept_repeat(pos: u32, rep: u32, last: u32) -> Option<u32> {
+    (pos <= last).then(|| rep.min(last - pos + 1))
+}
+
+fn rewrite_content(content: &Path, out: &Path, used: &[UsedRange]) -> Result<()> {
+    let mut reader = Reader::from_file(content)?;
+    let mut writer = Writer::new(BufWriter::new(File::create(out)?));
+    let mut buf = Vec::new();
+    let mut table_idx = 0usize;
+    let mut current: Option<UsedRange> = None;
+    let (mut row, mut col, mut column) = (1u32, 1u32, 1u32);
+
+    loop {
+        let ev = reader.read_event_into(&mut buf)?;
+        let tag = match &ev {
+            Event::Start(e) | Event::Empty(e) => local_name(e.name().as_ref()).to_string(),
+            Event::End(e) => local_name(e.name().as_ref()).to_string(),
+            _ => String::new(),
+        };
+        let Some(u) = current else {
+            match ev {
+                Event::Start(ref e) if tag == "table" => {
+                    current = Some(used.get(table_idx).copied().unwrap_or_default());
+                    table_idx += 1;
+                    (row, column) = (1, 1);
+                    writer.write_event(Event::Start(e.borrow()))?;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+            continue;
+        };
+        match ev {
+            Event::End(ref e) if tag == "table" => {
+                current = None;
+                writer.write_event(Event::End(e.borrow()))?;
+            }
+            Event::Start(ref e) | Event::Empty(ref e) if tag == "table-column" => {
+                let rep = repeat(e, "number-columns-repeated");
+                let kept = kept_repeat(column, rep, u.last_col);
+                column = advance(column, rep, MAX_COL);
+                let is_start = matches!(ev, Event::Start(_));
+                match kept {
+                    None if is_start => {
+                        reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                    }
+                    None => {}
+                    Some(k) => {
+                        let el = clip_repeat(e, "number-columns-repeated", k);
+                        writer.write_event(if is_start {
+                            Event::Start(el)
+                        } else {
+                            Event::Empty(el)
+                        })?;
+                    }
+                }
+            }
+            Event::Start(ref e) | Event::Empty(ref e) if tag == "table-row" => {
+                let rep = repeat(e, "number-rows-repeated");
+                let kept = kept_repeat(row, rep, u.last_row);
+                row = advance(row, rep, MAX_ROW);
+                col = 1;
+                let is_start = matches!(ev, Event::Start(_));
+                match kept {
+                    None if is_start => {
+                        reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                    }
+                    None => {}
+                    Some(k) => {
+                        let el = clip_repeat(e, "number-rows-repeated", k);
+                        writer.write_event(if is_start {
+                            Event::Start(el)
+                        } else {
+                            Event::Empty(el)
+                        })?;
+                    }
+                }
+            }
+            Event::Start(ref e) | Event::Empty(ref e) if is_cell(&tag) => {
+                let rep = repeat(e, "number-columns-repeated");
+                let kept = kept_repeat(col, rep, u.last_col);
+                col = advance(col, rep, MAX_COL);
+                let is_start = matches!(ev, Event::Start(_));
+                match kept {
+                    None if is_start => {
+                        reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                    }
+                    None => {}
+                    Some(k) => {
+                        let el = clip_repeat(e, "number-columns-repeated", k);
+                        if is_start {
+                            // nội dung ô (có thể chứa bảng con) được chép nguyên
+                            copy_children(&mut reader, &mut writer, el)?;
+                        } else {
+                            writer.write_event(Event::Empty(el))?;
+                        }
+                    }
+                }
+            }
+            Event::Eof => break,
+            ev => writer.write_event(ev)?,
+        }
+        buf.clear();
+    }
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+/// Ghi thẻ mở `start` rồi chép nguyên mọi event đến thẻ đóng tương ứng
+fn copy_children<R: BufRead, W: Write>(
+    reader: &mut Reader<R>,
+    writer: &mut Writer<W>,
+    start: BytesStart<'static>,
+) -> Result<()> {
+    writer.write_event(Event::Start(start))?;
+    let mut buf = Vec::new();
+    let mut depth = 1usize;
+    loop {
+        let ev = reader.read_event_into(&mut buf)?;
+        match ev {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => anyhow::bail!("content.xml kết thúc giữa chừng"),
+            _ => {}
+        }
+        writer.write_event(ev)?;
+        if depth == 0 {
+            return Ok(());
+        }
+        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAD: &str = r#"<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0"><office:body><office:spreadsheet>"#;
+    const TAIL: &str = "</office:spreadsheet></office:body></office:document-content>";
+
+    fn trimmed(tables: &str) -> (Vec<(String, UsedRange)>, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content.xml");
+        fs::write(&content, format!("{HEAD}{tables}{TAIL}")).unwrap();
+        let used = find_used_ranges(&content, RangeMode::Values).unwrap();
+        let out = dir.path().join("out.xml");
+        let ranges: Vec<UsedRange> = used.iter().map(|(_, u)| *u).collect();
+        rewrite_content(&content, &out, &ranges).unwrap();
+        (used, fs::read_to_string(out).unwrap())
+    }
+
+    #[test]
+    fn repeated_rows_and_columns_are_cut_after_last_value() {
+        let (used, xml) = trimmed(concat!(
+            r#"<table:table table:name="Data">"#,
+            r#"<table:table-column table:number-columns-repeated="1024"/>"#,
+            r#"<table:table-row><table:table-cell office:value-type="float"/>"#,
+            r#"<table:table-cell office:value-type="string"><text:p>x</text:p></table:table-cell>"#,
+            r#"<table:table-cell table:number-columns-repeated="1022"/></table:table-row>"#,
+            r#"<table:table-row table:number-rows-repeated="1048575">"#,
+            r#"<table:table-cell table:number-columns-repeated="1024"/></table:table-row>"#,
+            r#"</table:table><table:table table:name="Empty">"#,
+            r#"<table:table-row><table:table-cell/></table:table-row></table:table>"#,
+        ));
+        assert_eq!(used[0].0, "Data");
+        assert_eq!(used[0].1.dimension_ref(), "A1:B1");
+        assert_eq!(used[1].1.dimension_ref(), "A1");
+        assert!(xml.contains(r#"<table:table-column table:number-columns-repeated="2"/>"#));
+        assert!(!xml.contains("1022"));
+        assert!(!xml.contains("number-rows-repeated"));
+        assert!(xml.contains("<text:p>x</text:p>"));
+    }
+
+    #[test]
+    fn huge_repeat_counts_saturate() {
+        assert_eq!(advance(u32::MAX - 1, 5, MAX_ROW), MAX_ROW + 1);
+        assert_eq!(kept_repeat(3, u32::MAX, 10), Some(8));
+        assert_eq!(kept_repeat(11, 1, 10), None);
+
+        let (used, xml) = trimmed(concat!(
+            r#"<table:table table:name="Big">"#,
+            r#"<table:table-row><table:table-cell office:value-type="float"/>"#,
+            r#"<table:table-cell table:number-columns-repeated="4294967295"/></table:table-row>"#,
+            r#"<table:table-row table:number-rows-repeated="4294967295">"#,
+            r#"<table:table-cell table:number-columns-repeated="4294967295"/></table:table-row>"#,
+            r#"</table:table>"#,
+        ));
+        assert_eq!(used[0].1.dimension_ref(), "A1");
+        assert!(!xml.contains("4294967295"));
+    }
+}
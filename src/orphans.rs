@@ -0,0 +1,274 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::rels;
+
+/// Dọn chartsheet/dialogsheet: bỏ các quan hệ mà sheet không còn trỏ tới, rồi làm tương tự
+/// cho drawing của nó. Trả về các part đã bị xoá khỏi package.
+pub fn clean_sheet_parts(root: &Path, sheet_xml: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = prune_orphan_rels(root, sheet_xml)?;
+    for rel in rels::read_rels(sheet_xml)?.iter().filter(|r| !r.external) {
+        if rel.is("drawing") || rel.is("vmlDrawing") {
+            match rels::resolve_target(root, sheet_xml, &rel.target) {
+                Some(drawing) if drawing.exists() => {
+                    removed.extend(prune_orphan_rels(root, &drawing)?);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Bỏ các Relationship của `part` có Id không xuất hiện trong bất kỳ thuộc tính nào của part
+/// (r:id, r:embed, o:relid...). Part đích không còn quan hệ nào khác trỏ tới thì bị xoá.
+pub fn prune_orphan_rels(root: &Path, part: &Path) -> Result<Vec<PathBuf>> {
+    let all = rels::read_rels(part)?;
+    if all.is_empty() {
+        return Ok(Vec::new());
+    }
+    let values = attr_values(part)?;
+    let orphans: Vec<&rels::Relationship> =
+        all.iter().filter(|r| !values.contains(&r.id)).collect();
+    if orphans.is_empty() {
+        return Ok(Vec::new());
+    }
+    let ids: HashSet<String> = orphans.iter().map(|r| r.id.clone()).collect();
+    rels::remove_rels(part, &ids)?;
+    remove_unreferenced(root, part, &orphans)
+}
+
+/// Xoá đích của các quan hệ `dropped` (của `part`, đã bỏ khỏi file .rels) khi không còn quan hệ
+/// nào khác trỏ tới, rồi lặp lại cho các part mà chúng dùng (style/màu của chart...).
+/// Trả về các part đã bị xoá khỏi package.
+pub fn remove_unreferenced(
+    root: &Path,
+    part: &Path,
+    dropped: &[&rels::Relationship],
+) -> Result<Vec<PathBuf>> {
+    let mut queue: Vec<PathBuf> = dropped
+        .iter()
+        .filter(|r| !r.external)
+        .filter_map(|r| rels::resolve_target(root, part, &r.target))
+        .collect();
+    let mut removed = Vec::new();
+    while !queue.is_empty() {
+        let still_used = referenced_parts(root)?;
+        let mut next = Vec::new();
+        for target in queue {
+            if !target.is_file() || still_used.contains(&target) {
+                continue;
+            }
+            let children = rels::read_rels(&target)?;
+            match fs::remove_file(&target) {
+                Ok(()) => {}
+                // sheet khác (chạy song song) vừa xoá cùng part này
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+            let target_rels = rels::rels_path_for(&target);
+            if target_rels.exists() {
+                fs::remove_file(&target_rels)?;
+            }
+            // thư mục chỉ chứa part này (xl/media, _rels...) giờ đã rỗng
+            for dir in [target_rels.parent(), target.parent()]
+                .into_iter()
+                .flatten()
+            {
+                let _ = fs::remove_dir(dir);
+            }
+            next.extend(
+                children
+                    .iter()
+                    .filter(|r| !r.external)
+                    .filter_map(|r| rels::resolve_target(root, &target, &r.target)),
+            );
+            removed.push(target);
+        }
+        queue = next;
+    }
+    Ok(removed)
+}
+
+/// Mọi giá trị thuộc tính trong part (VML cũ không đóng thẻ chặt chẽ nên không kiểm tra tên)
+fn attr_values(part: &Path) -> Result<HashSet<String>> {
+    let mut reader = Reader::from_file(part)?;
+    reader.config_mut().check_end_names = false;
+    let mut buf = Vec::new();
+    let mut values = HashSet::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                for a in e.attributes().with_checks(false).flatten() {
+                    values.insert(String::from_utf8_lossy(&a.value).to_string());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(values)
+}
+
+/// Các part đang là đích của một quan hệ nào đó trong package
+fn referenced_parts(root: &Path) -> Result<HashSet<PathBuf>> {
+    let mut parts = HashSet::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().is_none_or(|x| x != "rels") {
+            continue;
+        }
+        let Some(dir) = path.parent().and_then(|d| d.parent()) else {
+            continue;
+        };
+        // "a/_rels/b.xml.rels" là quan hệ của part "a/b.xml"
+        let name = path.file_stem().unwrap_or_default();
+        let owner = dir.join(name);
+        for rel in rels::read_rels(&owner)?.iter().filter(|r| !r.external) {
+            parts.extend(rels::resolve_target(root, &owner, &rel.target));
+        }
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RELS: &str =
+        r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#;
+    const CHART: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships/chart";
+
+    fn write(root: &Path, name: &str, data: &str) {
+        let path = root.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn shared_parts_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        // drawing1 dùng chart1 và chart2; drawing2 cũng dùng chart2; chart1 dùng style1
+        for (name, targets) in [
+            ("drawing1", &["chart1", "chart2"][..]),
+            ("drawing2", &["chart2"][..]),
+        ] {
+            write(root, &format!("xl/drawings/{}.xml", name), "<wsDr/>");
+            let rels: String = targets
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    format!(
+                        r#"<Relationship Id="rId{}" Type="{}" Target="../charts/{}.xml"/>"#,
+                        i + 1,
+                        CHART,
+                        t
+                    )
+                })
+                .collect();
+            write(
+                root,
+                &format!("xl/drawings/_rels/{}.xml.rels", name),
+                &format!("{}{}</Relationships>", RELS, rels),
+            );
+        }
+        write(root, "xl/charts/chart1.xml", "<chartSpace/>");
+        write(root, "xl/charts/chart2.xml", "<chartSpace/>");
+        write(root, "xl/charts/style1.xml", "<chartStyle/>");
+        write(
+            root,
+            "xl/charts/_rels/chart1.xml.rels",
+            &format!(
+                r#"{}<Relationship Id="rId1" Type="{}Style" Target="style1.xml"/></Relationships>"#,
+                RELS, CHART
+            ),
+        );
+
+        // drawing1 bỏ cả hai quan hệ
+        let drawing = root.join("xl/drawings/drawing1.xml");
+        let all = rels::read_rels(&drawing).unwrap();
+        fs::remove_file(rels::rels_path_for(&drawing)).unwrap();
+        let dropped: Vec<&rels::Relationship> = all.iter().collect();
+        let mut removed = remove_unreferenced(root, &drawing, &dropped).unwrap();
+        removed.sort();
+
+        assert_eq!(
+            removed,
+            vec![
+                root.join("xl/charts/chart1.xml"),
+                root.join("xl/charts/style1.xml")
+            ]
+        );
+        assert!(root.join("xl/charts/chart2.xml").exists());
+        assert!(!root.join("xl/charts/_rels").exists());
+    }
+
+    #[test]
+    fn chartsheet_drops_unused_relationships() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let sheet = root.join("xl/chartsheets/sheet1.xml");
+        write(
+            root,
+            "xl/chartsheets/sheet1.xml",
+            r#"<chartsheet><drawing r:id="rId1"/></chartsheet>"#,
+        );
+        write(
+            root,
+            "xl/chartsheets/_rels/sheet1.xml.rels",
+            &format!(
+                concat!(
+                    r#"{}<Relationship Id="rId1" Type="http://x/drawing" Target="../drawings/drawing1.xml"/>"#,
+                    r#"<Relationship Id="rId2" Type="http://x/printerSettings" Target="../printerSettings/printerSettings1.bin"/>"#,
+                    r#"</Relationships>"#
+                ),
+                RELS
+            ),
+        );
+        write(root, "xl/printerSettings/printerSettings1.bin", "");
+        write(
+            root,
+            "xl/drawings/drawing1.xml",
+            r#"<wsDr><graphicFrame><chart r:id="rId1"/></graphicFrame></wsDr>"#,
+        );
+        write(
+            root,
+            "xl/drawings/_rels/drawing1.xml.rels",
+            &format!(
+                concat!(
+                    r#"{}<Relationship Id="rId1" Type="{}" Target="../charts/chart1.xml"/>"#,
+                    r#"<Relationship Id="rId2" Type="http://x/image" Target="../media/image1.png"/>"#,
+                    r#"</Relationships>"#
+                ),
+                RELS, CHART
+            ),
+        );
+        write(root, "xl/charts/chart1.xml", "<chartSpace/>");
+        write(root, "xl/media/image1.png", "");
+
+        let mut removed = clean_sheet_parts(root, &sheet).unwrap();
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                root.join("xl/media/image1.png"),
+                root.join("xl/printerSettings/printerSettings1.bin"),
+            ]
+        );
+        assert!(root.join("xl/charts/chart1.xml").exists());
+        let ids: Vec<String> = rels::read_rels(&sheet)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, ["rId1"]);
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::Result;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::{attr_value, local_name, replace_attr};
+
+const PACKAGE_RELS_NS: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+
+#[derive(Debug, Clone)]
+pub struct Relationship {
+    pub id: String,
+    pub rel_type: String,
+    pub target: String,
+    pub external: bool,
+}
+
+impl Relationship {
+    /// So khớp theo phần cuối của Type, vd "table", "hyperlink", "drawing". Với các quan hệ của
+    /// SpreadsheetML phần này giống nhau giữa Transitional
+    /// (`http://schemas.openxmlformats.org/officeDocument/2006/relationships/…`) và Strict
+    /// (`http://purl.oclc.org/ooxml/officeDocument/relationships/…`); riêng thuộc tính tài liệu
+    /// thì khác (`extended-properties`/`extendedProperties`, `custom-properties`/`customProperties`)
+    pub fn is(&self, kind: &str) -> bool {
+        self.rel_type.rsplit('/').next() == Some(kind)
+    }
+}
+
+/// "xl/worksheets/sheet1.xml" -> "xl/worksheets/_rels/sheet1.xml.rels"
+pub fn rels_path_for(part: &Path) -> PathBuf {
+    let name = part
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    part.parent()
+        .unwrap_or(Path::new(""))
+        .join("_rels")
+        .join(format!("{}.rels", name))
+}
+
+/// Đọc danh sách quan hệ của một part; không có file .rels thì trả về rỗng
+pub fn read_rels(part: &Path) -> Result<Vec<Relationship>> {
+    read_rels_file(&rels_path_for(part))
+}
+
+/// Part chính của workbook (thường là xl/workbook.xml), lấy từ _rels/.rels
+pub fn workbook_part(root: &Path) -> Result<PathBuf> {
+    let part = read_rels_file(&root.join("_rels/.rels"))?
+        .into_iter()
+        .find(|r| r.is("officeDocument") && !r.external)
+        .and_then(|r| resolve_in(root, Path::new(""), &r.target));
+    Ok(part.unwrap_or_else(|| root.join("xl/workbook.xml")))
+}
+
+/// Đọc thẳng một file .rels (kể cả `_rels/.rels` của package, không có part sở hữu)
+pub fn read_rels_file(path: &Path) -> Result<Vec<Relationship>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut reader = Reader::from_file(path)?;
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+    let mut out = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e)
+                if local_name(e.name().as_ref()) == "Relationship" =>
+            {
+                out.push(Relationship {
+                    id: attr_value(e, b"Id").unwrap_or_default(),
+                    rel_type: attr_value(e, b"Type").unwrap_or_default(),
+                    target: attr_value(e, b"Target").unwrap_or_default(),
+                    external: attr_value(e, b"TargetMode").as_deref() == Some("External"),
+                });
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(out)
+}
+
+/// Đường dẫn thật của target (tương đối theo thư mục của part, hoặc tuyệt đối từ gốc package).
+/// None nếu target thoát ra ngoài `root` ("../../x", "/../x") hoặc part không nằm trong `root`.
+pub fn resolve_target(root: &Path, part: &Path, target: &str) -> Option<PathBuf> {
+    let dir = part.parent()?.strip_prefix(root).ok()?;
+    resolve_in(root, dir, target)
+}
+
+/// `target` tính từ thư mục `dir` (tương đối theo `root`)
+fn resolve_in(root: &Path, dir: &Path, target: &str) -> Option<PathBuf> {
+    let joined = match target.strip_prefix('/') {
+        Some(abs) => PathBuf::from(abs),
+        None => dir.join(target),
+    };
+    // chuẩn hoá "..": "xl/worksheets/../tables/t.xml" -> "xl/tables/t.xml"
+    let mut norm = PathBuf::new();
+    for comp in joined.components() {
+        match comp {
+            Component::Normal(c) => norm.push(c),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !norm.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!norm.as_os_str().is_empty()).then(|| root.join(norm))
+}
+
+/// Xoá các Relationship có Id trong `ids` khỏi file .rels của part
+pub fn remove_rels(part: &Path, ids: &HashSet<String>) -> Result<()> {
+    remove_rels_file(&rels_path_for(part), ids)
+}
+
+/// Xoá các Relationship có Id trong `ids` khỏi file .rels `path`
+pub fn remove_rels_file(path: &Path, ids: &HashSet<String>) -> Result<()> {
+    if ids.is_empty() || !path.exists() {
+        return Ok(());
+    }
+    let tmp_out = path.with_extension("rels.out");
+    {
+        let mut reader = Reader::from_file(path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Empty(ref e)
+                    if local_name(e.name().as_ref()) == "Relationship"
+                        && attr_value(e, b"Id").is_some_and(|id| ids.contains(&id)) => {}
+                Event::Start(ref e)
+                    if local_name(e.name().as_ref()) == "Relationship"
+                        && attr_value(e, b"Id").is_some_and(|id| ids.contains(&id)) =>
+                {
+                    reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, path)?;
+    Ok(())
+}
+
+/// Đổi Type của Relationship `id` trong .rels của part
+pub fn set_rel_type(part: &Path, id: &str, rel_type: &str) -> Result<()> {
+    let path = rels_path_for(part);
+    let tmp_out = path.with_extension("rels.out");
+    {
+        let mut reader = Reader::from_file(&path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Empty(ref e)
+                    if local_name(e.name().as_ref()) == "Relationship"
+                        && attr_value(e, b"Id").as_deref() == Some(id) =>
+                {
+                    writer.write_event(Event::Empty(replace_attr(e, b"Type", Some(rel_type))))?;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, &path)?;
+    Ok(())
+}
+
+/// Thêm một Relationship mới vào .rels của part (tạo file nếu chưa có), trả về Id mới
+pub fn add_rel(part: &Path, rel_type: &str, target: &str) -> Result<String> {
+    let path = rels_path_for(part);
+    let existing = read_rels_file(&path)?;
+    let id = (1..)
+        .map(|n| format!("rId{}", n))
+        .find(|id| !existing.iter().any(|r| &r.id == id))
+        .unwrap_or_default();
+    let mut rel = BytesStart::new("Relationship");
+    rel.push_attribute(("Id", id.as_str()));
+    rel.push_attribute(("Type", rel_type));
+    rel.push_attribute(("Target", target));
+
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = Writer::new(BufWriter::new(File::create(&path)?));
+        writer.write_event(Event::Decl(BytesDecl::new(
+            "1.0",
+            Some("UTF-8"),
+            Some("yes"),
+        )))?;
+        let mut root = BytesStart::new("Relationships");
+        root.push_attribute(("xmlns", PACKAGE_RELS_NS));
+        writer.write_event(Event::Start(root))?;
+        writer.write_event(Event::Empty(rel))?;
+        writer.write_event(Event::End(BytesEnd::new("Relationships")))?;
+        writer.into_inner().flush()?;
+        return Ok(id);
+    }
+
+    let tmp_out = path.with_extension("rels.out");
+    {
+        let mut reader = Reader::from_file(&path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::End(e) if local_name(e.name().as_ref()) == "Relationships" => {
+                    writer.write_event(Event::Empty(rel.borrow()))?;
+                    writer.write_event(Event::End(e))?;
+                }
+                // <Relationships/> rỗng
+                Event::Empty(e) if local_name(e.name().as_ref()) == "Relationships" => {
+                    let end = e.to_end().into_owned();
+                    writer.write_event(Event::Start(e))?;
+                    writer.write_event(Event::Empty(rel.borrow()))?;
+                    writer.write_event(Event::End(end))?;
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, &path)?;
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_resolve_inside_the_package() {
+        let root = Path::new("/tmp/pkg");
+        let sheet = root.join("xl/worksheets/sheet1.xml");
+        assert_eq!(
+            resolve_target(root, &sheet, "../tables/table1.xml"),
+            Some(root.join("xl/tables/table1.xml"))
+        );
+        assert_eq!(
+            resolve_target(root, &sheet, "/xl/./calcChain.xml"),
+            Some(root.join("xl/calcChain.xml"))
+        );
+    }
+
+    #[test]
+    fn targets_escaping_the_root_are_rejected() {
+        let root = Path::new("/tmp/pkg");
+        let sheet = root.join("xl/worksheets/sheet1.xml");
+        assert_eq!(resolve_target(root, &sheet, "../../../secret.xml"), None);
+        assert_eq!(resolve_target(root, &sheet, "/../secret.xml"), None);
+        assert_eq!(resolve_target(root, &sheet, "/"), None);
+        // part nằm ngoài root
+        assert_eq!(resolve_target(root, Path::new("/etc/x.xml"), "a.xml"), None);
+    }
+
+    #[test]
+    fn remove_rels_keeps_other_relationships() {
+        let dir = tempfile::tempdir().unwrap();
+        let part = dir.path().join("xl/worksheets/sheet1.xml");
+        let rels = rels_path_for(&part);
+        fs::create_dir_all(rels.parent().unwrap()).unwrap();
+        fs::write(
+            &rels,
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://x/hyperlink" Target="http://a" TargetMode="External"/>"#,
+                r#"<Relationship Id="rId2" Type="http://x/table" Target="../tables/table1.xml"/>"#,
+                r#"</Relationships>"#
+            ),
+        )
+        .unwrap();
+        remove_rels(&part, &HashSet::from(["rId1".to_string()])).unwrap();
+        let left = read_rels(&part).unwrap();
+        assert_eq!(left.len(), 1);
+        assert!(left[0].is("table") && !left[0].external);
+    }
+
+    #[test]
+    fn add_rel_picks_a_free_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let part = dir.path().join("xl/workbook.xml");
+        assert_eq!(
+            add_rel(&part, "http://x/styles", "styles.xml").unwrap(),
+            "rId1"
+        );
+        let second = add_rel(
+            &part,
+            &crate::Conformance::Strict.rel_type("sharedStrings"),
+            "sharedStrings.xml",
+        )
+        .unwrap();
+        assert_eq!(second, "rId2");
+        let all = read_rels(&part).unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(all[1].is("sharedStrings") && all[1].target == "sharedStrings.xml");
+    }
+}
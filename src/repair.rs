@@ -0,0 +1,1198 @@
+use anyhow::{bail, Result};
+use flate2::{Crc, Decompress, FlushDecompress, Status};
+use memchr::memmem;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::conformance::Conformance;
+use crate::workbook::{self, SheetKind};
+use crate::{attr_value, content_types, local_name, rel_id_attr, rels, replace_attr, sst, xlsb};
+
+const LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+const DATA_DESCRIPTOR: &[u8] = b"PK\x07\x08";
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+/// bit 3: CRC và kích thước nằm trong data descriptor sau dữ liệu, trong header để 0
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+/// Kích thước trong header có thể sai (file hỏng): cấp phát trước tối đa chừng này
+const MAX_PREALLOC: usize = 64 << 20;
+
+const WORKSHEET_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml";
+/// Quan hệ của OPC (ISO/IEC 29500-2), giống nhau ở Transitional và Strict
+const CORE_PROPERTIES_REL: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties";
+
+/// Một entry đọc từ local file header
+struct LocalEntry {
+    name: String,
+    data: Vec<u8>,
+    /// giải nén hết và CRC khớp
+    complete: bool,
+    /// vị trí ngay sau dữ liệu nén
+    end: usize,
+}
+
+/// Giải nén bằng cách quét các local file header, dùng khi central directory hỏng hoặc bị
+/// cắt mất. Entry hỏng/cắt cụt: part XML giữ phần đọc được để cứu ở `repair_package`,
+/// part khác bị bỏ. Trả về mô tả các entry bị mất hoặc không nguyên vẹn.
+pub fn salvage_zip(input: &Path, dest: &Path) -> Result<Vec<String>> {
+    let data = fs::read(input)?;
+    let mut entries: Vec<LocalEntry> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut pos = 0;
+    while let Some(found) = memmem::find(&data[pos..], LOCAL_HEADER) {
+        let off = pos + found;
+        let Some(entry) = read_local_entry(&data, off) else {
+            pos = off + LOCAL_HEADER.len();
+            continue;
+        };
+        pos = entry.end.max(off + LOCAL_HEADER.len());
+        // cùng tên xuất hiện nhiều lần (file được ghi nối): giữ bản nguyên vẹn sau cùng
+        match index.get(&entry.name) {
+            Some(&i) if entries[i].complete && !entry.complete => {}
+            Some(&i) => entries[i] = entry,
+            None => {
+                index.insert(entry.name.clone(), entries.len());
+                entries.push(entry);
+            }
+        }
+    }
+    if entries.is_empty() {
+        bail!("không tìm thấy local file header nào");
+    }
+
+    let mut report = Vec::new();
+    for entry in entries {
+        let Some(relative) = entry_path(&entry.name) else {
+            report.push(format!("{}: đường dẫn không hợp lệ, đã bỏ", entry.name));
+            continue;
+        };
+        let out_path = dest.join(relative);
+        if entry.name.ends_with('/') {
+            fs::create_dir_all(&out_path)?;
+            continue;
+        }
+        let keep = entry.complete || (is_xml(&out_path) && !entry.data.is_empty());
+        if !entry.complete {
+            report.push(match keep {
+                true => format!(
+                    "{}: bị cắt cụt, giữ {} byte giải nén được",
+                    entry.name,
+                    entry.data.len()
+                ),
+                false => format!("{}: hỏng, đã bỏ", entry.name),
+            });
+        }
+        if keep {
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&out_path, &entry.data)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Đường dẫn tương đối của entry trong thư mục giải nén. Chỉ nhận các thành phần thường;
+/// `..`, đường dẫn tuyệt đối hay ổ đĩa (`/etc/…`, `C:\…`) bị từ chối vì sẽ ghi ra ngoài
+/// thư mục tạm.
+fn entry_path(name: &str) -> Option<&Path> {
+    let path = Path::new(name);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then_some(path)
+}
+
+fn read_local_entry(data: &[u8], off: usize) -> Option<LocalEntry> {
+    let h = data.get(off..off + 30)?;
+    let u16_at = |i: usize| u16::from_le_bytes([h[i], h[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes(h[i..i + 4].try_into().unwrap());
+    let (flags, method, crc) = (u16_at(6), u16_at(8), u32_at(14));
+    let (size, raw_size) = (u32_at(18), u32_at(22));
+    let name_end = off + 30 + u16_at(26) as usize;
+    let name = std::str::from_utf8(data.get(off + 30..name_end)?).ok()?;
+    if name.is_empty() || name.contains('\0') {
+        return None;
+    }
+    let start = (name_end + u16_at(28) as usize).min(data.len());
+    let rest = &data[start..];
+    // kích thước trong header không dùng được khi có data descriptor hoặc ZIP64
+    let has_descriptor = flags & FLAG_DATA_DESCRIPTOR != 0;
+    let known_size = (!has_descriptor && size != u32::MAX).then_some(size as usize);
+    let known_raw_size = (!has_descriptor && raw_size != u32::MAX).then_some(raw_size as usize);
+
+    let (out, used, ended) = match (method, known_size) {
+        (METHOD_DEFLATE, _) => inflate(rest, known_raw_size),
+        (METHOD_STORED, Some(n)) => {
+            let n_read = n.min(rest.len());
+            (rest[..n_read].to_vec(), n_read, n_read == n)
+        }
+        (METHOD_STORED, None) => match memmem::find(rest, DATA_DESCRIPTOR) {
+            Some(n) => (rest[..n].to_vec(), n, true),
+            None => (rest.to_vec(), rest.len(), false),
+        },
+        _ => (Vec::new(), 0, false),
+    };
+    let expected_crc = match has_descriptor {
+        false => Some(crc),
+        true => {
+            let desc = &rest[used..];
+            let desc = desc.strip_prefix(DATA_DESCRIPTOR).unwrap_or(desc);
+            desc.get(..4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+        }
+    };
+    let mut sum = Crc::new();
+    sum.update(&out);
+    Some(LocalEntry {
+        name: name.to_string(),
+        complete: ended && expected_crc.is_none_or(|c| c == sum.sum()),
+        data: out,
+        end: start + used,
+    })
+}
+
+/// Giải nén raw deflate đến cuối stream: (dữ liệu, số byte nén đã đọc, stream kết thúc đúng).
+/// Dữ liệu hỏng hoặc bị cắt thì trả về phần đã giải nén được. `size_hint` là kích thước giải
+/// nén trong local header (nếu có), chỉ dùng để cấp phát trước.
+fn inflate(input: &[u8], size_hint: Option<usize>) -> (Vec<u8>, usize, bool) {
+    let mut d = Decompress::new(false);
+    let mut out = Vec::with_capacity(size_hint.unwrap_or(1 << 16).min(MAX_PREALLOC));
+    loop {
+        if out.len() == out.capacity() {
+            out.reserve(out.capacity().max(1 << 16));
+        }
+        let (before_in, before_out) = (d.total_in(), d.total_out());
+        let status = d.decompress_vec(
+            &input[d.total_in() as usize..],
+            &mut out,
+            FlushDecompress::None,
+        );
+        match status {
+            Ok(Status::StreamEnd) => return (out, d.total_in() as usize, true),
+            Ok(_) if d.total_in() != before_in || d.total_out() != before_out => {}
+            _ => return (out, d.total_in() as usize, false),
+        }
+    }
+}
+
+fn is_xml(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|e| e == "xml" || e == "rels")
+}
+
+/// Tình trạng một part XML
+enum XmlState {
+    Intact,
+    /// bị cắt: phần còn nguyên vẹn, đã đóng các thẻ còn mở
+    Salvaged {
+        xml: Vec<u8>,
+        /// `r` của hàng hoàn chỉnh cuối cùng (với sheet)
+        last_row: Option<String>,
+        /// số byte bị bỏ ở cuối
+        lost: usize,
+    },
+    Unusable,
+}
+
+/// Điểm cắt an toàn gần nhất khi đọc part bị hỏng
+struct CutPoint {
+    pos: usize,
+    /// các thẻ đang mở tại `pos`
+    open: Vec<Vec<u8>>,
+    last_row: Option<String>,
+    /// đã gặp phần tử con bắt buộc của gốc
+    has_required: bool,
+}
+
+/// Kiểm tra part XML; nếu hỏng thì cắt tại điểm cuối cùng mà độ sâu còn <= `keep_depth`
+/// (với sheet: sau `</row>` cuối cùng trong `<sheetData>`) rồi đóng các thẻ đang mở.
+/// `required` là phần tử con bắt buộc của gốc, được thêm rỗng nếu file bị cắt trước nó.
+fn check_xml(data: &[u8], keep_depth: usize, required: Option<&str>) -> XmlState {
+    let mut reader = Reader::from_reader(data);
+    let mut buf = Vec::new();
+    let mut stack: Vec<Vec<u8>> = Vec::new();
+    let (mut seen_root, mut seen_required) = (false, false);
+    let (mut row, mut last_row) = (None::<String>, None::<String>);
+    let mut cut: Option<CutPoint> = None;
+    while let Ok(ev) = reader.read_event_into(&mut buf) {
+        match &ev {
+            Event::Start(e) | Event::Empty(e) => {
+                let name = e.name();
+                let tag = local_name(name.as_ref());
+                seen_root = true;
+                if stack.len() == 1 && required == Some(tag) {
+                    seen_required = true;
+                }
+                if tag == "row" {
+                    row = attr_value(e, b"r");
+                    if matches!(ev, Event::Empty(_)) {
+                        last_row = row.take();
+                    }
+                }
+                if matches!(ev, Event::Start(_)) {
+                    stack.push(e.name().as_ref().to_vec());
+                }
+            }
+            Event::End(e) => {
+                stack.pop();
+                if local_name(e.name().as_ref()) == "row" {
+                    last_row = row.take();
+                }
+            }
+            Event::Eof if stack.is_empty() && seen_root => return XmlState::Intact,
+            Event::Eof => break,
+            _ => {}
+        }
+        if !stack.is_empty() && stack.len() <= keep_depth {
+            cut = Some(CutPoint {
+                pos: reader.buffer_position() as usize,
+                open: stack.clone(),
+                last_row: last_row.clone(),
+                has_required: seen_required,
+            });
+        }
+        buf.clear();
+    }
+
+    let Some(CutPoint {
+        pos,
+        open,
+        last_row,
+        has_required,
+    }) = cut
+    else {
+        return XmlState::Unusable;
+    };
+    let mut xml = data[..pos].to_vec();
+    for (depth, name) in open.iter().enumerate().rev() {
+        if let (0, Some(tag), false) = (depth, required, has_required) {
+            // cùng prefix với phần tử gốc, vd "x:worksheet" -> "x:sheetData"
+            let prefix = match name.iter().position(|&b| b == b':') {
+                Some(i) => &name[..=i],
+                None => &[],
+            };
+            xml.extend_from_slice(&[b"<", prefix, tag.as_bytes(), b"/>"].concat());
+        }
+        xml.extend_from_slice(&[b"</", name.as_slice(), b">"].concat());
+    }
+    XmlState::Salvaged {
+        xml,
+        last_row,
+        lost: data.len() - pos,
+    }
+}
+
+/// "xl/worksheets/sheet1.xml" (đường dẫn trong package, dùng trong báo cáo)
+fn label(root: &Path, path: &Path) -> String {
+    content_types::part_name(root, path)
+        .trim_start_matches('/')
+        .to_string()
+}
+
+fn is_sheet_part(path: &Path) -> bool {
+    let dir = path.parent().and_then(|d| d.file_name());
+    dir.is_some_and(|d| d == "worksheets" || d == "macrosheets")
+        && path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("xml"))
+}
+
+fn is_shared_strings(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| {
+        n.to_string_lossy()
+            .eq_ignore_ascii_case("sharedStrings.xml")
+    })
+}
+
+/// Sửa package OOXML đã giải nén để Excel mở được: cứu sheet và sharedStrings bị cắt, bỏ part
+/// XML hỏng, dựng lại [Content_Types].xml và _rels/.rels, thay sheet bị mất bằng sheet trống,
+/// bỏ quan hệ và `r:id` trỏ tới part không còn. Trả về mô tả những gì đã mất.
+pub fn repair_package(root: &Path) -> Result<Vec<String>> {
+    let mut report = Vec::new();
+    let mut strings_damaged = false;
+
+    // 1) part XML hỏng: sheet cắt sau hàng hoàn chỉnh cuối, sharedStrings sau <si> cuối,
+    //    part khác không cứu được thì bỏ
+    let parts: Vec<PathBuf> = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_xml(e.path()))
+        .map(|e| e.into_path())
+        .collect();
+    for path in parts {
+        let name = label(root, &path);
+        let (keep_depth, required) = if is_sheet_part(&path) {
+            (2, Some("sheetData"))
+        } else if is_shared_strings(&path) {
+            (1, None)
+        } else {
+            (0, None)
+        };
+        match check_xml(&fs::read(&path)?, keep_depth, required) {
+            XmlState::Intact => {}
+            XmlState::Salvaged {
+                xml,
+                last_row,
+                lost,
+            } if keep_depth == 2 => {
+                fs::write(&path, xml)?;
+                report.push(match last_row {
+                    Some(r) => format!(
+                        "{}: bị cắt, giữ đến hàng {} (mất {} byte cuối)",
+                        name, r, lost
+                    ),
+                    None => format!(
+                        "{}: bị cắt trước hàng đầu tiên (mất {} byte cuối)",
+                        name, lost
+                    ),
+                });
+            }
+            XmlState::Salvaged { xml, lost, .. } => {
+                // count/uniqueCount cũ không còn đúng, Excel tự tính lại khi thiếu
+                fs::write(&path, drop_root_attrs(&xml, &[b"count", b"uniqueCount"])?)?;
+                strings_damaged = true;
+                report.push(format!("{}: bị cắt, mất {} byte cuối", name, lost));
+            }
+            _ if path.file_name().is_some_and(|n| n == "workbook.xml") => {
+                bail!("{}: XML hỏng, không sửa được", name);
+            }
+            _ => {
+                fs::remove_file(&path)?;
+                strings_damaged |= is_shared_strings(&path);
+                report.push(format!("{}: XML hỏng, đã bỏ", name));
+            }
+        }
+    }
+
+    // 2) [Content_Types].xml và _rels/.rels bị mất thì dựng lại từ các part còn lại
+    if !root.join("[Content_Types].xml").exists() {
+        write_content_types(root)?;
+        report.push("[Content_Types].xml bị mất, đã dựng lại theo các part còn lại".to_string());
+    }
+    if !root.join("_rels/.rels").exists() {
+        write_package_rels(root)?;
+        report.push("_rels/.rels bị mất, đã dựng lại".to_string());
+    }
+    let workbook_xml = rels::workbook_part(root)?;
+    if !workbook_xml.exists() {
+        bail!("{} bị mất, không sửa được", label(root, &workbook_xml));
+    }
+    let binary = xlsb::is_binary_workbook(&workbook_xml);
+    if !rels::rels_path_for(&workbook_xml).exists() {
+        bail!(
+            "{} bị mất, không xác định được part của các sheet",
+            label(root, &rels::rels_path_for(&workbook_xml))
+        );
+    }
+
+    // 3) sheet mất hẳn: thay bằng worksheet trống để thứ tự sheet (localSheetId...) giữ nguyên
+    if !binary {
+        let conformance = Conformance::detect(&workbook_xml)?;
+        let names: HashMap<PathBuf, String> = workbook::read_sheets(root, &workbook_xml)?
+            .into_iter()
+            .map(|s| (s.path, s.name))
+            .collect();
+        for rel in rels::read_rels(&workbook_xml)?
+            .iter()
+            .filter(|r| !r.external)
+        {
+            if SheetKind::from_rel(rel).is_none() {
+                continue;
+            }
+            let Some(path) = rels::resolve_target(root, &workbook_xml, &rel.target) else {
+                continue;
+            };
+            if path.exists() {
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(
+                &path,
+                format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+                     <worksheet xmlns=\"{}\"><sheetData/></worksheet>",
+                    conformance.main_ns()
+                ),
+            )?;
+            if !rel.is("worksheet") {
+                rels::set_rel_type(&workbook_xml, &rel.id, &conformance.rel_type("worksheet"))?;
+            }
+            content_types::remove_overrides(root, std::slice::from_ref(&path))?;
+            content_types::add_override(root, &path, WORKSHEET_TYPE)?;
+            report.push(format!(
+                "sheet '{}' ({}) bị mất, thay bằng sheet trống",
+                names.get(&path).map(String::as_str).unwrap_or("?"),
+                label(root, &path)
+            ));
+        }
+    }
+
+    // 4) quan hệ trỏ tới part không còn
+    let rels_files: Vec<PathBuf> = WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| p.extension().is_some_and(|x| x == "rels"))
+        .collect();
+    for rels_file in rels_files {
+        let Some(dir) = rels_file.parent().and_then(|d| d.parent()) else {
+            continue;
+        };
+        // "a/_rels/b.xml.rels" là quan hệ của part "a/b.xml"
+        let owner = dir.join(rels_file.file_stem().unwrap_or_default());
+        let mut dangling = HashSet::new();
+        for rel in rels::read_rels_file(&rels_file)?
+            .iter()
+            .filter(|r| !r.external)
+        {
+            // target thoát ra ngoài package cũng coi như part đã mất
+            let target = rels::resolve_target(root, &owner, &rel.target);
+            if target.as_ref().is_some_and(|t| t.exists()) {
+                continue;
+            }
+            strings_damaged |= rel.is("sharedStrings");
+            report.push(format!(
+                "{}: bỏ quan hệ {} tới {} (part đã mất)",
+                label(root, &rels_file),
+                rel.rel_type.rsplit('/').next().unwrap_or_default(),
+                target
+                    .map(|t| label(root, &t))
+                    .unwrap_or_else(|| rel.target.clone())
+            ));
+            dangling.insert(rel.id.clone());
+        }
+        rels::remove_rels_file(&rels_file, &dangling)?;
+    }
+
+    // 5) Override của part không còn
+    let missing: Vec<PathBuf> = overrides(root)?
+        .into_iter()
+        .filter(|p| !p.exists())
+        .collect();
+    content_types::remove_overrides(root, &missing)?;
+
+    if binary {
+        return Ok(report);
+    }
+
+    // 6) phần tử của sheet trỏ tới quan hệ đã bỏ (drawing, hyperlink, tablePart...), và ô
+    //    chuỗi trỏ ra ngoài sharedStrings bị cắt/mất
+    let sheets = workbook::read_sheets(root, &workbook_xml)?;
+    let string_count = match strings_damaged {
+        true => {
+            let sst_path = rels::read_rels(&workbook_xml)?
+                .into_iter()
+                .find(|r| r.is("sharedStrings") && !r.external)
+                .and_then(|r| rels::resolve_target(root, &workbook_xml, &r.target));
+            Some(match sst_path {
+                Some(p) if p.exists() => sst::load_blank_flags(&p)?.len(),
+                _ => 0,
+            })
+        }
+        false => None,
+    };
+    for sheet in sheets
+        .iter()
+        .filter(|s| s.kind.has_cells() && s.path.is_file())
+    {
+        let ids: HashSet<String> = rels::read_rels(&sheet.path)?
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        let dropped = drop_dangling_ids(&sheet.path, &ids)?;
+        if dropped > 0 {
+            report.push(format!(
+                "{}: bỏ {} phần tử trỏ tới part đã mất",
+                label(root, &sheet.path),
+                dropped
+            ));
+        }
+        if let Some(count) = string_count {
+            let cleared = clear_missing_strings(&sheet.path, count)?;
+            if cleared > 0 {
+                report.push(format!(
+                    "{}: {} ô chuỗi mất nội dung (sharedStrings bị cắt/mất)",
+                    label(root, &sheet.path),
+                    cleared
+                ));
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Bỏ các thuộc tính `names` của phần tử gốc
+fn drop_root_attrs(xml: &[u8], names: &[&[u8]]) -> Result<Vec<u8>> {
+    let mut reader = Reader::from_reader(xml);
+    let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+    let mut buf = Vec::new();
+    let mut at_root = true;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) if at_root => {
+                at_root = false;
+                let el = names
+                    .iter()
+                    .fold(e.into_owned(), |el, name| replace_attr(&el, name, None));
+                writer.write_event(Event::Start(el))?;
+            }
+            Event::Eof => break,
+            ev => writer.write_event(ev)?,
+        }
+        buf.clear();
+    }
+    Ok(writer.into_inner())
+}
+
+/// Đường dẫn các part khai báo `<Override>` trong [Content_Types].xml
+fn overrides(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut reader = Reader::from_file(root.join("[Content_Types].xml"))?;
+    let mut buf = Vec::new();
+    let mut parts = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e)
+                if local_name(e.name().as_ref()) == "Override" =>
+            {
+                if let Some(name) = attr_value(e, b"PartName") {
+                    parts.push(root.join(name.trim_start_matches('/')));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(parts)
+}
+
+/// Content type theo vị trí part mà Excel dùng; None = để Default theo phần mở rộng
+fn override_type(part: &str, macros: bool) -> Option<&'static str> {
+    macro_rules! sml {
+        ($t:literal) => {
+            concat!(
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.",
+                $t
+            )
+        };
+    }
+    const TYPES: &[(&str, &str)] = &[
+        ("xl/worksheets/", sml!("worksheet+xml")),
+        ("xl/chartsheets/", sml!("chartsheet+xml")),
+        ("xl/dialogsheets/", sml!("dialogsheet+xml")),
+        ("xl/macrosheets/", "application/vnd.ms-excel.macrosheet+xml"),
+        ("xl/styles.xml", sml!("styles+xml")),
+        ("xl/sharedStrings.xml", sml!("sharedStrings+xml")),
+        ("xl/calcChain.xml", sml!("calcChain+xml")),
+        ("xl/connections.xml", sml!("connections+xml")),
+        ("xl/tables/", sml!("table+xml")),
+        ("xl/comments", sml!("comments+xml")),
+        ("xl/pivotTables/", sml!("pivotTable+xml")),
+        (
+            "xl/pivotCache/pivotCacheDefinition",
+            sml!("pivotCacheDefinition+xml"),
+        ),
+        (
+            "xl/pivotCache/pivotCacheRecords",
+            sml!("pivotCacheRecords+xml"),
+        ),
+        ("xl/externalLinks/", sml!("externalLink+xml")),
+        ("xl/queryTables/", sml!("queryTable+xml")),
+        (
+            "xl/theme/",
+            "application/vnd.openxmlformats-officedocument.theme+xml",
+        ),
+        (
+            "xl/drawings/drawing",
+            "application/vnd.openxmlformats-officedocument.drawing+xml",
+        ),
+        (
+            "xl/charts/chart",
+            "application/vnd.openxmlformats-officedocument.drawingml.chart+xml",
+        ),
+        (
+            "xl/charts/style",
+            "application/vnd.ms-office.chartstyle+xml",
+        ),
+        (
+            "xl/charts/colors",
+            "application/vnd.ms-office.chartcolorstyle+xml",
+        ),
+        ("xl/persons/", "application/vnd.ms-excel.person+xml"),
+        (
+            "xl/threadedComments/",
+            "application/vnd.ms-excel.threadedcomments+xml",
+        ),
+        ("xl/vbaProject.bin", "application/vnd.ms-office.vbaProject"),
+        (
+            "docProps/core.xml",
+            "application/vnd.openxmlformats-package.core-properties+xml",
+        ),
+        (
+            "docProps/app.xml",
+            "application/vnd.openxmlformats-officedocument.extended-properties+xml",
+        ),
+        (
+            "docProps/custom.xml",
+            "application/vnd.openxmlformats-officedocument.custom-properties+xml",
+        ),
+        (
+            "customXml/itemProps",
+            "application/vnd.openxmlformats-officedocument.customXmlProperties+xml",
+        ),
+    ];
+    if part == "xl/workbook.xml" {
+        return Some(match macros {
+            true => "application/vnd.ms-excel.sheet.macroEnabled.main+xml",
+            false => sml!("sheet.main+xml"),
+        });
+    }
+    if part.contains("/_rels/") || part.ends_with(".rels") {
+        return None;
+    }
+    TYPES
+        .iter()
+        .find(|(prefix, _)| part.starts_with(prefix))
+        .map(|(_, t)| *t)
+}
+
+fn default_type(ext: &str) -> &'static str {
+    match ext {
+        "rels" => "application/vnd.openxmlformats-package.relationships+xml",
+        "xml" => "application/xml",
+        "vml" => "application/vnd.openxmlformats-officedocument.vmlDrawing",
+        "bin" => "application/vnd.openxmlformats-officedocument.spreadsheetml.printerSettings",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "emf" => "image/x-emf",
+        "wmf" => "image/x-wmf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_content_types(root: &Path) -> Result<()> {
+    let parts: Vec<String> = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| label(root, e.path()))
+        .collect();
+    let macros = parts.iter().any(|p| p == "xl/vbaProject.bin");
+    let mut exts: Vec<String> = vec!["rels".to_string(), "xml".to_string()];
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">",
+    );
+    let mut overrides = String::new();
+    for part in &parts {
+        match override_type(part, macros) {
+            Some(t) => overrides.push_str(&format!(
+                "<Override PartName=\"/{}\" ContentType=\"{}\"/>",
+                part, t
+            )),
+            None => {
+                let ext = Path::new(part)
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_ascii_lowercase())
+                    .unwrap_or_default();
+                if !ext.is_empty() && !exts.contains(&ext) {
+                    exts.push(ext);
+                }
+            }
+        }
+    }
+    for ext in &exts {
+        xml.push_str(&format!(
+            "<Default Extension=\"{}\" ContentType=\"{}\"/>",
+            ext,
+            default_type(ext)
+        ));
+    }
+    xml.push_str(&overrides);
+    xml.push_str("</Types>");
+    fs::write(root.join("[Content_Types].xml"), xml)?;
+    Ok(())
+}
+
+fn write_package_rels(root: &Path) -> Result<()> {
+    let workbook = ["xl/workbook.xml", "xl/workbook.bin"]
+        .into_iter()
+        .find(|p| root.join(p).exists());
+    let Some(workbook) = workbook else {
+        bail!("_rels/.rels và workbook đều bị mất, không sửa được");
+    };
+    let conformance = Conformance::detect(&root.join(workbook))?;
+    let mut targets = vec![(conformance.rel_type("officeDocument"), workbook)];
+    if root.join("docProps/core.xml").exists() {
+        targets.push((CORE_PROPERTIES_REL.to_string(), "docProps/core.xml"));
+    }
+    if root.join("docProps/app.xml").exists() {
+        targets.push((
+            conformance.rel_type("extended-properties"),
+            "docProps/app.xml",
+        ));
+    }
+    if root.join("docProps/custom.xml").exists() {
+        targets.push((
+            conformance.rel_type("custom-properties"),
+            "docProps/custom.xml",
+        ));
+    }
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">",
+    );
+    for (i, (rel_type, target)) in targets.iter().enumerate() {
+        xml.push_str(&format!(
+            "<Relationship Id=\"rId{}\" Type=\"{}\" Target=\"{}\"/>",
+            i + 1,
+            rel_type,
+            target
+        ));
+    }
+    xml.push_str("</Relationships>");
+    fs::create_dir_all(root.join("_rels"))?;
+    fs::write(root.join("_rels/.rels"), xml)?;
+    Ok(())
+}
+
+/// Ghi lại part qua file tạm; `edit` trả về số thay đổi, không có thay đổi thì giữ file cũ
+fn rewrite_part<F>(part: &Path, edit: F) -> Result<usize>
+where
+    F: FnOnce(&mut Reader<std::io::BufReader<File>>, &mut Writer<BufWriter<File>>) -> Result<usize>,
+{
+    let tmp_out = part.with_extension("xml.out");
+    let changed = {
+        let mut reader = Reader::from_file(part)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let changed = edit(&mut reader, &mut writer)?;
+        writer.into_inner().flush()?;
+        changed
+    };
+    match changed {
+        0 => fs::remove_file(&tmp_out)?,
+        _ => fs::rename(&tmp_out, part)?,
+    }
+    Ok(changed)
+}
+
+/// Bỏ các phần tử có `r:id` không còn trong .rels của sheet; `<tableParts>` được đếm lại
+fn drop_dangling_ids(sheet: &Path, ids: &HashSet<String>) -> Result<usize> {
+    let dangling = |e: &BytesStart| rel_id_attr(e).is_some_and(|id| !ids.contains(&id));
+    rewrite_part(sheet, |reader, writer| {
+        let mut buf = Vec::new();
+        let mut dropped = 0;
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Empty(ref e) if dangling(e) => dropped += 1,
+                Event::Start(ref e) if dangling(e) => {
+                    reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                    dropped += 1;
+                }
+                Event::Start(ref e) if local_name(e.name().as_ref()) == "tableParts" => {
+                    let inner = sst::read_inner_xml(reader, e.name().as_ref())?;
+                    let mut parts = Vec::new();
+                    let mut inner_reader = Reader::from_reader(inner.as_slice());
+                    let mut inner_buf = Vec::new();
+                    loop {
+                        match inner_reader.read_event_into(&mut inner_buf)? {
+                            Event::Empty(p) if dangling(&p) => dropped += 1,
+                            Event::Empty(p) => parts.push(p.into_owned()),
+                            Event::Eof => break,
+                            _ => {}
+                        }
+                        inner_buf.clear();
+                    }
+                    if !parts.is_empty() {
+                        let count = parts.len().to_string();
+                        writer.write_event(Event::Start(replace_attr(
+                            e,
+                            b"count",
+                            Some(&count),
+                        )))?;
+                        for p in parts {
+                            writer.write_event(Event::Empty(p))?;
+                        }
+                        writer.write_event(Event::End(e.to_end()))?;
+                    }
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        Ok(dropped)
+    })
+}
+
+/// Ô `t="s"` trỏ tới chuỗi thứ `count` trở đi (đã mất) thành ô trống giữ style
+fn clear_missing_strings(sheet: &Path, count: usize) -> Result<usize> {
+    rewrite_part(sheet, |reader, writer| {
+        let mut buf = Vec::new();
+        let mut cleared = 0;
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e)
+                    if local_name(e.name().as_ref()) == "c"
+                        && attr_value(e, b"t").as_deref() == Some("s") =>
+                {
+                    let inner = sst::read_inner_xml(reader, e.name().as_ref())?;
+                    if string_index(&inner)?.is_some_and(|i| i >= count) {
+                        writer.write_event(Event::Empty(replace_attr(e, b"t", None)))?;
+                        cleared += 1;
+                    } else {
+                        writer.write_event(Event::Start(e.borrow()))?;
+                        writer.get_mut().write_all(&inner)?;
+                        writer.write_event(Event::End(e.to_end()))?;
+                    }
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        Ok(cleared)
+    })
+}
+
+/// Chỉ số chuỗi trong `<v>` của ô
+fn string_index(inner: &[u8]) -> Result<Option<usize>> {
+    let mut reader = Reader::from_reader(inner);
+    let mut buf = Vec::new();
+    let mut in_v = false;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) if local_name(e.name().as_ref()) == "v" => in_v = true,
+            Event::Text(t) if in_v => return Ok(String::from_utf8_lossy(&t).trim().parse().ok()),
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    const MAIN_NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+
+    fn sheet_xml(rows: usize) -> Vec<u8> {
+        let mut xml = format!("<worksheet xmlns=\"{}\"><sheetData>", MAIN_NS);
+        for r in 1..=rows {
+            // giá trị khác nhau để dữ liệu nén không quá ngắn
+            let v = (r as u64).wrapping_mul(2654435761) % 1_000_003;
+            xml.push_str(&format!(
+                "<row r=\"{r}\"><c r=\"A{r}\"><v>{v}</v></c><c r=\"B{r}\"><v>{}</v></c></row>",
+                v * 7
+            ));
+        }
+        xml.push_str("</sheetData></worksheet>");
+        xml.into_bytes()
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 0x2545F491u32;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
+
+    fn zip_bytes(entries: &[(&str, &[u8], CompressionMethod)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data, method) in entries {
+            zip.start_file(
+                *name,
+                SimpleFileOptions::default().compression_method(*method),
+            )
+            .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// Vị trí dữ liệu nén của entry `name` (ngay sau local header)
+    fn data_offset(zip: &[u8], name: &str) -> usize {
+        let u16_at = |i: usize| u16::from_le_bytes([zip[i], zip[i + 1]]) as usize;
+        memmem::find_iter(zip, LOCAL_HEADER)
+            .find(|&off| &zip[off + 30..off + 30 + u16_at(off + 26)] == name.as_bytes())
+            .map(|off| off + 30 + u16_at(off + 26) + u16_at(off + 28))
+            .unwrap()
+    }
+
+    fn salvage(zip: &[u8]) -> (tempfile::TempDir, Vec<String>) {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.xlsx");
+        fs::write(&input, zip).unwrap();
+        let dest = dir.path().join("out");
+        fs::create_dir(&dest).unwrap();
+        let report = salvage_zip(&input, &dest).unwrap();
+        (dir, report)
+    }
+
+    #[test]
+    fn salvage_keeps_entries_before_cut_and_partial_xml() {
+        let types = b"<Types/>".to_vec();
+        let image = noise(300);
+        let sheet = sheet_xml(3000);
+        let zip = zip_bytes(&[
+            ("[Content_Types].xml", &types, CompressionMethod::Deflated),
+            ("xl/media/image1.png", &image, CompressionMethod::Stored),
+            (
+                "xl/worksheets/sheet1.xml",
+                &sheet,
+                CompressionMethod::Deflated,
+            ),
+        ]);
+        let cut = data_offset(&zip, "xl/worksheets/sheet1.xml") + 5000;
+        let (dir, report) = salvage(&zip[..cut]);
+        let out = dir.path().join("out");
+
+        assert_eq!(fs::read(out.join("[Content_Types].xml")).unwrap(), types);
+        assert_eq!(fs::read(out.join("xl/media/image1.png")).unwrap(), image);
+        let partial = fs::read(out.join("xl/worksheets/sheet1.xml")).unwrap();
+        assert!(!partial.is_empty() && partial.len() < sheet.len());
+        assert!(sheet.starts_with(&partial));
+        assert_eq!(report.len(), 1);
+        assert!(report[0].starts_with("xl/worksheets/sheet1.xml: bị cắt cụt"));
+    }
+
+    #[test]
+    fn salvage_drops_truncated_binary_entry() {
+        let image = noise(20_000);
+        let zip = zip_bytes(&[
+            (
+                "xl/workbook.xml",
+                b"<workbook/>",
+                CompressionMethod::Deflated,
+            ),
+            ("xl/media/image1.png", &image, CompressionMethod::Deflated),
+        ]);
+        let cut = data_offset(&zip, "xl/media/image1.png") + 1000;
+        let (dir, report) = salvage(&zip[..cut]);
+        let out = dir.path().join("out");
+
+        assert!(out.join("xl/workbook.xml").exists());
+        assert!(!out.join("xl/media/image1.png").exists());
+        assert_eq!(report, ["xl/media/image1.png: hỏng, đã bỏ"]);
+    }
+
+    #[test]
+    fn salvage_rejects_paths_outside_destination() {
+        let outside = tempfile::tempdir().unwrap();
+        let absolute = outside.path().join("evil.xml");
+        let absolute = absolute.to_str().unwrap();
+        let zip = zip_bytes(&[
+            ("../evil.xml", b"<a/>", CompressionMethod::Stored),
+            (absolute, b"<a/>", CompressionMethod::Stored),
+            ("xl/workbook.xml", b"<workbook/>", CompressionMethod::Stored),
+        ]);
+        let (dir, report) = salvage(&zip);
+
+        assert!(!outside.path().join("evil.xml").exists());
+        assert!(!dir.path().join("evil.xml").exists());
+        assert!(dir.path().join("out/xl/workbook.xml").exists());
+        assert_eq!(report.len(), 2);
+        assert!(report
+            .iter()
+            .all(|r| r.ends_with("đường dẫn không hợp lệ, đã bỏ")));
+    }
+
+    #[test]
+    fn local_entry_with_data_descriptor() {
+        // ghi vào writer không seek được để zip dùng data descriptor
+        let mut zip = ZipWriter::new_stream(Vec::new());
+        zip.start_file("xl/styles.xml", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"<styleSheet/>").unwrap();
+        let data = zip.finish().unwrap().into_inner();
+        let flags = u16::from_le_bytes([data[6], data[7]]);
+        assert_ne!(flags & FLAG_DATA_DESCRIPTOR, 0);
+
+        let entry = read_local_entry(&data, 0).unwrap();
+        assert_eq!(entry.name, "xl/styles.xml");
+        assert_eq!(entry.data, b"<styleSheet/>");
+        assert!(entry.complete);
+    }
+
+    #[test]
+    fn check_xml_intact() {
+        assert!(matches!(
+            check_xml(&sheet_xml(3), 2, Some("sheetData")),
+            XmlState::Intact
+        ));
+    }
+
+    #[test]
+    fn check_xml_rolls_back_to_last_complete_row() {
+        let head = format!(
+            "<worksheet xmlns=\"{}\"><sheetData><row r=\"1\"><c r=\"A1\"><v>1</v></c></row>\
+             <row r=\"2\"><c r=\"A2\"><v>2</v></c></row>",
+            MAIN_NS
+        );
+        let tail = "<row r=\"3\"><c r=\"A3\"><v>3";
+        let data = format!("{}{}", head, tail);
+        let XmlState::Salvaged {
+            xml,
+            last_row,
+            lost,
+        } = check_xml(data.as_bytes(), 2, Some("sheetData"))
+        else {
+            panic!("sheet bị cắt phải cứu được");
+        };
+        assert_eq!(
+            String::from_utf8(xml).unwrap(),
+            format!("{}</sheetData></worksheet>", head)
+        );
+        assert_eq!(last_row.as_deref(), Some("2"));
+        assert_eq!(lost, tail.len());
+    }
+
+    #[test]
+    fn check_xml_adds_missing_sheet_data_with_root_prefix() {
+        let data = format!(
+            "<x:worksheet xmlns:x=\"{}\"><x:dimension ref=\"A1\"/><x:sheetVi",
+            MAIN_NS
+        );
+        let XmlState::Salvaged { xml, last_row, .. } =
+            check_xml(data.as_bytes(), 2, Some("sheetData"))
+        else {
+            panic!("sheet bị cắt phải cứu được");
+        };
+        assert_eq!(
+            String::from_utf8(xml).unwrap(),
+            format!(
+                "<x:worksheet xmlns:x=\"{}\"><x:dimension ref=\"A1\"/><x:sheetData/></x:worksheet>",
+                MAIN_NS
+            )
+        );
+        assert_eq!(last_row, None);
+    }
+
+    #[test]
+    fn check_xml_unusable_without_root() {
+        assert!(matches!(check_xml(b"<workb", 0, None), XmlState::Unusable));
+    }
+
+    /// Package tối thiểu, thiếu [Content_Types].xml và _rels/.rels
+    fn bare_package(workbook_xml: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (part, data) in [
+            ("xl/workbook.xml", workbook_xml.as_bytes()),
+            ("xl/worksheets/sheet1.xml", b"<worksheet/>"),
+            ("xl/media/image1.png", b"png"),
+            ("docProps/core.xml", b"<cp:coreProperties/>"),
+            ("docProps/app.xml", b"<Properties/>"),
+        ] {
+            let path = dir.path().join(part);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+        dir
+    }
+
+    fn package_rel_types(root: &Path) -> Vec<(String, String)> {
+        rels::read_rels_file(&root.join("_rels/.rels"))
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.rel_type, r.target))
+            .collect()
+    }
+
+    #[test]
+    fn rebuild_package_rels_transitional() {
+        let dir = bare_package(&format!("<workbook xmlns=\"{}\"/>", MAIN_NS));
+        write_package_rels(dir.path()).unwrap();
+        let base = "http://schemas.openxmlformats.org/officeDocument/2006/relationships/";
+        assert_eq!(
+            package_rel_types(dir.path()),
+            [
+                (
+                    format!("{}officeDocument", base),
+                    "xl/workbook.xml".to_string()
+                ),
+                (
+                    CORE_PROPERTIES_REL.to_string(),
+                    "docProps/core.xml".to_string()
+                ),
+                (
+                    format!("{}extended-properties", base),
+                    "docProps/app.xml".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebuild_package_rels_strict() {
+        let dir = bare_package(
+            "<workbook xmlns=\"http://purl.oclc.org/ooxml/spreadsheetml/main\" \
+             conformance=\"strict\"/>",
+        );
+        write_package_rels(dir.path()).unwrap();
+        let base = "http://purl.oclc.org/ooxml/officeDocument/relationships/";
+        assert_eq!(
+            package_rel_types(dir.path()),
+            [
+                (
+                    format!("{}officeDocument", base),
+                    "xl/workbook.xml".to_string()
+                ),
+                (
+                    CORE_PROPERTIES_REL.to_string(),
+                    "docProps/core.xml".to_string()
+                ),
+                (
+                    format!("{}extendedProperties", base),
+                    "docProps/app.xml".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebuild_content_types() {
+        for workbook in [
+            format!("<workbook xmlns=\"{}\"/>", MAIN_NS),
+            "<workbook xmlns=\"http://purl.oclc.org/ooxml/spreadsheetml/main\"/>".to_string(),
+        ] {
+            let dir = bare_package(&workbook);
+            write_package_rels(dir.path()).unwrap();
+            write_content_types(dir.path()).unwrap();
+            let xml = fs::read_to_string(dir.path().join("[Content_Types].xml")).unwrap();
+            for expected in [
+                "<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>",
+                "<Default Extension=\"png\" ContentType=\"image/png\"/>",
+                "<Override PartName=\"/xl/workbook.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/>",
+                "<Override PartName=\"/xl/worksheets/sheet1.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>",
+                "<Override PartName=\"/docProps/app.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.extended-properties+xml\"/>",
+            ] {
+                assert!(xml.contains(expected), "thiếu {} trong {}", expected, xml);
+            }
+            assert!(!xml.contains("PartName=\"/_rels/.rels\""));
+            let parts = overrides(dir.path()).unwrap();
+            assert!(parts.iter().all(|p| p.exists()));
+        }
+    }
+}
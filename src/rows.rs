@@ -0,0 +1,158 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::{attr_value, local_name};
+
+/// Thuộc tính mà một hàng "mặc định" được phép có
+const DEFAULT_ROW_ATTRS: &[&str] = &["r", "spans", "ht", "customHeight", "dyDescent"];
+
+/// Hàng không có ô và chỉ mang chiều cao (không style, không ẩn, không outline)
+fn is_plain_row(e: &BytesStart) -> bool {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .all(|a| DEFAULT_ROW_ATTRS.contains(&local_name(a.key.as_ref())))
+}
+
+/// Bỏ các hàng trống (không ô, chỉ mang chiều cao) có `ht` bằng `defaultRowHeight` của
+/// `<sheetFormatPr>`: hàng khuyết cũng nhận chiều cao này nên hiển thị không đổi.
+/// Không đổi `defaultRowHeight` vì nó áp dụng cho cả các hàng sau vùng dữ liệu.
+/// Bỏ qua nếu sheet không có `defaultRowHeight`. Trả về số hàng đã bỏ.
+pub fn collapse_default_rows(sheet_xml: &Path) -> Result<usize> {
+    // pass 1: tìm chiều cao mặc định và xem có hàng nào khớp không
+    let mut default_height: Option<String> = None;
+    let mut heights: Vec<String> = Vec::new();
+    {
+        let mut reader = Reader::from_file(sheet_xml)?;
+        let mut buf = Vec::new();
+        let mut held: Option<BytesStart<'static>> = None;
+        loop {
+            let ev = reader.read_event_into(&mut buf)?;
+            // <row ...></row> rỗng coi như <row .../>
+            if let Some(h) = held.take() {
+                if matches!(&ev, Event::End(e) if local_name(e.name().as_ref()) == "row")
+                    && is_plain_row(&h)
+                {
+                    heights.extend(attr_value(&h, b"ht"));
+                }
+            }
+            match ev {
+                Event::Start(ref e) | Event::Empty(ref e)
+                    if local_name(e.name().as_ref()) == "sheetFormatPr" =>
+                {
+                    default_height = attr_value(e, b"defaultRowHeight");
+                }
+                Event::Start(ref e) if local_name(e.name().as_ref()) == "row" => {
+                    held = Some(e.to_owned().into_owned());
+                }
+                Event::Empty(ref e)
+                    if local_name(e.name().as_ref()) == "row" && is_plain_row(e) =>
+                {
+                    heights.extend(attr_value(e, b"ht"));
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+    }
+
+    let Some(default_height) = default_height else {
+        return Ok(0);
+    };
+    let same_height = |a: &str| a.parse::<f64>().ok() == default_height.parse::<f64>().ok();
+    if !heights.iter().any(|h| same_height(h)) {
+        return Ok(0);
+    }
+
+    // pass 2: ghi lại. Hàng không có `r` thì giữ: bỏ nó sẽ dời vị trí các hàng sau
+    let removable = |e: &BytesStart| {
+        is_plain_row(e)
+            && attr_value(e, b"r").is_some()
+            && attr_value(e, b"ht").is_some_and(|ht| same_height(&ht))
+    };
+    let mut removed = 0usize;
+    let tmp_out = sheet_xml.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(sheet_xml)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        let mut held: Option<BytesStart<'static>> = None;
+        loop {
+            let ev = reader.read_event_into(&mut buf)?;
+            if let Some(h) = held.take() {
+                if matches!(&ev, Event::End(e) if local_name(e.name().as_ref()) == "row") {
+                    if removable(&h) {
+                        removed += 1;
+                    } else {
+                        writer.write_event(Event::Empty(h))?;
+                    }
+                    buf.clear();
+                    continue;
+                }
+                writer.write_event(Event::Start(h))?;
+            }
+            match ev {
+                Event::Start(ref e) if local_name(e.name().as_ref()) == "row" => {
+                    held = Some(e.to_owned().into_owned());
+                }
+                Event::Empty(ref e) if local_name(e.name().as_ref()) == "row" => {
+                    if removable(e) {
+                        removed += 1;
+                    } else {
+                        writer.write_event(Event::Empty(e.clone()))?;
+                    }
+                }
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, sheet_xml)?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collapse(xml: &str) -> (usize, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sheet1.xml");
+        fs::write(&path, xml).unwrap();
+        let removed = collapse_default_rows(&path).unwrap();
+        (removed, fs::read_to_string(&path).unwrap())
+    }
+
+    #[test]
+    fn only_rows_at_the_default_height_are_removed() {
+        let rows = concat!(
+            r#"<sheetData><row r="1"><c r="A1"><v>1</v></c></row>"#,
+            r#"<row r="2" ht="15" customHeight="1"></row><row r="3" ht="30" customHeight="1"/>"#,
+            r#"<row r="4" ht="15.0" customHeight="1"/><row r="5" ht="15" hidden="1"/>"#,
+            r#"<row ht="15"/></sheetData>"#
+        );
+        let (removed, out) = collapse(&format!(
+            r#"<worksheet><sheetFormatPr defaultRowHeight="15"/>{}</worksheet>"#,
+            rows
+        ));
+        assert_eq!(removed, 2);
+        assert_eq!(
+            out,
+            concat!(
+                r#"<worksheet><sheetFormatPr defaultRowHeight="15"/><sheetData><row r="1"><c r="A1"><v>1</v></c></row>"#,
+                r#"<row r="3" ht="30" customHeight="1"/><row r="5" ht="15" hidden="1"/><row ht="15"/></sheetData></worksheet>"#
+            )
+        );
+
+        // không có defaultRowHeight: giữ nguyên
+        let xml = format!("<worksheet>{}</worksheet>", rows);
+        assert_eq!(collapse(&xml), (0, xml));
+    }
+}
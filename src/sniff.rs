@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::cfb::{self, CompoundFile};
+
+/// Lý do một file không xử lý được, nhận ra từ chữ ký đầu file
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("file được mã hoá bằng mật khẩu (cần --password hoặc --password-file)")]
+    Encrypted,
+    #[error("file Excel 97-2003 (BIFF, .xls), không phải Open XML")]
+    LegacyBiff,
+    #[error("không phải file bảng tính: {0}")]
+    NotSpreadsheet(String),
+}
+
+impl FormatError {
+    /// Nhãn ngắn để thống kê các file bị bỏ qua
+    pub fn label(&self) -> &'static str {
+        match self {
+            FormatError::Encrypted => "mã hoá",
+            FormatError::LegacyBiff => ".xls cũ",
+            FormatError::NotSpreadsheet(_) => "không phải bảng tính",
+        }
+    }
+}
+
+const ZIP_SIGNATURES: &[&[u8]] = &[b"PK\x03\x04", b"PK\x05\x06"];
+/// BOF của BIFF2-BIFF8 lưu thẳng, không bọc OLE
+const BIFF_BOF: &[&[u8]] = &[b"\x09\x00", b"\x09\x02", b"\x09\x04", b"\x09\x08"];
+
+/// Kiểm tra chữ ký: ZIP thì Ok, OLE thì phân biệt OOXML mã hoá và .xls, còn lại báo lỗi
+pub fn check_signature(path: &Path) -> Result<(), FormatError> {
+    let mut head = [0u8; 8];
+    let read = File::open(path)
+        .and_then(|mut f| f.read(&mut head))
+        .map_err(|e| FormatError::NotSpreadsheet(e.to_string()))?;
+    let head = &head[..read];
+    if ZIP_SIGNATURES.iter().any(|s| head.starts_with(s)) {
+        return Ok(());
+    }
+    if head == cfb::SIGNATURE {
+        let cf =
+            CompoundFile::open(path).map_err(|e| FormatError::NotSpreadsheet(e.to_string()))?;
+        return Err(if cf.has_entry("EncryptedPackage") {
+            FormatError::Encrypted
+        } else if cf.has_entry("Workbook") || cf.has_entry("Book") {
+            FormatError::LegacyBiff
+        } else {
+            FormatError::NotSpreadsheet("file OLE không chứa workbook".to_string())
+        });
+    }
+    if BIFF_BOF.iter().any(|s| head.starts_with(s)) {
+        return Err(FormatError::LegacyBiff);
+    }
+    Err(FormatError::NotSpreadsheet(
+        "không có chữ ký ZIP".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Compound file nhỏ nhất (sector 512 byte): header, một sector FAT, một sector thư mục
+    /// gồm Root Entry và tối đa ba stream rỗng tên `names`
+    fn compound_file(names: &[&str]) -> Vec<u8> {
+        let mut data = vec![0u8; 512 * 3];
+        data[..8].copy_from_slice(&cfb::SIGNATURE);
+        let put = |d: &mut [u8], at: usize, v: &[u8]| d[at..at + v.len()].copy_from_slice(v);
+        put(&mut data, 0x18, &[0x3E, 0, 3, 0, 0xFE, 0xFF, 9, 0, 6, 0]);
+        put(&mut data, 0x2C, &1u32.to_le_bytes());
+        put(&mut data, 0x30, &1u32.to_le_bytes());
+        put(&mut data, 0x3C, &0xFFFF_FFFEu32.to_le_bytes());
+        put(&mut data, 0x44, &0xFFFF_FFFEu32.to_le_bytes());
+        for k in 0..109 {
+            let v: u32 = if k == 0 { 0 } else { 0xFFFF_FFFF };
+            put(&mut data, 0x4C + k * 4, &v.to_le_bytes());
+        }
+        // FAT: sector 0 là FAT, sector 1 (thư mục) kết thúc chuỗi
+        for k in 0..128 {
+            let v: u32 = match k {
+                0 => 0xFFFF_FFFD,
+                1 => 0xFFFF_FFFE,
+                _ => 0xFFFF_FFFF,
+            };
+            put(&mut data, 512 + k * 4, &v.to_le_bytes());
+        }
+        let entries = std::iter::once(("Root Entry", 5u8)).chain(names.iter().map(|n| (*n, 2)));
+        for (i, (name, kind)) in entries.enumerate() {
+            let at = 1024 + i * 128;
+            let units: Vec<u8> = name.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+            put(&mut data, at, &units);
+            put(&mut data, at + 64, &(units.len() as u16 + 2).to_le_bytes());
+            data[at + 66] = kind;
+            put(&mut data, at + 116, &0xFFFF_FFFEu32.to_le_bytes());
+        }
+        data
+    }
+
+    fn check(bytes: &[u8]) -> Result<(), FormatError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xlsx");
+        fs::write(&path, bytes).unwrap();
+        check_signature(&path)
+    }
+
+    #[test]
+    fn zip_is_accepted() {
+        assert!(check(b"PK\x03\x04rest of the archive").is_ok());
+        assert!(check(b"PK\x05\x06").is_ok());
+    }
+
+    #[test]
+    fn ole_files_are_told_apart() {
+        let encrypted = compound_file(&["EncryptionInfo", "EncryptedPackage"]);
+        assert!(matches!(check(&encrypted), Err(FormatError::Encrypted)));
+        let xls = compound_file(&["workbook", "SummaryInformation"]);
+        assert!(matches!(check(&xls), Err(FormatError::LegacyBiff)));
+        let doc = compound_file(&["WordDocument"]);
+        assert!(matches!(check(&doc), Err(FormatError::NotSpreadsheet(_))));
+    }
+
+    #[test]
+    fn other_signatures_are_rejected() {
+        assert!(matches!(
+            check(b"\x09\x08\x10\x00\x00\x06\x05\x00"),
+            Err(FormatError::LegacyBiff)
+        ));
+        assert!(matches!(
+            check(b"%PDF-1.7"),
+            Err(FormatError::NotSpreadsheet(_))
+        ));
+        assert!(matches!(check(b""), Err(FormatError::NotSpreadsheet(_))));
+        // chữ ký OLE nhưng header cụt
+        assert!(matches!(
+            check(&cfb::SIGNATURE),
+            Err(FormatError::NotSpreadsheet(_))
+        ));
+    }
+}
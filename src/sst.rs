@@ -0,0 +1,385 @@
+use anyhow::Result;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesRef, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::{attr_value, local_name, replace_attr};
+
+/// Đọc sharedStrings.xml, trả về cờ "chuỗi rỗng/chỉ khoảng trắng" cho từng `<si>`.
+/// Phần phiên âm (`<rPh>`) không được hiển thị nên không tính.
+pub fn load_blank_flags(path: &Path) -> Result<Vec<bool>> {
+    let mut reader = Reader::from_file(path)?;
+    let mut buf = Vec::new();
+    let mut flags = Vec::new();
+    let mut text = String::new();
+    let mut in_si = false;
+    let mut rph_depth = 0usize;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) => match local_name(e.name().as_ref()) {
+                "si" => {
+                    in_si = true;
+                    text.clear();
+                }
+                "rPh" => rph_depth += 1,
+                _ => {}
+            },
+            Event::Empty(ref e) if local_name(e.name().as_ref()) == "si" => flags.push(true),
+            Event::Text(ref t) if in_si && rph_depth == 0 => {
+                text.push_str(&t.decode()?);
+            }
+            Event::CData(ref t) if in_si && rph_depth == 0 => {
+                text.push_str(&String::from_utf8_lossy(t));
+            }
+            Event::GeneralRef(ref r) if in_si && rph_depth == 0 => push_ref(&mut text, r),
+            Event::End(ref e) => match local_name(e.name().as_ref()) {
+                "si" => {
+                    in_si = false;
+                    flags.push(text.trim().is_empty());
+                }
+                "rPh" => rph_depth = rph_depth.saturating_sub(1),
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(flags)
+}
+
+/// `&#32;` -> ' ', `&quot;` -> '"'; thực thể lạ giữ lại dấu '&'
+pub fn push_ref(text: &mut String, r: &BytesRef) {
+    match r.resolve_char_ref() {
+        Ok(Some(ch)) => text.push(ch),
+        _ => match r
+            .decode()
+            .ok()
+            .and_then(|name| resolve_predefined_entity(&name))
+        {
+            Some(s) => text.push_str(s),
+            None => text.push('&'),
+        },
+    }
+}
+
+/// Bảng chuỗi chia sẻ dùng khi chuyển chuỗi inline sang sharedStrings. Khoá là XML bên trong
+/// `<si>`/`<is>` (đã chuẩn hoá qua quick-xml), nên chuỗi rich text cũng được gộp đúng.
+/// Chuỗi mới được cấp chỉ số theo thứ tự gặp, nên các sheet phải được chuyển tuần tự.
+#[derive(Default)]
+pub struct StringTable {
+    index: HashMap<Vec<u8>, u32>,
+    /// số `<si>` đã có trong sharedStrings.xml
+    existing: u32,
+    /// các `<si>` mới, theo thứ tự chỉ số
+    added: Vec<Vec<u8>>,
+    /// số ô đã đổi sang t="s"
+    refs: u64,
+}
+
+impl StringTable {
+    /// Nạp các `<si>` đã có (nếu file tồn tại) để dùng lại chỉ số của chúng
+    pub fn load(path: Option<&Path>) -> Result<StringTable> {
+        let mut table = StringTable::default();
+        if let Some(path) = path {
+            let mut reader = Reader::from_file(path)?;
+            let mut buf = Vec::new();
+            loop {
+                match reader.read_event_into(&mut buf)? {
+                    Event::Start(ref e) if local_name(e.name().as_ref()) == "si" => {
+                        let xml = read_inner_xml(&mut reader, e.name().as_ref())?;
+                        table.index.entry(xml).or_insert(table.existing);
+                        table.existing += 1;
+                    }
+                    Event::Empty(ref e) if local_name(e.name().as_ref()) == "si" => {
+                        table.index.entry(Vec::new()).or_insert(table.existing);
+                        table.existing += 1;
+                    }
+                    Event::Eof => break,
+                    _ => {}
+                }
+                buf.clear();
+            }
+        }
+        Ok(table)
+    }
+
+    /// Chỉ số của chuỗi (XML bên trong `<is>`), thêm mới nếu chưa có
+    pub fn intern(&mut self, xml: Vec<u8>) -> u32 {
+        self.refs += 1;
+        if let Some(&i) = self.index.get(&xml) {
+            return i;
+        }
+        let i = self.existing + self.added.len() as u32;
+        self.added.push(xml.clone());
+        self.index.insert(xml, i);
+        i
+    }
+
+    /// Ghi lại các ô `t="inlineStr"` của sheet thành `t="s"` trỏ vào bảng
+    pub fn share_inline(&mut self, sheet_xml: &Path) -> Result<()> {
+        let tmp_out = sheet_xml.with_extension("xml.out");
+        let mut changed = 0usize;
+        {
+            let mut reader = Reader::from_file(sheet_xml)?;
+            let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+            let mut buf = Vec::new();
+            loop {
+                match reader.read_event_into(&mut buf)? {
+                    Event::Start(ref e)
+                        if local_name(e.name().as_ref()) == "c"
+                            && attr_value(e, b"t").as_deref() == Some("inlineStr") =>
+                    {
+                        let cell = e.to_owned().into_owned();
+                        self.write_inline_as_shared(&mut reader, &mut writer, cell)?;
+                        changed += 1;
+                    }
+                    Event::Eof => break,
+                    ev => writer.write_event(ev)?,
+                }
+                buf.clear();
+            }
+            writer.into_inner().flush()?;
+        }
+        match changed {
+            0 => fs::remove_file(&tmp_out)?,
+            _ => fs::rename(&tmp_out, sheet_xml)?,
+        }
+        Ok(())
+    }
+
+    /// Ghi lại ô `t="inlineStr"` thành `t="s"`, thay `<is>` bằng `<v>` chỉ số chuỗi chia sẻ
+    fn write_inline_as_shared<R: BufRead, W: Write>(
+        &mut self,
+        reader: &mut Reader<R>,
+        writer: &mut Writer<W>,
+        cell: BytesStart<'static>,
+    ) -> Result<()> {
+        let end = cell.to_end().into_owned();
+        writer.write_event(Event::Start(replace_attr(&cell, b"t", Some("s"))))?;
+        let mut buf = Vec::new();
+        let mut depth = 0usize;
+        loop {
+            let idx = match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) if depth == 0 && local_name(e.name().as_ref()) == "is" => {
+                    let name = e.name().as_ref().to_vec();
+                    Some(self.intern(read_inner_xml(reader, &name)?))
+                }
+                Event::Empty(ref e) if depth == 0 && local_name(e.name().as_ref()) == "is" => {
+                    Some(self.intern(Vec::new()))
+                }
+                Event::End(_) if depth == 0 => break,
+                Event::Eof => break,
+                ev => {
+                    match ev {
+                        Event::Start(_) => depth += 1,
+                        Event::End(_) => depth -= 1,
+                        _ => {}
+                    }
+                    writer.write_event(ev)?;
+                    None
+                }
+            };
+            if let Some(i) = idx {
+                writer.write_event(Event::Start(BytesStart::new("v")))?;
+                writer.write_event(Event::Text(BytesText::new(&i.to_string())))?;
+                writer.write_event(Event::End(BytesEnd::new("v")))?;
+            }
+            buf.clear();
+        }
+        writer.write_event(Event::End(end))?;
+        Ok(())
+    }
+
+    /// Số chuỗi mới và số ô đã chuyển
+    pub fn stats(&self) -> (usize, u64) {
+        (self.added.len(), self.refs)
+    }
+
+    /// Ghi các chuỗi mới vào cuối sharedStrings.xml (tạo file nếu chưa có) và cập nhật
+    /// `count`/`uniqueCount`. `main_ns`: namespace cho file mới (Transitional hoặc Strict)
+    pub fn write(&self, path: &Path, main_ns: &str) -> Result<()> {
+        let t = self;
+        let unique = (t.existing as usize + t.added.len()).to_string();
+        let write_items = |w: &mut Writer<BufWriter<File>>| -> Result<()> {
+            for xml in &t.added {
+                w.write_event(Event::Start(BytesStart::new("si")))?;
+                w.get_mut().write_all(xml)?;
+                w.write_event(Event::End(BytesEnd::new("si")))?;
+            }
+            Ok(())
+        };
+
+        if !path.exists() {
+            let mut writer = Writer::new(BufWriter::new(File::create(path)?));
+            writer.write_event(Event::Decl(BytesDecl::new(
+                "1.0",
+                Some("UTF-8"),
+                Some("yes"),
+            )))?;
+            let mut root = BytesStart::new("sst");
+            root.push_attribute(("xmlns", main_ns));
+            root.push_attribute(("count", t.refs.to_string().as_str()));
+            root.push_attribute(("uniqueCount", unique.as_str()));
+            writer.write_event(Event::Start(root))?;
+            write_items(&mut writer)?;
+            writer.write_event(Event::End(BytesEnd::new("sst")))?;
+            writer.into_inner().flush()?;
+            return Ok(());
+        }
+
+        let tmp_out = path.with_extension("xml.out");
+        {
+            let mut reader = Reader::from_file(path)?;
+            let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+            let mut buf = Vec::new();
+            loop {
+                match reader.read_event_into(&mut buf)? {
+                    Event::Start(ref e) if local_name(e.name().as_ref()) == "sst" => {
+                        writer.write_event(Event::Start(sst_counts(e, &unique, t.refs)))?;
+                    }
+                    // <sst/> rỗng
+                    Event::Empty(ref e) if local_name(e.name().as_ref()) == "sst" => {
+                        let el = sst_counts(e, &unique, t.refs);
+                        let end = el.to_end().into_owned();
+                        writer.write_event(Event::Start(el))?;
+                        write_items(&mut writer)?;
+                        writer.write_event(Event::End(end))?;
+                    }
+                    Event::End(ref e) if local_name(e.name().as_ref()) == "sst" => {
+                        write_items(&mut writer)?;
+                        writer.write_event(Event::End(e.borrow()))?;
+                    }
+                    Event::Eof => break,
+                    ev => writer.write_event(ev)?,
+                }
+                buf.clear();
+            }
+            writer.into_inner().flush()?;
+        }
+        fs::rename(&tmp_out, path)?;
+        Ok(())
+    }
+}
+
+/// `<sst>` với uniqueCount mới và count cộng thêm số ô vừa chuyển
+fn sst_counts(e: &BytesStart, unique: &str, added_refs: u64) -> BytesStart<'static> {
+    let mut el = replace_attr(e, b"uniqueCount", Some(unique));
+    if let Some(count) = attr_value(e, b"count").and_then(|c| c.parse::<u64>().ok()) {
+        el = replace_attr(&el, b"count", Some(&(count + added_refs).to_string()));
+    }
+    el
+}
+
+/// Đọc đến thẻ đóng của phần tử `name` vừa mở, trả về XML của phần bên trong
+pub fn read_inner_xml<R: BufRead>(reader: &mut Reader<R>, name: &[u8]) -> Result<Vec<u8>> {
+    let mut writer = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    let mut depth = 0usize;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::End(e) if depth == 0 && e.name().as_ref() == name => break,
+            Event::Eof => break,
+            ev => {
+                match ev {
+                    Event::Start(_) => depth += 1,
+                    Event::End(_) => depth -= 1,
+                    _ => {}
+                }
+                writer.write_event(ev)?;
+            }
+        }
+        buf.clear();
+    }
+    Ok(writer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blank_flags_ignore_phonetic_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sharedStrings.xml");
+        std::fs::write(
+            &path,
+            concat!(
+                r#"<sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+                r#"<si><t>a</t></si><si><t xml:space="preserve">  </t></si><si/>"#,
+                r#"<si><r><t> </t></r><rPh sb="0" eb="1"><t>ア</t></rPh></si>"#,
+                r#"<si><t>&#32;&#9;</t></si><si><t>&amp;</t></si></sst>"#
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            load_blank_flags(&path).unwrap(),
+            vec![false, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn inline_strings_reuse_and_extend_the_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let sst_xml = dir.path().join("sharedStrings.xml");
+        fs::write(
+            &sst_xml,
+            r#"<sst count="3" uniqueCount="2"><si><t>a</t></si><si><r><t>b</t></r></si></sst>"#,
+        )
+        .unwrap();
+        let sheets: Vec<_> = [
+            (
+                r#"<c r="A1" t="inlineStr"><is><t>new</t></is></c>"#,
+                r#"<c r="B1" t="inlineStr"><is><t>a</t></is></c>"#,
+            ),
+            (
+                r#"<c r="A1" t="inlineStr"><is><t>new</t></is></c>"#,
+                r#"<c r="A2" t="inlineStr"><is><t>z</t></is></c>"#,
+            ),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, (c1, c2))| {
+            let path = dir.path().join(format!("sheet{}.xml", i + 1));
+            fs::write(
+                &path,
+                format!(
+                    "<worksheet><sheetData><row>{}{}</row></sheetData></worksheet>",
+                    c1, c2
+                ),
+            )
+            .unwrap();
+            path
+        })
+        .collect();
+
+        let mut table = StringTable::load(Some(&sst_xml)).unwrap();
+        for sheet in &sheets {
+            table.share_inline(sheet).unwrap();
+        }
+        assert_eq!(table.stats(), (2, 4));
+        assert_eq!(
+            fs::read_to_string(&sheets[0]).unwrap(),
+            r#"<worksheet><sheetData><row><c r="A1" t="s"><v>2</v></c><c r="B1" t="s"><v>0</v></c></row></sheetData></worksheet>"#
+        );
+        assert!(fs::read_to_string(&sheets[1])
+            .unwrap()
+            .contains(r#"<c r="A2" t="s"><v>3</v></c>"#));
+
+        table
+            .write(&sst_xml, crate::Conformance::Transitional.main_ns())
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&sst_xml).unwrap(),
+            concat!(
+                r#"<sst count="7" uniqueCount="4"><si><t>a</t></si><si><r><t>b</t></r></si>"#,
+                r#"<si><t>new</t></si><si><t>z</t></si></sst>"#
+            )
+        );
+    }
+}
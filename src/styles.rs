@@ -0,0 +1,130 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::path::Path;
+
+use crate::{attr_value, local_name};
+
+const BORDER_SIDES: &[&str] = &[
+    "left",
+    "right",
+    "top",
+    "bottom",
+    "start",
+    "end",
+    "diagonal",
+    "vertical",
+    "horizontal",
+];
+
+/// Đọc styles.xml, trả về cờ "có hiệu ứng nhìn thấy được" cho từng xf trong `<cellXfs>`
+/// (chỉ số trùng với thuộc tính `s` của ô): fill khác none, có viền, hoặc numFmtId khác 0.
+pub fn load_visible_xfs(path: &Path) -> Result<Vec<bool>> {
+    let mut reader = Reader::from_file(path)?;
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+
+    let mut stack: Vec<String> = Vec::new();
+    let mut fills: Vec<bool> = Vec::new();
+    let mut borders: Vec<bool> = Vec::new();
+    let mut xfs: Vec<(usize, usize, u32)> = Vec::new();
+
+    loop {
+        let ev = reader.read_event_into(&mut buf)?;
+        let (e, empty) = match ev {
+            Event::Start(ref e) => (e, false),
+            Event::Empty(ref e) => (e, true),
+            Event::End(_) => {
+                stack.pop();
+                buf.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        let tag = local_name(e.name().as_ref()).to_string();
+        let parent = stack.last().map(String::as_str);
+        let grand = stack.len().checked_sub(2).map(|i| stack[i].as_str());
+        match (tag.as_str(), parent, grand) {
+            ("fill", Some("fills"), _) => fills.push(false),
+            ("gradientFill", Some("fill"), Some("fills")) => set_last(&mut fills),
+            ("patternFill", Some("fill"), Some("fills")) if pattern_visible(e) => {
+                set_last(&mut fills)
+            }
+            ("border", Some("borders"), _) => borders.push(false),
+            (side, Some("border"), Some("borders"))
+                if BORDER_SIDES.contains(&side)
+                    && attr_value(e, b"style").is_some_and(|s| s != "none") =>
+            {
+                set_last(&mut borders)
+            }
+            ("xf", Some("cellXfs"), _) => {
+                let num = |k: &[u8]| attr_value(e, k).and_then(|s| s.parse().ok());
+                xfs.push((
+                    num(b"fillId").unwrap_or(0) as usize,
+                    num(b"borderId").unwrap_or(0) as usize,
+                    num(b"numFmtId").unwrap_or(0),
+                ));
+            }
+            _ => {}
+        }
+        if !empty {
+            stack.push(tag);
+        }
+        buf.clear();
+    }
+
+    Ok(xfs
+        .into_iter()
+        .map(|(fill, border, num_fmt)| {
+            fills.get(fill).copied().unwrap_or(false)
+                || borders.get(border).copied().unwrap_or(false)
+                || num_fmt != 0
+        })
+        .collect())
+}
+
+fn set_last(v: &mut [bool]) {
+    if let Some(last) = v.last_mut() {
+        *last = true;
+    }
+}
+
+fn pattern_visible(e: &BytesStart) -> bool {
+    attr_value(e, b"patternType").is_some_and(|p| p != "none")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_xfs_need_fill_border_or_number_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("styles.xml");
+        std::fs::write(
+            &path,
+            concat!(
+                r#"<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+                r#"<fills count="3"><fill><patternFill patternType="none"/></fill>"#,
+                r#"<fill><patternFill patternType="gray125"/></fill>"#,
+                r#"<fill><patternFill patternType="solid"><fgColor rgb="FFFF0000"/></patternFill></fill></fills>"#,
+                r#"<borders count="2"><border><left/><right/></border>"#,
+                r#"<border><left style="thin"/><right/></border></borders>"#,
+                r#"<cellStyleXfs count="1"><xf fillId="2"/></cellStyleXfs>"#,
+                r#"<cellXfs count="5"><xf numFmtId="0" fillId="0" borderId="0"/>"#,
+                r#"<xf fillId="2" borderId="0"/><xf fillId="0" borderId="1"/>"#,
+                r#"<xf numFmtId="14"/><xf fillId="0" borderId="0" fontId="3"/></cellXfs>"#,
+                r#"</styleSheet>"#
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            load_visible_xfs(&path).unwrap(),
+            vec![false, true, true, true, false]
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::cellref::Range;
+use crate::{attr_value, local_name, replace_attr, UsedRange};
+
+/// Ghi lại `<autoFilter>` với `new_ref`; bỏ filterColumn có colId vượt quá số cột
+/// của `new_ref` và cắt sortState/sortCondition theo góc dưới phải của nó.
+/// `empty` = thẻ dạng `<autoFilter .../>`.
+pub fn write_auto_filter<R: BufRead, W: Write>(
+    reader: &mut Reader<R>,
+    writer: &mut Writer<W>,
+    e: &BytesStart,
+    empty: bool,
+    new_ref: &str,
+) -> Result<()> {
+    let rg = Range::parse(new_ref).unwrap_or(Range::new(1, 1, 1, 1));
+    let width = rg.width();
+    let el = replace_attr(e, b"ref", Some(new_ref));
+    if empty {
+        writer.write_event(Event::Empty(el))?;
+        return Ok(());
+    }
+    writer.write_event(Event::Start(el))?;
+
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref fc)
+                if local_name(fc.name().as_ref()) == "filterColumn"
+                    && col_id(fc).is_some_and(|id| id >= width) =>
+            {
+                reader.read_to_end_into(fc.name(), &mut Vec::new())?;
+            }
+            Event::Empty(ref fc)
+                if local_name(fc.name().as_ref()) == "filterColumn"
+                    && col_id(fc).is_some_and(|id| id >= width) => {}
+            Event::Start(ref ss) if local_name(ss.name().as_ref()) == "sortState" => {
+                write_sort_state(reader, writer, ss, false, rg.last.col, rg.last.row)?;
+            }
+            Event::Empty(ref ss) if local_name(ss.name().as_ref()) == "sortState" => {
+                write_sort_state(reader, writer, ss, true, rg.last.col, rg.last.row)?;
+            }
+            Event::End(ee) => {
+                let done = local_name(ee.name().as_ref()) == "autoFilter";
+                writer.write_event(Event::End(ee))?;
+                if done {
+                    break;
+                }
+            }
+            Event::Eof => break,
+            ev => writer.write_event(ev)?,
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+fn col_id(e: &BytesStart) -> Option<u32> {
+    attr_value(e, b"colId").and_then(|s| s.parse().ok())
+}
+
+/// sortState: cắt ref; sortCondition nằm hẳn ngoài vùng thì bỏ
+fn write_sort_state<R: BufRead, W: Write>(
+    reader: &mut Reader<R>,
+    writer: &mut Writer<W>,
+    e: &BytesStart,
+    empty: bool,
+    last_col: u32,
+    last_row: u32,
+) -> Result<()> {
+    let clipped = |el: &BytesStart| -> Option<Option<String>> {
+        match attr_value(el, b"ref") {
+            Some(r) => Range::parse(&r)
+                .and_then(|rg| rg.clip(last_col, last_row))
+                .map(|rg| Some(rg.to_string())),
+            None => Some(None),
+        }
+    };
+    let el = match clipped(e) {
+        Some(Some(r)) => replace_attr(e, b"ref", Some(&r)),
+        Some(None) => e.clone().into_owned(),
+        None => {
+            // cả vùng sort nằm ngoài: bỏ luôn
+            if !empty {
+                reader.read_to_end_into(e.name(), &mut Vec::new())?;
+            }
+            return Ok(());
+        }
+    };
+    if empty {
+        writer.write_event(Event::Empty(el))?;
+        return Ok(());
+    }
+    writer.write_event(Event::Start(el))?;
+
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref sc) if local_name(sc.name().as_ref()) == "sortCondition" => {
+                match clipped(sc) {
+                    Some(Some(r)) => {
+                        writer.write_event(Event::Start(replace_attr(sc, b"ref", Some(&r))))?
+                    }
+                    Some(None) => writer.write_event(Event::Start(sc.clone()))?,
+                    None => {
+                        reader.read_to_end_into(sc.name(), &mut Vec::new())?;
+                    }
+                }
+            }
+            Event::Empty(ref sc) if local_name(sc.name().as_ref()) == "sortCondition" => {
+                match clipped(sc) {
+                    Some(Some(r)) => {
+                        writer.write_event(Event::Empty(replace_attr(sc, b"ref", Some(&r))))?
+                    }
+                    Some(None) => writer.write_event(Event::Empty(sc.clone()))?,
+                    None => {}
+                }
+            }
+            Event::End(ee) => {
+                let done = local_name(ee.name().as_ref()) == "sortState";
+                writer.write_event(Event::End(ee))?;
+                if done {
+                    break;
+                }
+            }
+            Event::Eof => break,
+            ev => writer.write_event(ev)?,
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+/// Thuộc tính của thẻ gốc `<table>`: (ref, headerRowCount, totalsRowCount)
+pub fn read_table_header(path: &Path) -> Result<Option<(String, u32, u32)>> {
+    let mut reader = Reader::from_file(path)?;
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                if local_name(e.name().as_ref()) != "table" {
+                    return Ok(None);
+                }
+                let num =
+                    |k: &[u8], d: u32| attr_value(e, k).and_then(|s| s.parse().ok()).unwrap_or(d);
+                return Ok(attr_value(e, b"ref")
+                    .map(|r| (r, num(b"headerRowCount", 1), num(b"totalsRowCount", 0))));
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Cắt `ref` của table (xl/tables/tableN.xml) về vùng dữ liệu của sheet chứa nó.
+/// Giữ hàng tiêu đề và ít nhất một hàng dữ liệu; autoFilter của table được tính lại
+/// từ ref mới (không gồm hàng tổng). Nếu hàng tổng bị cắt thì bỏ luôn cấu hình tổng.
+pub fn rewrite_table(xml_path: &Path, used: UsedRange) -> Result<()> {
+    if used.is_empty() {
+        return Ok(());
+    }
+    let Some((old_ref, header_rows, totals_rows)) = read_table_header(xml_path)? else {
+        return Ok(());
+    };
+    let Some(old) = Range::parse(&old_ref) else {
+        return Ok(());
+    };
+    // headerRowCount/totalsRowCount không khớp với ref (file hỏng): để nguyên
+    if header_rows.saturating_add(totals_rows) > old.last.row - old.first.row + 1 {
+        return Ok(());
+    }
+    let Some(mut clipped) = old.clip(used.last_col, used.last_row) else {
+        // table nằm hẳn ngoài vùng dữ liệu: để nguyên
+        return Ok(());
+    };
+    clipped.last.row = clipped
+        .last
+        .row
+        .max(old.first.row.saturating_add(header_rows))
+        .min(old.last.row);
+    if clipped == old {
+        return Ok(());
+    }
+    let totals_cut = totals_rows > 0 && clipped.last.row < old.last.row;
+    let totals_kept = if totals_cut { 0 } else { totals_rows };
+    let width = clipped.width();
+    let (nc2, nr2) = (clipped.last.col, clipped.last.row);
+    let new_ref = clipped.to_string();
+    let mut filter = clipped;
+    filter.last.row = filter
+        .last
+        .row
+        .saturating_sub(totals_kept)
+        .max(filter.first.row);
+    let filter_ref = filter.to_string();
+
+    let tmp_out = xml_path.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(xml_path)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        let mut col_idx = 0u32;
+
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) => match local_name(e.name().as_ref()) {
+                    "table" => {
+                        let mut el = replace_attr(e, b"ref", Some(&new_ref));
+                        if totals_cut {
+                            el = replace_attr(&el, b"totalsRowCount", None);
+                        }
+                        writer.write_event(Event::Start(el))?;
+                    }
+                    "autoFilter" => {
+                        write_auto_filter(&mut reader, &mut writer, e, false, &filter_ref)?
+                    }
+                    "sortState" => write_sort_state(&mut reader, &mut writer, e, false, nc2, nr2)?,
+                    "tableColumns" => {
+                        let w = width.to_string();
+                        writer.write_event(Event::Start(replace_attr(e, b"count", Some(&w))))?;
+                    }
+                    "tableColumn" => {
+                        col_idx += 1;
+                        if col_idx > width {
+                            reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                        } else {
+                            writer.write_event(Event::Start(strip_totals(e, totals_cut)))?;
+                        }
+                    }
+                    "totalsRowFormula" if totals_cut => {
+                        reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                    }
+                    _ => writer.write_event(Event::Start(e.clone()))?,
+                },
+                Event::Empty(ref e) => match local_name(e.name().as_ref()) {
+                    "table" => {
+                        let mut el = replace_attr(e, b"ref", Some(&new_ref));
+                        if totals_cut {
+                            el = replace_attr(&el, b"totalsRowCount", None);
+                        }
+                        writer.write_event(Event::Empty(el))?;
+                    }
+                    "autoFilter" => {
+                        write_auto_filter(&mut reader, &mut writer, e, true, &filter_ref)?
+                    }
+                    "sortState" => write_sort_state(&mut reader, &mut writer, e, true, nc2, nr2)?,
+                    "tableColumn" => {
+                        col_idx += 1;
+                        if col_idx <= width {
+                            writer.write_event(Event::Empty(strip_totals(e, totals_cut)))?;
+                        }
+                    }
+                    _ => writer.write_event(Event::Empty(e.clone()))?,
+                },
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, xml_path)?;
+    Ok(())
+}
+
+fn strip_totals(e: &BytesStart, totals_cut: bool) -> BytesStart<'static> {
+    if !totals_cut {
+        return e.clone().into_owned();
+    }
+    let el = replace_attr(e, b"totalsRowFunction", None);
+    replace_attr(&el, b"totalsRowLabel", None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(xml: &str, last_col: u32, last_row: u32) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("table1.xml");
+        fs::write(&path, xml).unwrap();
+        let used = UsedRange {
+            first_row: 1,
+            first_col: 1,
+            last_row,
+            last_col,
+        };
+        rewrite_table(&path, used).unwrap();
+        fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn table_is_clipped_and_cut_totals_are_dropped() {
+        let xml = concat!(
+            r#"<table ref="A1:D20" totalsRowCount="1"><autoFilter ref="A1:D19"/>"#,
+            r#"<tableColumns count="4">"#,
+            r#"<tableColumn id="1" name="a" totalsRowLabel="Tổng"/>"#,
+            r#"<tableColumn id="2" name="b" totalsRowFunction="sum"/>"#,
+            r#"<tableColumn id="3" name="c"/><tableColumn id="4" name="d"/>"#,
+            r#"</tableColumns></table>"#
+        );
+        let out = rewrite(xml, 2, 5);
+        assert!(out.contains(r#"<table ref="A1:B5">"#), "{}", out);
+        assert!(out.contains(r#"<autoFilter ref="A1:B5"/>"#), "{}", out);
+        assert!(out.contains(r#"<tableColumns count="2">"#), "{}", out);
+        assert!(!out.contains("totalsRow"), "{}", out);
+        assert!(!out.contains(r#"name="c""#), "{}", out);
+    }
+
+    #[test]
+    fn empty_table_element_drops_cut_totals_count() {
+        let out = rewrite(r#"<table ref="A1:B10" totalsRowCount="1"/>"#, 5, 4);
+        assert_eq!(out, r#"<table ref="A1:B4"/>"#);
+    }
+
+    #[test]
+    fn header_and_one_data_row_are_kept() {
+        // vùng dữ liệu chỉ tới hàng tiêu đề
+        let out = rewrite(r#"<table ref="B3:C10"/>"#, 5, 3);
+        assert_eq!(out, r#"<table ref="B3:C4"/>"#);
+        // table nằm hẳn ngoài vùng: để nguyên
+        let out = rewrite(r#"<table ref="B3:C10"/>"#, 5, 2);
+        assert_eq!(out, r#"<table ref="B3:C10"/>"#);
+    }
+
+    #[test]
+    fn table_with_inconsistent_counts_is_left_alone() {
+        let xml = r#"<table ref="A1:B2" headerRowCount="1" totalsRowCount="5"/>"#;
+        assert_eq!(rewrite(xml, 1, 1), xml);
+        let xml = r#"<table ref="A1:B2" headerRowCount="4294967295"/>"#;
+        assert_eq!(rewrite(xml, 1, 1), xml);
+    }
+}
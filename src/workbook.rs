@@ -0,0 +1,228 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::{Reader, Writer};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{attr_value, local_name, rel_id_attr, rels, sst};
+
+/// Loại sheet, theo Type của quan hệ từ workbook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SheetKind {
+    Worksheet,
+    Chartsheet,
+    Dialogsheet,
+    /// sheet macro Excel 4 (`xlMacrosheet`, `xlIntlMacrosheet`): cùng cấu trúc ô với worksheet
+    Macrosheet,
+}
+
+impl SheetKind {
+    pub fn from_rel(rel: &rels::Relationship) -> Option<Self> {
+        match rel.rel_type.rsplit('/').next()? {
+            "worksheet" => Some(SheetKind::Worksheet),
+            "chartsheet" => Some(SheetKind::Chartsheet),
+            "dialogsheet" => Some(SheetKind::Dialogsheet),
+            "xlMacrosheet" | "xlIntlMacrosheet" => Some(SheetKind::Macrosheet),
+            _ => None,
+        }
+    }
+
+    /// Sheet có `<sheetData>`, cắt được theo vùng dữ liệu
+    pub fn has_cells(self) -> bool {
+        matches!(self, SheetKind::Worksheet | SheetKind::Macrosheet)
+    }
+}
+
+/// Một sheet khai báo trong `<sheets>` của workbook.xml
+#[derive(Debug, Clone)]
+pub struct SheetEntry {
+    pub name: String,
+    pub kind: SheetKind,
+    /// sheetId (thuộc tính `i` trong calcChain trỏ tới giá trị này)
+    pub sheet_id: u32,
+    /// part của sheet, đã resolve qua quan hệ của workbook
+    pub path: PathBuf,
+}
+
+/// Danh sách sheet theo thứ tự trong workbook; sheet có quan hệ hỏng thì bỏ qua
+pub fn read_sheets(root: &Path, workbook_xml: &Path) -> Result<Vec<SheetEntry>> {
+    let wb_rels = rels::read_rels(workbook_xml)?;
+    let mut reader = Reader::from_file(workbook_xml)?;
+    let mut buf = Vec::new();
+    let mut sheets = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e)
+                if local_name(e.name().as_ref()) == "sheet" =>
+            {
+                let (Some(name), Some(id)) = (attr_value(e, b"name"), rel_id_attr(e)) else {
+                    buf.clear();
+                    continue;
+                };
+                let rel = wb_rels.iter().find(|r| r.id == id && !r.external);
+                let kind = rel.and_then(SheetKind::from_rel);
+                let path = rel.and_then(|r| rels::resolve_target(root, workbook_xml, &r.target));
+                if let (Some(kind), Some(path)) = (kind, path) {
+                    sheets.push(SheetEntry {
+                        name,
+                        kind,
+                        sheet_id: attr_value(e, b"sheetId").and_then(|v| v.parse().ok()).unwrap_or(0),
+                        path,
+                    });
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(sheets)
+}
+
+/// Tìm sheet theo tên (Excel không phân biệt hoa thường)
+pub fn find_sheet<'a>(sheets: &'a [SheetEntry], name: &str) -> Option<&'a SheetEntry> {
+    let name = name.to_lowercase();
+    sheets.iter().find(|s| s.name.to_lowercase() == name)
+}
+
+/// Một `<definedName>` trong workbook.xml
+#[derive(Debug, Clone)]
+pub struct DefinedName {
+    pub name: String,
+    /// localSheetId: chỉ số (từ 0) của sheet trong `<sheets>`; None = tên toàn workbook
+    pub local_sheet: Option<usize>,
+    pub formula: String,
+}
+
+/// Các `<definedName>` theo thứ tự trong file
+pub fn read_defined_names(workbook_xml: &Path) -> Result<Vec<DefinedName>> {
+    let mut reader = Reader::from_file(workbook_xml)?;
+    let mut buf = Vec::new();
+    let mut names = Vec::new();
+    let mut current: Option<DefinedName> = None;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) if local_name(e.name().as_ref()) == "definedName" => {
+                current = Some(DefinedName {
+                    name: attr_value(e, b"name").unwrap_or_default(),
+                    local_sheet: attr_value(e, b"localSheetId").and_then(|v| v.parse().ok()),
+                    formula: String::new(),
+                });
+            }
+            Event::Text(ref t) => {
+                if let Some(d) = current.as_mut() {
+                    d.formula.push_str(&t.decode()?);
+                }
+            }
+            Event::GeneralRef(ref r) => {
+                if let Some(d) = current.as_mut() {
+                    sst::push_ref(&mut d.formula, r);
+                }
+            }
+            Event::End(ref e) if local_name(e.name().as_ref()) == "definedName" => {
+                names.extend(current.take());
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(names)
+}
+
+/// Bỏ khối `<externalReferences>` khỏi workbook.xml (sau khi đã xoá các part externalLink)
+pub fn remove_external_references(workbook_xml: &Path) -> Result<()> {
+    let tmp_out = workbook_xml.with_extension("xml.out");
+    {
+        let mut reader = Reader::from_file(workbook_xml)?;
+        let mut writer = Writer::new(BufWriter::new(File::create(&tmp_out)?));
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) if local_name(e.name().as_ref()) == "externalReferences" => {
+                    reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                }
+                Event::Empty(ref e) if local_name(e.name().as_ref()) == "externalReferences" => {}
+                Event::Eof => break,
+                ev => writer.write_event(ev)?,
+            }
+            buf.clear();
+        }
+        writer.into_inner().flush()?;
+    }
+    fs::rename(&tmp_out, workbook_xml)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sheets_are_routed_by_relationship_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let workbook_xml = root.join("xl/workbook.xml");
+        fs::create_dir_all(root.join("xl/_rels")).unwrap();
+        fs::write(
+            &workbook_xml,
+            concat!(
+                r#"<workbook><sheets><sheet name="Data" sheetId="1" r:id="rId1"/><sheet name="Chart" sheetId="4" r:id="rId2"/>"#,
+                r#"<sheet name="Macro1" sheetId="2" r:id="rId3"/><sheet name="Odd" sheetId="3" r:id="rId4"/>"#,
+                r#"<sheet name="Evil" sheetId="5" r:id="rId5"/></sheets></workbook>"#
+            ),
+        )
+        .unwrap();
+        fs::write(
+            rels::rels_path_for(&workbook_xml),
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://x/worksheet" Target="worksheets/sheet1.xml"/>"#,
+                r#"<Relationship Id="rId2" Type="http://x/chartsheet" Target="chartsheets/sheet1.xml"/>"#,
+                r#"<Relationship Id="rId3" Type="http://schemas.microsoft.com/office/2006/relationships/xlMacrosheet" Target="macrosheets/sheet1.xml"/>"#,
+                r#"<Relationship Id="rId4" Type="http://x/styles" Target="styles.xml"/>"#,
+                r#"<Relationship Id="rId5" Type="http://x/worksheet" Target="../../outside.xml"/>"#,
+                r#"</Relationships>"#
+            ),
+        )
+        .unwrap();
+
+        let sheets = read_sheets(root, &workbook_xml).unwrap();
+        let kinds: Vec<_> = sheets
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.sheet_id))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("Data", SheetKind::Worksheet, 1),
+                ("Chart", SheetKind::Chartsheet, 4),
+                ("Macro1", SheetKind::Macrosheet, 2),
+            ]
+        );
+        assert_eq!(sheets[2].path, root.join("xl/macrosheets/sheet1.xml"));
+        assert!(sheets[2].kind.has_cells() && !sheets[1].kind.has_cells());
+        assert_eq!(find_sheet(&sheets, "data").map(|s| s.sheet_id), Some(1));
+    }
+
+    #[test]
+    fn external_references_block_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workbook.xml");
+        fs::write(
+            &path,
+            concat!(
+                r#"<workbook><sheets><sheet name="A" sheetId="1" r:id="rId1"/></sheets>"#,
+                r#"<externalReferences><externalReference r:id="rId4"/></externalReferences>"#,
+                r#"<calcPr calcId="191029"/></workbook>"#
+            ),
+        )
+        .unwrap();
+        remove_external_references(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            r#"<workbook><sheets><sheet name="A" sheetId="1" r:id="rId1"/></sheets><calcPr calcId="191029"/></workbook>"#
+        );
+    }
+}
@@ -0,0 +1,548 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::workbook::SheetKind;
+use crate::{content_types, rels, sheet_label, RangeMode, TrimOptions, UsedRange};
+
+// Kiểu record BIFF12 (MS-XLSB 2.3)
+const BRT_ROW_HDR: u32 = 0;
+const BRT_CELL_BLANK: u32 = 1;
+/// BrtCellRk ... BrtFmlaError: ô có giá trị hoặc công thức
+const BRT_CELL_VALUES: std::ops::RangeInclusive<u32> = 2..=11;
+const BRT_AC_BEGIN: u32 = 37;
+const BRT_AC_END: u32 = 38;
+const BRT_COL_INFO: u32 = 60;
+const BRT_CELL_RSTRING: u32 = 62;
+const BRT_BEGIN_SHEET_DATA: u32 = 145;
+const BRT_END_SHEET_DATA: u32 = 146;
+const BRT_WS_DIM: u32 = 148;
+const BRT_MERGE_CELL: u32 = 176;
+const BRT_BEGIN_MERGE_CELLS: u32 = 177;
+const BRT_END_MERGE_CELLS: u32 = 178;
+const BRT_BEGIN_COL_INFOS: u32 = 390;
+const BRT_END_COL_INFOS: u32 = 391;
+
+/// Phần cố định của BrtRowHdr trước `ccolspan`: rw, ixfe, miyRw, 3 byte cờ
+const ROW_HDR_FIXED: usize = 13;
+/// BrtColSpan gom ô theo từng khối 1024 cột
+const COLSPAN_BLOCK: u32 = 1024;
+
+struct Record {
+    rec_type: u32,
+    data: Vec<u8>,
+}
+
+/// Workbook nhị phân (.xlsb): part chính là `workbook.bin`
+pub fn is_binary_workbook(workbook_part: &Path) -> bool {
+    workbook_part.extension().is_some_and(|x| x == "bin")
+}
+
+/// Cắt các worksheet/macrosheet của workbook .xlsb theo vùng dữ liệu, rồi bỏ calcChain.bin.
+/// Các tùy chọn dựa trên XML (công thức, sharedStrings, definedNames...) không áp dụng.
+pub fn trim_workbook(root: &Path, workbook_bin: &Path, opts: &TrimOptions) -> Result<()> {
+    let ignored = opts.ooxml_only();
+    if !ignored.is_empty() {
+        eprintln!("   ⚠ .xlsb: bỏ qua {}", ignored.join(", "));
+    }
+
+    let wb_rels = rels::read_rels(workbook_bin)?;
+    let mut sheets: Vec<PathBuf> = Vec::new();
+    for rel in wb_rels.iter().filter(|r| !r.external) {
+        if SheetKind::from_rel(rel).is_some_and(|k| k.has_cells()) {
+            match rels::resolve_target(root, workbook_bin, &rel.target) {
+                Some(path) if path.is_file() && !sheets.contains(&path) => sheets.push(path),
+                _ => {}
+            }
+        }
+    }
+
+    let mut report = sheets
+        .par_iter()
+        .map(|sheet_bin| -> Result<(String, UsedRange)> {
+            let used = find_used_range(sheet_bin, opts.range_mode)
+                .with_context(|| format!("find_used_range {}", sheet_bin.display()))?;
+            let tmp_out = sheet_bin.with_extension("bin.out");
+            rewrite_sheet(sheet_bin, &tmp_out, used)
+                .with_context(|| format!("rewrite_sheet {}", sheet_bin.display()))?;
+            fs::rename(&tmp_out, sheet_bin)?;
+            Ok((sheet_label(sheet_bin), used))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    report.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, used) in report {
+        eprintln!("   • {}: {}", name, used.dimension_ref());
+    }
+
+    // calcChain.bin trỏ tới ô đã bị cắt: xoá, Excel tự dựng lại
+    let calc: Vec<_> = wb_rels
+        .iter()
+        .filter(|r| r.is("calcChain") && !r.external)
+        .collect();
+    if !calc.is_empty() {
+        let mut removed = Vec::new();
+        for rel in &calc {
+            let Some(part) = rels::resolve_target(root, workbook_bin, &rel.target) else {
+                continue;
+            };
+            if part.exists() {
+                fs::remove_file(&part)?;
+            }
+            removed.push(part);
+        }
+        let ids: HashSet<String> = calc.iter().map(|r| r.id.clone()).collect();
+        rels::remove_rels(workbook_bin, &ids)?;
+        content_types::remove_overrides(root, &removed)?;
+    }
+    Ok(())
+}
+
+fn is_cell(rec_type: u32) -> bool {
+    rec_type == BRT_CELL_BLANK
+        || rec_type == BRT_CELL_RSTRING
+        || BRT_CELL_VALUES.contains(&rec_type)
+}
+
+/// Vùng dữ liệu của sheet từ các BrtRowHdr/BrtCell*. Ở chế độ `formatting`, ô trống được tính
+/// khi có style khác 0 (không đọc styles.bin để biết style có nhìn thấy được hay không).
+fn find_used_range(sheet_bin: &Path, mode: RangeMode) -> Result<UsedRange> {
+    let mut reader = BufReader::new(File::open(sheet_bin)?);
+    let mut used = UsedRange::default();
+    let mut in_data = false;
+    let mut row = 0u32;
+    while let Some(rec) = read_record(&mut reader)? {
+        match rec.rec_type {
+            BRT_BEGIN_SHEET_DATA => in_data = true,
+            BRT_END_SHEET_DATA => break,
+            // hàng/cột từ 1; 0 (record cụt, chỉ số tràn) thì `include` bỏ qua
+            BRT_ROW_HDR if in_data => row = u32_at(&rec.data, 0).map_or(0, one_based),
+            t if in_data && is_cell(t) => {
+                let Some(col) = u32_at(&rec.data, 0).map(one_based) else {
+                    continue;
+                };
+                let counts = t != BRT_CELL_BLANK
+                    || match mode {
+                        RangeMode::Values => false,
+                        RangeMode::AnyCell => true,
+                        RangeMode::Formatting => cell_style(&rec.data) != 0,
+                    };
+                if counts {
+                    used.include(col, row);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(used)
+}
+
+/// Hàng đang chờ ghi: BrtRowHdr, các record của hàng và cột (từ 0) của các ô được giữ
+struct PendingRow {
+    header: Record,
+    records: Vec<Record>,
+    cols: Vec<u32>,
+}
+
+/// Ghi lại sheet .bin: bỏ hàng/ô ngoài vùng (kèm BrtShrFmla/BrtArrFmla/BrtTable đi sau ô),
+/// tính lại colspan của hàng, ghi BrtWsDim mới, lọc merge và cắt BrtColInfo theo vùng.
+fn rewrite_sheet(path_in: &Path, path_out: &Path, used: UsedRange) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path_in)?);
+    let mut writer = BufWriter::new(File::create(path_out)?);
+    // chỉ số trong record tính từ 0, của UsedRange từ 1
+    let row_out = |row: u32| used.last_row > 0 && row >= used.last_row;
+    let col_out = |col: u32| used.last_col > 0 && col >= used.last_col;
+
+    let mut in_data = false;
+    let mut in_ac = false;
+    let mut pending: Option<PendingRow> = None;
+    let mut drop_row = false;
+    // record đi kèm ô vừa bị bỏ (công thức shared/array, data table)
+    let mut drop_attached = false;
+    let mut merges: Option<Vec<Record>> = None;
+    let mut col_infos: Option<Vec<Record>> = None;
+
+    while let Some(mut rec) = read_record(&mut reader)? {
+        match rec.rec_type {
+            BRT_WS_DIM => {
+                rec.data = dimension(used);
+                write_record(&mut writer, &rec)?;
+            }
+            BRT_BEGIN_SHEET_DATA => {
+                in_data = true;
+                write_record(&mut writer, &rec)?;
+            }
+            BRT_END_SHEET_DATA => {
+                flush_row(&mut writer, pending.take())?;
+                in_data = false;
+                write_record(&mut writer, &rec)?;
+            }
+            // ACCELLTABLE đứng trước BrtRowHdr của hàng kế tiếp
+            BRT_AC_BEGIN if in_data => {
+                flush_row(&mut writer, pending.take())?;
+                drop_attached = false;
+                in_ac = true;
+                write_record(&mut writer, &rec)?;
+            }
+            BRT_AC_END if in_ac => {
+                in_ac = false;
+                write_record(&mut writer, &rec)?;
+            }
+            _ if in_ac => write_record(&mut writer, &rec)?,
+            BRT_ROW_HDR if in_data => {
+                flush_row(&mut writer, pending.take())?;
+                drop_attached = false;
+                drop_row = u32_at(&rec.data, 0).is_some_and(row_out);
+                if !drop_row {
+                    pending = Some(PendingRow {
+                        header: rec,
+                        records: Vec::new(),
+                        cols: Vec::new(),
+                    });
+                }
+            }
+            t if in_data && is_cell(t) => {
+                // record ô cụt (thiếu cột) là record hỏng: bỏ cùng các record đi kèm
+                let col = match u32_at(&rec.data, 0) {
+                    Some(c) if !drop_row && !col_out(c) => c,
+                    _ => {
+                        drop_attached = true;
+                        continue;
+                    }
+                };
+                drop_attached = false;
+                match pending.as_mut() {
+                    Some(row) => {
+                        row.cols.push(col);
+                        row.records.push(rec);
+                    }
+                    None => write_record(&mut writer, &rec)?,
+                }
+            }
+            _ if in_data => {
+                if drop_row || drop_attached {
+                    continue;
+                }
+                match pending.as_mut() {
+                    Some(row) => row.records.push(rec),
+                    None => write_record(&mut writer, &rec)?,
+                }
+            }
+            BRT_BEGIN_MERGE_CELLS => merges = Some(Vec::new()),
+            BRT_MERGE_CELL if merges.is_some() => {
+                // RfX: rwFirst, rwLast, colFirst, colLast (từ 0)
+                let fits = match (u32_at(&rec.data, 4), u32_at(&rec.data, 12)) {
+                    (Some(rw_last), Some(col_last)) => !row_out(rw_last) && !col_out(col_last),
+                    _ => true,
+                };
+                if fits {
+                    merges.as_mut().unwrap().push(rec);
+                }
+            }
+            BRT_END_MERGE_CELLS if merges.is_some() => {
+                let kept = merges.take().unwrap();
+                if kept.iter().any(|m| m.rec_type == BRT_MERGE_CELL) {
+                    let begin = Record {
+                        rec_type: BRT_BEGIN_MERGE_CELLS,
+                        data: (kept.iter().filter(|m| m.rec_type == BRT_MERGE_CELL).count() as u32)
+                            .to_le_bytes()
+                            .to_vec(),
+                    };
+                    write_record(&mut writer, &begin)?;
+                    for m in &kept {
+                        write_record(&mut writer, m)?;
+                    }
+                    write_record(&mut writer, &rec)?;
+                }
+            }
+            BRT_BEGIN_COL_INFOS => col_infos = Some(vec![rec]),
+            BRT_COL_INFO if col_infos.is_some() => {
+                // colFirst, colLast (từ 0): bỏ nếu nằm hẳn ngoài vùng, cắt nếu vắt qua
+                if let (Some(first), Some(last)) = (u32_at(&rec.data, 0), u32_at(&rec.data, 4)) {
+                    if col_out(first) {
+                        continue;
+                    }
+                    if col_out(last) {
+                        rec.data[4..8].copy_from_slice(&(used.last_col - 1).to_le_bytes());
+                    }
+                }
+                col_infos.as_mut().unwrap().push(rec);
+            }
+            BRT_END_COL_INFOS if col_infos.is_some() => {
+                let kept = col_infos.take().unwrap();
+                // chỉ còn BrtBeginColInfos: bỏ cả khối
+                if kept.len() > 1 {
+                    for c in &kept {
+                        write_record(&mut writer, c)?;
+                    }
+                    write_record(&mut writer, &rec)?;
+                }
+            }
+            _ => match (merges.as_mut(), col_infos.as_mut()) {
+                (Some(m), _) => m.push(rec),
+                (_, Some(c)) => c.push(rec),
+                _ => write_record(&mut writer, &rec)?,
+            },
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Ghi hàng đang chờ với rgBrtColspan tính lại từ các ô còn lại
+fn flush_row<W: Write>(writer: &mut W, row: Option<PendingRow>) -> Result<()> {
+    let Some(mut row) = row else {
+        return Ok(());
+    };
+    if row.header.data.len() >= ROW_HDR_FIXED + 4 {
+        let mut spans: Vec<(u32, u32)> = Vec::new();
+        row.cols.sort_unstable();
+        for &c in &row.cols {
+            match spans.last_mut() {
+                Some(s) if s.0 / COLSPAN_BLOCK == c / COLSPAN_BLOCK => s.1 = c,
+                _ => spans.push((c, c)),
+            }
+        }
+        let mut data = row.header.data[..ROW_HDR_FIXED].to_vec();
+        data.extend_from_slice(&(spans.len() as u32).to_le_bytes());
+        for (first, last) in spans {
+            data.extend_from_slice(&first.to_le_bytes());
+            data.extend_from_slice(&last.to_le_bytes());
+        }
+        row.header.data = data;
+    }
+    write_record(writer, &row.header)?;
+    for rec in &row.records {
+        write_record(writer, rec)?;
+    }
+    Ok(())
+}
+
+/// BrtWsDim: rwFirst, rwLast, colFirst, colLast (từ 0); sheet trống ghi A1
+fn dimension(used: UsedRange) -> Vec<u8> {
+    let (rw_first, rw_last, col_first, col_last) = if used.is_empty() {
+        (0, 0, 0, 0)
+    } else {
+        (
+            used.first_row - 1,
+            used.last_row - 1,
+            used.first_col - 1,
+            used.last_col - 1,
+        )
+    };
+    [rw_first, rw_last, col_first, col_last]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+/// Chỉ số từ 0 trong record sang hàng/cột từ 1; tràn (record hỏng) thì 0
+fn one_based(index: u32) -> u32 {
+    index.checked_add(1).unwrap_or(0)
+}
+
+/// iStyleRef (24 bit thấp của trường thứ hai trong cấu trúc Cell)
+fn cell_style(data: &[u8]) -> u32 {
+    u32_at(data, 4).map_or(0, |v| v & 0x00FF_FFFF)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Đọc một record: kiểu (1-2 byte) và độ dài (1-4 byte) mã hoá 7 bit mỗi byte.
+/// None khi hết file.
+fn read_record<R: Read>(reader: &mut R) -> Result<Option<Record>> {
+    let mut byte = [0u8; 1];
+    match reader.read_exact(&mut byte) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut rec_type = (byte[0] & 0x7F) as u32;
+    if byte[0] & 0x80 != 0 {
+        reader.read_exact(&mut byte)?;
+        rec_type |= ((byte[0] & 0x7F) as u32) << 7;
+    }
+    let mut size = 0u32;
+    for i in 0..4 {
+        reader.read_exact(&mut byte)?;
+        size |= ((byte[0] & 0x7F) as u32) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut data = vec![0u8; size as usize];
+    reader.read_exact(&mut data)?;
+    Ok(Some(Record { rec_type, data }))
+}
+
+fn write_record<W: Write>(writer: &mut W, rec: &Record) -> Result<()> {
+    let mut head = Vec::with_capacity(6);
+    push_varint(&mut head, rec.rec_type);
+    push_varint(&mut head, rec.data.len() as u32);
+    writer.write_all(&head)?;
+    writer.write_all(&rec.data)?;
+    Ok(())
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let b = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rec(rec_type: u32, data: Vec<u8>) -> Record {
+        Record { rec_type, data }
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// BrtRowHdr của hàng `rw` (từ 0) với một colspan bất kỳ
+    fn row_hdr(rw: u32) -> Record {
+        let mut data = rw.to_le_bytes().to_vec();
+        data.resize(ROW_HDR_FIXED, 0);
+        data.extend(words(&[1, 0, 0]));
+        rec(BRT_ROW_HDR, data)
+    }
+
+    /// Ô số (BrtCellReal): cột từ 0, iStyleRef, giá trị
+    fn cell(col: u32) -> Record {
+        let mut data = words(&[col, 0]);
+        data.extend(1.5f64.to_le_bytes());
+        rec(5, data)
+    }
+
+    fn encode(records: &[Record]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for r in records {
+            write_record(&mut out, r).unwrap();
+        }
+        out
+    }
+
+    fn decode(mut data: &[u8]) -> Vec<Record> {
+        let mut out = Vec::new();
+        while let Some(r) = read_record(&mut data).unwrap() {
+            out.push(r);
+        }
+        out
+    }
+
+    fn trim(records: &[Record], used: UsedRange) -> Vec<Record> {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("in.bin"), dir.path().join("out.bin"));
+        fs::write(&input, encode(records)).unwrap();
+        rewrite_sheet(&input, &output, used).unwrap();
+        decode(&fs::read(output).unwrap())
+    }
+
+    #[test]
+    fn record_header_varints() {
+        for (rec_type, len, head) in [
+            (BRT_ROW_HDR, 0, vec![0x00, 0x00]),
+            (BRT_WS_DIM, 16, vec![0x94, 0x01, 0x10]),
+            (BRT_END_COL_INFOS, 200, vec![0x87, 0x03, 0xC8, 0x01]),
+            (1, 0x20_0000, vec![0x01, 0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let bytes = encode(&[rec(rec_type, vec![7; len])]);
+            assert_eq!(bytes[..head.len()], head[..]);
+            let back = decode(&bytes);
+            assert_eq!(back.len(), 1);
+            assert_eq!((back[0].rec_type, back[0].data.len()), (rec_type, len));
+        }
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let bytes = encode(&[rec(BRT_WS_DIM, vec![0; 16])]);
+        let mut data = &bytes[..bytes.len() - 1];
+        assert!(read_record(&mut data).is_err());
+    }
+
+    #[test]
+    fn rewrite_drops_cells_outside_and_recomputes_colspans() {
+        let used = UsedRange {
+            first_row: 1,
+            first_col: 1,
+            last_row: 1,
+            last_col: 3,
+        };
+        let out = trim(
+            &[
+                rec(BRT_BEGIN_SHEET_DATA, Vec::new()),
+                row_hdr(0),
+                cell(0),
+                cell(2),
+                cell(3),
+                cell(2000),
+                row_hdr(1),
+                cell(0),
+                rec(BRT_END_SHEET_DATA, Vec::new()),
+            ],
+            used,
+        );
+        let types: Vec<u32> = out.iter().map(|r| r.rec_type).collect();
+        assert_eq!(
+            types,
+            [BRT_BEGIN_SHEET_DATA, BRT_ROW_HDR, 5, 5, BRT_END_SHEET_DATA]
+        );
+        assert_eq!(out[1].data[ROW_HDR_FIXED..], words(&[1, 0, 2]));
+    }
+
+    #[test]
+    fn truncated_or_huge_cell_columns_do_not_panic() {
+        let used = UsedRange {
+            first_row: 1,
+            first_col: 1,
+            last_row: 1,
+            last_col: 2,
+        };
+        let out = trim(
+            &[
+                rec(BRT_BEGIN_SHEET_DATA, Vec::new()),
+                row_hdr(0),
+                rec(5, vec![1, 2]),
+                cell(u32::MAX),
+                cell(1),
+                rec(BRT_END_SHEET_DATA, Vec::new()),
+            ],
+            used,
+        );
+        assert_eq!(out.len(), 4);
+        assert_eq!(out[2].data[..4], 1u32.to_le_bytes());
+        assert_eq!(out[1].data[ROW_HDR_FIXED..], words(&[1, 1, 1]));
+    }
+
+    #[test]
+    fn used_range_ignores_broken_cells() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sheet1.bin");
+        let records = [
+            rec(BRT_BEGIN_SHEET_DATA, Vec::new()),
+            row_hdr(4),
+            rec(5, vec![1]),
+            cell(u32::MAX),
+            cell(2),
+            rec(BRT_END_SHEET_DATA, Vec::new()),
+        ];
+        fs::write(&path, encode(&records)).unwrap();
+        let used = find_used_range(&path, RangeMode::Values).unwrap();
+        assert_eq!(used.dimension_ref(), "C5");
+    }
+}